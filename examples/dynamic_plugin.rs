@@ -0,0 +1,129 @@
+/*
+ ** Dynamic Plugin Example **
+: A plugin built as a shared library and loaded through `library` in the config file
+: (see src/dynamic.rs). It counts shreds and logs every `every`-th one to stderr.
+:
+: cargo build --example dynamic_plugin
+: -> target/debug/examples/libdynamic_plugin.so
+:
+: [[plugins]]
+: name = "ShredCounter"
+: library = "target/debug/examples/libdynamic_plugin.so"
+: settings = { every = 1000 }
+:
+: tests/dynamic_plugin.rs also sets `expect_slot` (handle_shred fails for shreds of other
+: slots) and `abi_version` (reported in the PluginApi instead of PLUGIN_ABI_VERSION).
+*/
+
+use std::ffi::{CStr, CString, c_char, c_void};
+
+use chainsmoker::dynamic::{CShred, PLUGIN_ABI_VERSION, PluginApi};
+
+struct ShredCounter {
+    every: u64,
+    expect_slot: Option<u64>,
+    seen: u64,
+    last_error: CString,
+}
+
+const NAME: &CStr = c"ShredCounter";
+
+#[unsafe(no_mangle)]
+pub extern "C" fn chainsmoker_plugin_abi_version() -> u32 {
+    PLUGIN_ABI_VERSION
+}
+
+/// # Safety
+/// `settings` is NUL-terminated JSON and `api` points to writable memory for a PluginApi.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn chainsmoker_plugin_create(
+    settings: *const c_char,
+    api: *mut PluginApi,
+) -> i32 {
+    let settings = unsafe { CStr::from_ptr(settings) }.to_string_lossy();
+    let Ok(settings) = serde_json::from_str::<serde_json::Value>(&settings) else {
+        return 1;
+    };
+    let setting = |name: &str| settings.get(name).and_then(|value| value.as_u64());
+    let abi_version = setting("abi_version")
+        .and_then(|version| u32::try_from(version).ok())
+        .unwrap_or(PLUGIN_ABI_VERSION);
+
+    let counter = Box::new(ShredCounter {
+        every: setting("every").unwrap_or(1000).max(1),
+        expect_slot: setting("expect_slot"),
+        seen: 0,
+        last_error: CString::default(),
+    });
+    unsafe {
+        api.write(PluginApi {
+            abi_version,
+            instance: Box::into_raw(counter).cast(),
+            name,
+            start,
+            handle_shred,
+            stop,
+            last_error: Some(last_error),
+            destroy,
+        })
+    };
+    0
+}
+
+fn counter<'a>(instance: *mut c_void) -> &'a mut ShredCounter {
+    unsafe { &mut *instance.cast::<ShredCounter>() }
+}
+
+unsafe extern "C" fn name(_instance: *mut c_void) -> *const c_char {
+    NAME.as_ptr()
+}
+
+unsafe extern "C" fn start(instance: *mut c_void) -> i32 {
+    let counter = counter(instance);
+    counter.seen = 0;
+    eprintln!(
+        "ShredCounter started, logging every {} shreds",
+        counter.every
+    );
+    0
+}
+
+unsafe extern "C" fn handle_shred(instance: *mut c_void, shred: *const CShred) -> i32 {
+    let counter = counter(instance);
+    let shred = unsafe { &*shred };
+    if shred.payload_len == 0 {
+        counter.last_error = c"empty payload".into();
+        return 1;
+    }
+    if let Some(expected) = counter.expect_slot
+        && shred.slot != expected
+    {
+        counter.last_error =
+            CString::new(format!("slot {}, expected {}", shred.slot, expected)).unwrap_or_default();
+        return 1;
+    }
+    counter.seen += 1;
+    if counter.seen % counter.every == 0 {
+        eprintln!(
+            "ShredCounter: {} shreds, last slot {} index {} ({} bytes)",
+            counter.seen, shred.slot, shred.index, shred.payload_len
+        );
+    }
+    0
+}
+
+unsafe extern "C" fn stop(instance: *mut c_void) -> i32 {
+    eprintln!(
+        "ShredCounter stopped after {} shreds",
+        counter(instance).seen
+    );
+    0
+}
+
+unsafe extern "C" fn last_error(instance: *mut c_void) -> *const c_char {
+    counter(instance).last_error.as_ptr()
+}
+
+unsafe extern "C" fn destroy(instance: *mut c_void) {
+    drop(unsafe { Box::from_raw(instance.cast::<ShredCounter>()) });
+}
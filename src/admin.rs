@@ -0,0 +1,386 @@
+/*
+ ** Admin Endpoint **
+: A local HTTP/JSON control plane for a running node, bound to 127.0.0.1 by default.
+: Read-only views (peers, stats, log filter) are answered by the server task itself.
+: Anything that touches the plugins or the slot tracker is sent as an `AdminCommand` to
+: the main loop, which owns them, and answered from there (`handle_command()`), so a
+: request can take up to one loop iteration (~1s when idle).
+
+*  ** Routes **
+! +--------+--------------------------+------------------------------------------------+
+! | Method | Path                     | Action                                         |
+! +--------+--------------------------+------------------------------------------------+
+! | GET    | /peers                   | Peer directory from gossip                     |
+! | GET    | /stats                   | Packet counters, channel depth, peer count     |
+! | GET    | /slots                   | Slots currently being received                 |
+! | GET    | /health                  | 200 if every plugin is running and not         |
+! |        |                          | unhealthy, 503 otherwise (see supervision.rs)  |
+! | GET    | /plugins                 | Running plugins with status, registered names, |
+! |        |                          | WASM routers with their counts                 |
+! | POST   | /plugins/<name>          | Build <name> from the registry and start it    |
+! | DELETE | /plugins/<name>          | Stop and remove a running plugin               |
+! | POST   | /plugins/<name>/pause    | Stop dispatching to a plugin                   |
+! | POST   | /plugins/<name>/resume   | Dispatch to it again                           |
+! | POST   | /plugins/<name>/restart  | Stop and start it, also a disabled one         |
+! | GET    | /log                     | Current log filter                             |
+! | PUT    | /log                     | Replace the log filter (body, EnvFilter syntax)|
+! +--------+--------------------------+------------------------------------------------+
+
+*  ** Example **
+: curl -s 127.0.0.1:9091/plugins
+: curl -s 127.0.0.1:9091/health
+: curl -s -X POST 127.0.0.1:9091/plugins/Console/pause
+: curl -s -X PUT --data 'chainsmoker=debug' 127.0.0.1:9091/log
+*/
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use log::{debug, info};
+use serde::Serialize;
+use serde_json::{Value, json};
+use solana_gossip::cluster_info::ClusterInfo;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::{mpsc, oneshot},
+};
+
+use crate::{
+    error::{Error, report},
+    gossip::peer_directory,
+    logging::LogHandle,
+    metrics::Metrics,
+    node::NodeStats,
+    output::{PluginRegistry, PluginRunner, PluginStatus},
+    slots::{SlotProgress, SlotTracker},
+    supervision::{PluginHealth, PluginState},
+    types::PeerInfo,
+    wasm::RouterStatus,
+};
+
+pub const DEFAULT_ADMIN_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9091);
+
+const MAX_REQUEST_BYTES: usize = 64 * 1024;
+
+#[derive(Debug, Serialize)]
+pub struct PluginsReply {
+    pub running: Vec<PluginStatus>,
+    pub available: Vec<String>,
+    pub routers: Vec<RouterStatus>,
+}
+
+pub enum AdminCommand {
+    Plugins(oneshot::Sender<PluginsReply>),
+    Slots(oneshot::Sender<Vec<SlotProgress>>),
+    AddPlugin {
+        name: String,
+        reply: oneshot::Sender<Result<(), Error>>,
+    },
+    RemovePlugin {
+        name: String,
+        reply: oneshot::Sender<Result<(), Error>>,
+    },
+    SetPaused {
+        name: String,
+        paused: bool,
+        reply: oneshot::Sender<Result<(), Error>>,
+    },
+    RestartPlugin {
+        name: String,
+        reply: oneshot::Sender<Result<(), Error>>,
+    },
+}
+
+// everything the server answers without going through the main loop
+#[derive(Clone)]
+pub struct AdminState {
+    pub cluster_info: Arc<ClusterInfo>,
+    pub metrics: Arc<Metrics>,
+    // None when the embedding application owns logging, /log then answers 404
+    pub log_handle: Option<LogHandle>,
+    pub commands: mpsc::UnboundedSender<AdminCommand>,
+}
+
+// runs a command against the state owned by the main loop
+pub async fn handle_command(
+    command: AdminCommand,
+    plugins: &mut PluginRunner,
+    registry: &PluginRegistry,
+    slots: &SlotTracker,
+) {
+    match command {
+        AdminCommand::Plugins(reply) => {
+            let _ = reply.send(PluginsReply {
+                running: plugins.status(),
+                available: registry.names(),
+                routers: plugins.router_status(),
+            });
+        }
+        AdminCommand::Slots(reply) => {
+            let _ = reply.send(slots.progress());
+        }
+        AdminCommand::AddPlugin { name, reply } => {
+            // started with default settings, configured plugins come from the config file
+            let result = match registry.create(&name, &Value::Null) {
+                Ok(plugin) => plugins.start_plugin(plugin).await,
+                Err(e) => Err(e),
+            };
+            let _ = reply.send(result);
+        }
+        AdminCommand::RemovePlugin { name, reply } => {
+            let result = plugins.remove_plugin(&name).await;
+            let _ = reply.send(result);
+        }
+        AdminCommand::SetPaused {
+            name,
+            paused,
+            reply,
+        } => {
+            let result = plugins.set_paused(&name, paused);
+            let _ = reply.send(result);
+        }
+        AdminCommand::RestartPlugin { name, reply } => {
+            let result = plugins.restart_plugin(&name).await;
+            let _ = reply.send(result);
+        }
+    }
+}
+
+pub async fn serve(state: AdminState, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(
+        "Serving admin endpoint on http://{}",
+        listener.local_addr()?
+    );
+
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        let state = state.clone();
+        tokio::spawn(async move {
+            if let Err(e) = handle_connection(stream, &state).await {
+                debug!("Admin request from {} failed: {}", peer, e);
+            }
+        });
+    }
+}
+
+struct Request {
+    method: String,
+    path: String,
+    body: String,
+}
+
+async fn handle_connection(mut stream: TcpStream, state: &AdminState) -> std::io::Result<()> {
+    let (status, body) = match read_request(&mut stream).await? {
+        Some(request) => route(&request, state).await,
+        None => (400, json!({ "error": "malformed request" })),
+    };
+
+    let body = body.to_string();
+    let response = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        reason(status),
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+async fn read_request(stream: &mut TcpStream) -> std::io::Result<Option<Request>> {
+    let mut buffer = Vec::new();
+    let mut chunk = [0u8; 4096];
+
+    let header_end = loop {
+        if let Some(end) = find(&buffer, b"\r\n\r\n") {
+            break end + 4;
+        }
+        if buffer.len() >= MAX_REQUEST_BYTES {
+            return Ok(None);
+        }
+        let size = stream.read(&mut chunk).await?;
+        if size == 0 {
+            return Ok(None);
+        }
+        buffer.extend_from_slice(&chunk[..size]);
+    };
+
+    let head = String::from_utf8_lossy(&buffer[..header_end]).to_string();
+    let mut lines = head.lines();
+    let mut request_line = lines.next().unwrap_or_default().split_whitespace();
+    let (Some(method), Some(path)) = (request_line.next(), request_line.next()) else {
+        return Ok(None);
+    };
+
+    let content_length = lines
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0)
+        .min(MAX_REQUEST_BYTES);
+
+    while buffer.len() < header_end + content_length {
+        let size = stream.read(&mut chunk).await?;
+        if size == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..size]);
+    }
+    let body_end = buffer.len().min(header_end + content_length);
+
+    Ok(Some(Request {
+        method: method.to_string(),
+        path: path.to_string(),
+        body: String::from_utf8_lossy(&buffer[header_end..body_end]).to_string(),
+    }))
+}
+
+async fn route(request: &Request, state: &AdminState) -> (u16, Value) {
+    let segments: Vec<&str> = request
+        .path
+        .trim_matches('/')
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["peers"]) => {
+            let peers: Vec<Value> = peer_directory(&state.cluster_info)
+                .iter()
+                .map(PeerInfo::to_json)
+                .collect();
+            (200, Value::from(peers))
+        }
+        ("GET", ["stats"]) => (200, json!(NodeStats::from_metrics(&state.metrics))),
+        ("GET", ["slots"]) => query(state, AdminCommand::Slots).await,
+        ("GET", ["health"]) => match send(state, AdminCommand::Plugins).await {
+            Some(reply) => health(&reply.running),
+            None => shutting_down(),
+        },
+        ("GET", ["plugins"]) => query(state, AdminCommand::Plugins).await,
+        ("POST", ["plugins", name]) => {
+            let name = name.to_string();
+            execute(state, |reply| AdminCommand::AddPlugin { name, reply }).await
+        }
+        ("DELETE", ["plugins", name]) => {
+            let name = name.to_string();
+            execute(state, |reply| AdminCommand::RemovePlugin { name, reply }).await
+        }
+        ("POST", ["plugins", name, action @ ("pause" | "resume")]) => {
+            let name = name.to_string();
+            let paused = *action == "pause";
+            execute(state, |reply| AdminCommand::SetPaused {
+                name,
+                paused,
+                reply,
+            })
+            .await
+        }
+        ("POST", ["plugins", name, "restart"]) => {
+            let name = name.to_string();
+            execute(state, |reply| AdminCommand::RestartPlugin { name, reply }).await
+        }
+        ("GET", ["log"]) => match &state.log_handle {
+            Some(log_handle) => (200, json!({ "filter": log_handle.filter() })),
+            None => (
+                404,
+                json!({ "error": "log filter not managed by this node" }),
+            ),
+        },
+        ("PUT", ["log"]) => match &state.log_handle {
+            Some(log_handle) => match log_handle.set_filter(request.body.trim()) {
+                Ok(()) => (200, json!({ "filter": log_handle.filter() })),
+                Err(e) => (400, json!({ "error": report(&e) })),
+            },
+            None => (
+                404,
+                json!({ "error": "log filter not managed by this node" }),
+            ),
+        },
+        _ => (404, json!({ "error": "not found" })),
+    }
+}
+
+async fn query<T: Serialize>(
+    state: &AdminState,
+    command: impl FnOnce(oneshot::Sender<T>) -> AdminCommand,
+) -> (u16, Value) {
+    match send(state, command).await {
+        Some(value) => (200, json!(value)),
+        None => shutting_down(),
+    }
+}
+
+async fn execute(
+    state: &AdminState,
+    command: impl FnOnce(oneshot::Sender<Result<(), Error>>) -> AdminCommand,
+) -> (u16, Value) {
+    match send(state, command).await {
+        Some(Ok(())) => (200, json!({ "ok": true })),
+        Some(Err(e)) => (400, json!({ "error": report(&e) })),
+        None => shutting_down(),
+    }
+}
+
+// None once the main loop stopped taking commands
+async fn send<T>(
+    state: &AdminState,
+    command: impl FnOnce(oneshot::Sender<T>) -> AdminCommand,
+) -> Option<T> {
+    let (reply, response) = oneshot::channel();
+    state.commands.send(command(reply)).ok()?;
+    response.await.ok()
+}
+
+// paused plugins count as healthy, pausing is up to the operator
+fn health(plugins: &[PluginStatus]) -> (u16, Value) {
+    let failing: Vec<&str> = plugins
+        .iter()
+        .filter(|plugin| {
+            plugin.state != PluginState::Running
+                || matches!(plugin.health, PluginHealth::Unhealthy(_))
+        })
+        .map(|plugin| plugin.name.as_str())
+        .collect();
+    let plugins: Vec<Value> = plugins
+        .iter()
+        .map(|plugin| {
+            json!({
+                "name": plugin.name,
+                "state": plugin.state,
+                "health": plugin.health,
+            })
+        })
+        .collect();
+    let status = if failing.is_empty() { 200 } else { 503 };
+    (
+        status,
+        json!({
+            "healthy": failing.is_empty(),
+            "failing": failing,
+            "plugins": plugins,
+        }),
+    )
+}
+
+fn shutting_down() -> (u16, Value) {
+    (503, json!({ "error": "node is shutting down" }))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        _ => "Service Unavailable",
+    }
+}
@@ -0,0 +1,190 @@
+/*
+ ** Shred Archive **
+: A flat file of received shreds, written by `chainsmoker record` and read back by
+: `chainsmoker replay` / `chainsmoker inspect`. Records keep the receive metadata so a
+: replay produces the same `ReceivedShred`s (and slot reports) as the live run.
+
+*  ** File Layout **
+: 8-byte magic `CSARCHV1`, followed by records until EOF. All integers little endian.
+
+! +--------+-----+--------+-------------+-----------------------------------------+
+! | Offset | Size| Type   | Name        | Purpose                                 |
+! +--------+-----+--------+-------------+-----------------------------------------+
+! | 0x00   | 8B  | u64    | received_at | Receive time, µs since the unix epoch   |
+! | 0x08   | 1B  | u8     | source      | 0 = turbine, 1 = repair                 |
+! | 0x09   | 16B | [u8]   | ip          | Sender IP (IPv4 stored IPv6-mapped)     |
+! | 0x19   | 2B  | u16    | port        | Sender port                             |
+! | 0x1b   | 2B  | u16    | len         | Payload length                          |
+! | 0x1d   | len | [u8]   | payload     | Shred bytes as received                 |
+! +--------+-----+--------+-------------+-----------------------------------------+
+*/
+
+use std::{
+    fs::File,
+    io::{self, BufReader, BufWriter, ErrorKind, Read, Write},
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    path::Path,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{
+    error::Error,
+    types::{ReceivedShred, ShredSource},
+    utils::parse_shred,
+};
+
+pub const ARCHIVE_MAGIC: &[u8; 8] = b"CSARCHV1";
+const RECORD_HEADER_BYTES: usize = 8 + 1 + 16 + 2 + 2;
+
+#[derive(Debug, Clone)]
+pub struct ArchiveRecord {
+    pub received_at: SystemTime,
+    pub source: ShredSource,
+    pub from: SocketAddr,
+    pub payload: Vec<u8>,
+}
+
+impl ArchiveRecord {
+    pub fn to_received(&self) -> Result<ReceivedShred, Error> {
+        Ok(ReceivedShred {
+            shred: Arc::new(parse_shred(&self.payload)?),
+            source: self.source,
+            from: self.from,
+            received_at: self.received_at,
+        })
+    }
+}
+
+pub struct ArchiveWriter {
+    writer: BufWriter<File>,
+    records: u64,
+}
+
+impl ArchiveWriter {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        writer.write_all(ARCHIVE_MAGIC)?;
+        Ok(Self { writer, records: 0 })
+    }
+
+    pub fn write(&mut self, received: &ReceivedShred) -> io::Result<()> {
+        self.write_packet(
+            received.received_at,
+            received.source,
+            received.from,
+            received.shred.payload(),
+        )
+    }
+
+    // any payload, also ones that don't parse as a shred (see reject.rs)
+    pub fn write_packet(
+        &mut self,
+        received_at: SystemTime,
+        source: ShredSource,
+        from: SocketAddr,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let received_at = received_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros() as u64;
+        let source = match source {
+            ShredSource::Turbine => 0u8,
+            ShredSource::Repair => 1u8,
+        };
+        let ip = match from.ip() {
+            IpAddr::V4(ip) => ip.to_ipv6_mapped(),
+            IpAddr::V6(ip) => ip,
+        };
+
+        let mut header = [0u8; RECORD_HEADER_BYTES];
+        header[0..8].copy_from_slice(&received_at.to_le_bytes());
+        header[8] = source;
+        header[9..25].copy_from_slice(&ip.octets());
+        header[25..27].copy_from_slice(&from.port().to_le_bytes());
+        header[27..29].copy_from_slice(&(payload.len() as u16).to_le_bytes());
+
+        self.writer.write_all(&header)?;
+        self.writer.write_all(payload)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn records(&self) -> u64 {
+        self.records
+    }
+
+    pub fn finish(mut self) -> io::Result<u64> {
+        self.writer.flush()?;
+        Ok(self.records)
+    }
+}
+
+pub struct ArchiveReader<R> {
+    reader: R,
+}
+
+impl ArchiveReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> ArchiveReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != ARCHIVE_MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "not a chainsmoker archive (bad magic)",
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<ArchiveRecord>> {
+        let mut header = [0u8; RECORD_HEADER_BYTES];
+        match self.reader.read_exact(&mut header) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e),
+        }
+
+        let received_at = u64::from_le_bytes(header[0..8].try_into().unwrap());
+        let source = match header[8] {
+            1 => ShredSource::Repair,
+            _ => ShredSource::Turbine,
+        };
+        let ip = Ipv6Addr::from(<[u8; 16]>::try_from(&header[9..25]).unwrap());
+        let ip = match ip.to_ipv4_mapped() {
+            Some(ip) => IpAddr::V4(ip),
+            None => IpAddr::V6(ip),
+        };
+        let port = u16::from_le_bytes([header[25], header[26]]);
+        let len = u16::from_le_bytes([header[27], header[28]]) as usize;
+
+        let mut payload = vec![0u8; len];
+        self.reader.read_exact(&mut payload)?;
+
+        Ok(Some(ArchiveRecord {
+            received_at: UNIX_EPOCH + Duration::from_micros(received_at),
+            source,
+            from: SocketAddr::new(ip, port),
+            payload,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for ArchiveReader<R> {
+    type Item = io::Result<ArchiveRecord>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
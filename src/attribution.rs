@@ -0,0 +1,459 @@
+/*
+ ** Peer Traffic Attribution **
+: Every TVU packet is attributed to the peer that sent it, so we can see which
+: retransmitters are actually useful. Source IPs are mapped to gossip identities using
+: the TVU (and gossip) addresses in `ClusterInfo`; retransmit sockets are not advertised
+: but share the IP of the node's TVU socket.
+
+*  ** Per Peer **
+! +----------------+------------------------------------------------------------+
+! | Field          | Meaning                                                    |
+! +----------------+------------------------------------------------------------+
+! | packets        | UDP packets received from the peer                         |
+! | valid          | Packets with a shred header of our shred version           |
+! | duplicates     | Shreds we already had from someone else (or the same peer) |
+! | first_arrivals | Shreds this peer delivered before anyone else              |
+! +----------------+------------------------------------------------------------+
+
+: valid_ratio = valid / packets, duplicate_ratio = duplicates / valid.
+: A shred is identified by (slot, index, data/coding); the set of seen shreds is kept
+: for the last `dedup_slots` slots. The shred receiver can drop what `record()` reports
+: as a duplicate before parsing it (see shred.rs).
+: At most `max_peers` source IPs are tracked. Once full, the quarter with the fewest
+: first arrivals (then valid shreds) is dropped, so spoofed sources can't grow the map.
+
+*  ** Export **
+: `PeerAttribution::top(n)` returns the peers with the most first arrivals. The top
+: `top_n` are also exported as `chainsmoker_top_peer_*{peer}` gauges (see metrics.rs),
+: refreshed every `refresh_interval` together with the IP -> pubkey map by a thread of
+: its own (`start()`). The map is built from `ClusterInfo` without holding the attribution
+: lock, and never on a receiver's thread, so receivers aren't stalled.
+*/
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, HashMap, HashSet},
+    net::{IpAddr, SocketAddr},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+use solana_gossip::{cluster_info::ClusterInfo, contact_info::Protocol};
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+
+use crate::{metrics::Metrics, shred::ShredHeaderView};
+
+// how often the refresh thread checks the exit flag
+const REFRESH_TICK: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy)]
+pub struct AttributionConfig {
+    pub top_n: usize,
+    pub refresh_interval: Duration,
+    pub dedup_slots: usize,
+    pub max_peers: usize,
+}
+
+impl Default for AttributionConfig {
+    fn default() -> Self {
+        Self {
+            top_n: 20,
+            refresh_interval: Duration::from_secs(10),
+            dedup_slots: 64,
+            max_peers: 4096,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerTraffic {
+    pub addr: IpAddr,
+    pub pubkey: Option<Pubkey>,
+    pub packets: u64,
+    pub valid: u64,
+    pub duplicates: u64,
+    pub first_arrivals: u64,
+}
+
+impl PeerTraffic {
+    fn new(addr: IpAddr) -> Self {
+        Self {
+            addr,
+            pubkey: None,
+            packets: 0,
+            valid: 0,
+            duplicates: 0,
+            first_arrivals: 0,
+        }
+    }
+
+    pub fn valid_ratio(&self) -> f64 {
+        ratio(self.valid, self.packets)
+    }
+
+    pub fn duplicate_ratio(&self) -> f64 {
+        ratio(self.duplicates, self.valid)
+    }
+
+    // pubkey when known, the IP otherwise
+    pub fn label(&self) -> String {
+        match self.pubkey {
+            Some(pubkey) => pubkey.to_string(),
+            None => self.addr.to_string(),
+        }
+    }
+}
+
+struct AttributionState {
+    peers: HashMap<IpAddr, PeerTraffic>,
+    seen: BTreeMap<Slot, HashSet<(u32, bool)>>,
+    identities: HashMap<IpAddr, Pubkey>,
+}
+
+// shared between the shred receiver (recording) and whoever asks for the top peers
+#[derive(Clone)]
+pub struct PeerAttribution {
+    cluster_info: Arc<ClusterInfo>,
+    config: AttributionConfig,
+    metrics: Option<Arc<Metrics>>,
+    state: Arc<Mutex<AttributionState>>,
+}
+
+impl PeerAttribution {
+    pub fn new(cluster_info: Arc<ClusterInfo>, config: AttributionConfig) -> Self {
+        Self {
+            cluster_info,
+            config,
+            metrics: None,
+            state: Arc::new(Mutex::new(AttributionState {
+                peers: HashMap::new(),
+                seen: BTreeMap::new(),
+                identities: HashMap::new(),
+            })),
+        }
+    }
+
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    // called by the shred receiver for every packet, `shred` is None if it isn't one of ours.
+    // Returns whether the shred arrived first, None if unknown (not a shred, slot too old)
+    pub fn record(&self, from: SocketAddr, shred: Option<&ShredHeaderView>) -> Option<bool> {
+        let mut state = self.state.lock().unwrap();
+        let arrival = shred.and_then(|shred| Self::mark_seen(&mut state, shred, &self.config));
+
+        if state.peers.len() >= self.config.max_peers && !state.peers.contains_key(&from.ip()) {
+            Self::evict(&mut state, self.config.max_peers);
+        }
+        let peer = state
+            .peers
+            .entry(from.ip())
+            .or_insert_with(|| PeerTraffic::new(from.ip()));
+        peer.packets += 1;
+        if shred.is_some() {
+            peer.valid += 1;
+        }
+        match arrival {
+            Some(true) => peer.first_arrivals += 1,
+            Some(false) => peer.duplicates += 1,
+            None => {}
+        }
+        arrival
+    }
+
+    // refreshes identities and the top peer gauges every `refresh_interval` until `exit`
+    pub fn start(&self, exit: Arc<AtomicBool>) -> thread::JoinHandle<()> {
+        let attribution = self.clone();
+        thread::spawn(move || {
+            info!("Starting peer attribution refresh...");
+            let mut refreshed_at: Option<Instant> = None;
+            while !exit.load(Ordering::Relaxed) {
+                let interval = attribution.config.refresh_interval;
+                if refreshed_at.is_none_or(|at| at.elapsed() >= interval) {
+                    attribution.refresh();
+                    refreshed_at = Some(Instant::now());
+                }
+                // short sleeps so shutdown doesn't wait out a whole interval
+                thread::sleep(REFRESH_TICK);
+            }
+        })
+    }
+
+    // keep the best three quarters of `max_peers`, dropping in bulk keeps this off the
+    // per-packet path when many new IPs show up
+    fn evict(state: &mut AttributionState, max_peers: usize) {
+        let keep = max_peers * 3 / 4;
+        if keep == 0 {
+            state.peers.clear();
+            return;
+        }
+        let mut ranks: Vec<(u64, u64)> = state
+            .peers
+            .values()
+            .map(|peer| (peer.first_arrivals, peer.valid))
+            .collect();
+        let (_, cutoff, _) = ranks.select_nth_unstable_by_key(keep - 1, |&rank| Reverse(rank));
+        let cutoff = *cutoff;
+        let mut kept = 0;
+        state.peers.retain(|_, peer| {
+            let retain = kept < keep && (peer.first_arrivals, peer.valid) >= cutoff;
+            kept += retain as usize;
+            retain
+        });
+        debug!("Evicted attribution peers down to {}", state.peers.len());
+    }
+
+    // Some(true) if the shred was recorded before, without recording anything. None if the
+    // slot is too old to tell
+    pub fn seen(&self, shred: &ShredHeaderView) -> Option<bool> {
+        let state = self.state.lock().unwrap();
+        let slot = shred.slot();
+        if Self::forgotten(&state, slot, &self.config) {
+            return None;
+        }
+        let key = (shred.index(), shred.is_data());
+        Some(
+            state
+                .seen
+                .get(&slot)
+                .is_some_and(|seen| seen.contains(&key)),
+        )
+    }
+
+    // Some(true) if nobody delivered this shred before, None if the slot is too old to tell
+    fn mark_seen(
+        state: &mut AttributionState,
+        shred: &ShredHeaderView,
+        config: &AttributionConfig,
+    ) -> Option<bool> {
+        let slot = shred.slot();
+        if Self::forgotten(state, slot, config) {
+            return None;
+        }
+
+        let first = state
+            .seen
+            .entry(slot)
+            .or_default()
+            .insert((shred.index(), shred.is_data()));
+        while state.seen.len() > config.dedup_slots {
+            state.seen.pop_first();
+        }
+        Some(first)
+    }
+
+    // older than every slot in a full seen set
+    fn forgotten(state: &AttributionState, slot: Slot, config: &AttributionConfig) -> bool {
+        state.seen.len() >= config.dedup_slots
+            && !state.seen.contains_key(&slot)
+            && state
+                .seen
+                .first_key_value()
+                .is_some_and(|(&oldest, _)| slot < oldest)
+    }
+
+    // peers sorted by first arrivals, then by valid shreds
+    pub fn top(&self, n: usize) -> Vec<PeerTraffic> {
+        let state = self.state.lock().unwrap();
+        Self::ranked(&state, n)
+    }
+
+    fn ranked(state: &AttributionState, n: usize) -> Vec<PeerTraffic> {
+        let mut peers: Vec<PeerTraffic> = state
+            .peers
+            .values()
+            .map(|peer| PeerTraffic {
+                pubkey: state.identities.get(&peer.addr).copied(),
+                ..peer.clone()
+            })
+            .collect();
+        peers.sort_unstable_by_key(|peer| Reverse((peer.first_arrivals, peer.valid)));
+        peers.truncate(n);
+        peers
+    }
+
+    fn refresh(&self) {
+        let mut identities = HashMap::new();
+        for (contact_info, _) in self.cluster_info.all_peers() {
+            let addrs = [
+                contact_info.tvu(Protocol::UDP),
+                contact_info.tvu(Protocol::QUIC),
+                contact_info.gossip(),
+            ];
+            for addr in addrs.into_iter().flatten() {
+                // several nodes behind one IP: the first one seen keeps it
+                identities
+                    .entry(addr.ip())
+                    .or_insert(*contact_info.pubkey());
+            }
+        }
+        debug!("Mapped {} peer IPs to identities", identities.len());
+
+        let top = {
+            let mut state = self.state.lock().unwrap();
+            state.identities = identities;
+            Self::ranked(&state, self.config.top_n)
+        };
+
+        let Some(metrics) = &self.metrics else {
+            return;
+        };
+        metrics.top_peer_packets.reset();
+        metrics.top_peer_first_arrivals.reset();
+        metrics.top_peer_valid_ratio.reset();
+        metrics.top_peer_duplicate_ratio.reset();
+        for peer in top {
+            let label = peer.label();
+            let label = [label.as_str()];
+            metrics
+                .top_peer_packets
+                .with_label_values(&label)
+                .set(peer.packets as f64);
+            metrics
+                .top_peer_first_arrivals
+                .with_label_values(&label)
+                .set(peer.first_arrivals as f64);
+            metrics
+                .top_peer_valid_ratio
+                .with_label_values(&label)
+                .set(peer.valid_ratio());
+            metrics
+                .top_peer_duplicate_ratio
+                .with_label_values(&label)
+                .set(peer.duplicate_ratio());
+        }
+    }
+}
+
+fn ratio(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        part as f64 / total as f64
+    }
+}
+
+// attribution over an empty cluster, for tests
+#[cfg(test)]
+pub(crate) fn test_attribution(config: AttributionConfig) -> PeerAttribution {
+    use solana_gossip::contact_info::ContactInfo;
+    use solana_sdk::signer::{Signer, keypair::Keypair};
+    use solana_streamer::socket::SocketAddrSpace;
+
+    let keypair = Arc::new(Keypair::new());
+    let contact_info = ContactInfo::new_localhost(&keypair.pubkey(), 0);
+    let cluster_info = ClusterInfo::new(contact_info, keypair, SocketAddrSpace::Unspecified);
+    PeerAttribution::new(Arc::new(cluster_info), config)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shred::test_data_shred;
+
+    fn peer(n: u8) -> SocketAddr {
+        SocketAddr::from(([10, 0, 0, n], 8001))
+    }
+
+    // records a data shred from `from`, returning whether it arrived first
+    fn record(
+        attribution: &PeerAttribution,
+        from: SocketAddr,
+        slot: Slot,
+        index: u32,
+    ) -> Option<bool> {
+        let payload = test_data_shred(slot, index, 0, 0, b"entries");
+        let header = ShredHeaderView::new(&payload).unwrap();
+        attribution.record(from, Some(&header))
+    }
+
+    #[test]
+    fn first_arrivals_and_duplicates() {
+        let attribution = test_attribution(AttributionConfig::default());
+        assert_eq!(record(&attribution, peer(1), 100, 0), Some(true));
+        assert_eq!(record(&attribution, peer(2), 100, 0), Some(false));
+        assert_eq!(record(&attribution, peer(2), 100, 1), Some(true));
+        attribution.record(peer(2), None);
+
+        let top = attribution.top(10);
+        assert_eq!(top.len(), 2);
+        let busiest = &top[0];
+        assert_eq!(busiest.addr, peer(2).ip());
+        assert_eq!(
+            (
+                busiest.packets,
+                busiest.valid,
+                busiest.duplicates,
+                busiest.first_arrivals
+            ),
+            (3, 2, 1, 1)
+        );
+        assert_eq!(busiest.valid_ratio(), 2.0 / 3.0);
+        assert_eq!(busiest.duplicate_ratio(), 0.5);
+        assert_eq!(top[0].label(), peer(2).ip().to_string());
+    }
+
+    #[test]
+    fn evicts_down_to_three_quarters_keeping_the_best() {
+        let attribution = test_attribution(AttributionConfig {
+            max_peers: 8,
+            ..AttributionConfig::default()
+        });
+        // peer n delivers n shreds first
+        let mut index = 0;
+        for n in 1..=8 {
+            for _ in 0..n {
+                record(&attribution, peer(n), 100, index);
+                index += 1;
+            }
+        }
+        assert_eq!(attribution.top(usize::MAX).len(), 8);
+
+        // a ninth source drops the two weakest before it is added
+        attribution.record(peer(9), None);
+        let mut kept: Vec<IpAddr> = attribution
+            .top(usize::MAX)
+            .iter()
+            .map(|peer| peer.addr)
+            .collect();
+        kept.sort();
+        let expected: Vec<IpAddr> = (3..=9).map(|n| peer(n).ip()).collect();
+        assert_eq!(kept, expected);
+
+        // below max_peers new sources are just added
+        record(&attribution, peer(10), 100, index);
+        assert_eq!(attribution.top(usize::MAX).len(), 8);
+    }
+
+    #[test]
+    fn slots_past_dedup_slots_are_forgotten() {
+        let attribution = test_attribution(AttributionConfig {
+            dedup_slots: 2,
+            ..AttributionConfig::default()
+        });
+        let payload = test_data_shred(100, 0, 0, 0, b"entries");
+        let oldest = ShredHeaderView::new(&payload).unwrap();
+
+        assert_eq!(attribution.seen(&oldest), Some(false));
+        assert_eq!(record(&attribution, peer(1), 100, 0), Some(true));
+        assert_eq!(attribution.seen(&oldest), Some(true));
+        record(&attribution, peer(1), 101, 0);
+        record(&attribution, peer(1), 102, 0);
+
+        // 100 was dropped for 102 and is older than anything kept, nothing can be told
+        assert_eq!(attribution.seen(&oldest), None);
+        assert_eq!(record(&attribution, peer(1), 100, 0), None);
+        // newer slots are still tracked
+        assert_eq!(record(&attribution, peer(1), 103, 0), Some(true));
+        assert_eq!(record(&attribution, peer(1), 103, 0), Some(false));
+
+        let top = attribution.top(1);
+        assert_eq!((top[0].first_arrivals, top[0].duplicates), (4, 1));
+    }
+}
@@ -0,0 +1,273 @@
+use std::{net::IpAddr, net::SocketAddr, path::PathBuf};
+
+use clap::{Args, Parser, Subcommand};
+
+use chainsmoker::{
+    Error,
+    admin::DEFAULT_ADMIN_ADDR,
+    config::{
+        Config, EndpointsConfig, IdentityConfig, LogConfig, NetworkConfig, PluginConfig,
+        ReceiverConfig, SocketsConfig, VerificationConfig,
+    },
+    logging::{DEFAULT_LOG_FILTER, LogFormat},
+    metrics::DEFAULT_METRICS_ADDR,
+    types::{Network, NodeMode},
+};
+use solana_net_utils::PortRange;
+
+#[derive(Debug, Parser)]
+#[command(name = "chainsmoker", version, about = "Solana shred streaming client")]
+pub struct Cli {
+    #[arg(
+        long,
+        global = true,
+        default_value = "text",
+        help = "text or json lines"
+    )]
+    pub log_format: LogFormat,
+
+    #[arg(long, global = true, default_value = DEFAULT_LOG_FILTER, help = "EnvFilter directives, RUST_LOG wins if set")]
+    pub log_filter: String,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    #[command(about = "Join gossip, receive shreds and pass them to plugins")]
+    Listen(ListenArgs),
+    #[command(about = "Join gossip as an observer and dump the discovered peer directory")]
+    Peers(PeersArgs),
+    #[command(about = "Like listen, but also write every received shred to an archive")]
+    Record(RecordArgs),
+    #[command(about = "Feed a recorded archive into plugins")]
+    Replay(ReplayArgs),
+    #[command(about = "Decode every field of shreds from an archive, a raw shred file or hex")]
+    Inspect(InspectArgs),
+}
+
+#[derive(Debug, Args)]
+pub struct NetworkArgs {
+    #[arg(long, default_value = "mainnet", help = "mainnet or testnet")]
+    pub network: Network,
+
+    #[arg(
+        long,
+        default_value = "0.0.0.0",
+        help = "local interface the sockets are bound to"
+    )]
+    pub bind: IpAddr,
+
+    #[arg(
+        long,
+        help = "public IP put in ContactInfo, discovered through the entrypoints if not given"
+    )]
+    pub advertise: Option<IpAddr>,
+
+    #[arg(long, help = "fixed gossip port, overrides --port-range")]
+    pub gossip_port: Option<u16>,
+
+    #[arg(long, help = "fixed TVU port, overrides --port-range")]
+    pub tvu_port: Option<u16>,
+
+    #[arg(long, default_value = "8000-10000", value_parser = parse_port_range, help = "free ports are picked from this range, e.g. 8000-10000")]
+    pub port_range: PortRange,
+
+    #[arg(long, help = "skip the ip-echo reachability check of our ports")]
+    pub no_verify_reachable: bool,
+
+    #[arg(
+        long,
+        help = "keypair JSON file (solana-keygen format), a new identity is generated if not given"
+    )]
+    pub identity: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct ListenArgs {
+    #[arg(
+        long,
+        help = "TOML (or .yaml/.yml) config file, replaces the flags below and is reloaded on SIGHUP"
+    )]
+    pub config: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub network: NetworkArgs,
+
+    #[arg(long, default_value = "full", help = "full, observer or spy")]
+    pub mode: NodeMode,
+
+    #[arg(
+        long,
+        help = "`solana leader-schedule --output json` file, for per-leader latency"
+    )]
+    pub leader_schedule: Option<PathBuf>,
+
+    #[arg(long, help = "ask serve-repair peers for missing shreds")]
+    pub repair: bool,
+
+    #[arg(long, help = "fixed repair port, overrides --port-range")]
+    pub repair_port: Option<u16>,
+
+    #[arg(
+        long,
+        help = "write the raw bytes of rejected packets here, one archive per reason"
+    )]
+    pub quarantine_dir: Option<PathBuf>,
+
+    #[arg(
+        long = "plugin",
+        default_value = "Console",
+        help = "plugins to start with, by registered name (repeatable)"
+    )]
+    pub plugins: Vec<String>,
+
+    #[arg(long, default_value_t = DEFAULT_METRICS_ADDR, help = "Prometheus scrape endpoint")]
+    pub metrics_addr: SocketAddr,
+
+    #[arg(long, default_value_t = DEFAULT_ADMIN_ADDR, help = "admin endpoint, see admin.rs")]
+    pub admin_addr: SocketAddr,
+}
+
+impl ListenArgs {
+    // the config file if one is given, otherwise a config built from the flags
+    pub fn config(
+        &self,
+        logging: LogConfig,
+    ) -> Result<(Config, Option<PathBuf>), Box<dyn std::error::Error>> {
+        if let Some(path) = &self.config {
+            return Ok((Config::load(path)?, Some(path.clone())));
+        }
+
+        let network = &self.network;
+        let config = Config {
+            network: NetworkConfig {
+                cluster: network.network,
+                entrypoints: Vec::new(),
+                mode: self.mode,
+                leader_schedule: self.leader_schedule.clone(),
+            },
+            identity: IdentityConfig {
+                keypair: network.identity.clone(),
+            },
+            sockets: SocketsConfig {
+                bind: network.bind,
+                advertise: network.advertise,
+                port_range: network.port_range,
+                gossip_port: network.gossip_port,
+                tvu_port: network.tvu_port,
+                repair: self.repair,
+                repair_port: self.repair_port,
+            },
+            receiver: ReceiverConfig {
+                quarantine_dir: self.quarantine_dir.clone(),
+                ..ReceiverConfig::default()
+            },
+            verification: VerificationConfig {
+                reachable: !network.no_verify_reachable,
+                ..VerificationConfig::default()
+            },
+            logging,
+            endpoints: EndpointsConfig {
+                metrics: self.metrics_addr,
+                admin: self.admin_addr,
+            },
+            plugins: self
+                .plugins
+                .iter()
+                .map(|name| PluginConfig::new(name))
+                .collect(),
+            routers: Vec::new(),
+        };
+        config
+            .validate()
+            .map_err(|problems| Error::InvalidOptions { problems })?;
+        Ok((config, None))
+    }
+}
+
+#[derive(Debug, Args)]
+pub struct PeersArgs {
+    #[command(flatten)]
+    pub network: NetworkArgs,
+
+    #[arg(
+        long,
+        default_value_t = 30,
+        help = "seconds to let gossip fill the directory before dumping it"
+    )]
+    pub wait: u64,
+
+    #[arg(long, help = "one JSON array instead of a table")]
+    pub json: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct RecordArgs {
+    #[command(flatten)]
+    pub listen: ListenArgs,
+
+    #[arg(long, short, help = "archive file to write")]
+    pub output: PathBuf,
+
+    #[arg(
+        long,
+        help = "stop after this many seconds, runs until SIGINT/SIGTERM otherwise"
+    )]
+    pub duration: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+pub struct ReplayArgs {
+    #[arg(long, short, help = "archive written by `record`")]
+    pub input: PathBuf,
+
+    #[arg(
+        long = "plugin",
+        default_value = "Console",
+        help = "plugins to replay into (repeatable)"
+    )]
+    pub plugins: Vec<String>,
+
+    #[arg(
+        long,
+        help = "keep the original spacing between shreds instead of replaying as fast as possible"
+    )]
+    pub realtime: bool,
+}
+
+#[derive(Debug, Args)]
+#[command(group = clap::ArgGroup::new("source").required(true).args(["hex", "file"]))]
+pub struct InspectArgs {
+    #[arg(long, help = "one shred as hex")]
+    pub hex: Option<String>,
+
+    #[arg(long, help = "an archive written by `record`, or a single raw shred")]
+    pub file: Option<PathBuf>,
+
+    #[arg(long, help = "decode at most this many shreds from an archive")]
+    pub limit: Option<usize>,
+
+    #[arg(long, help = "JSON instead of text, one object per line for archives")]
+    pub json: bool,
+
+    #[arg(
+        long,
+        conflicts_with = "json",
+        help = "one line per shred instead of every field and a hex dump"
+    )]
+    pub brief: bool,
+}
+
+fn parse_port_range(s: &str) -> Result<PortRange, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("expected <start>-<end>, got '{}'", s))?;
+    let start: u16 = start.trim().parse().map_err(|e| format!("{}", e))?;
+    let end: u16 = end.trim().parse().map_err(|e| format!("{}", e))?;
+    if start >= end {
+        return Err(format!("empty port range {}-{}", start, end));
+    }
+    Ok((start, end))
+}
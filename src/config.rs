@@ -0,0 +1,947 @@
+/*
+ ** Config File **
+: Deployments describe the whole pipeline in one file instead of CLI flags:
+: `chainsmoker listen --config chainsmoker.toml`. TOML by default, YAML if the file ends
+: in `.yaml`/`.yml`. Every section and field is optional and defaults to what the CLI
+: would use; unknown fields are rejected so typos don't go unnoticed.
+: The file is validated as a whole at startup and every problem is reported at once.
+
+*  ** Sections **
+! +--------------+---------------------------------------------------------------+
+! | Section      | Fields                                                        |
+! +--------------+---------------------------------------------------------------+
+! | network      | cluster (mainnet/testnet), entrypoints (host:port, overrides  |
+! |              | the cluster's), mode (full/observer/spy), leader_schedule     |
+! |              | (`solana leader-schedule --output json` file, see latency.rs) |
+! | identity     | keypair (solana-keygen JSON file, new identity if unset)      |
+! | sockets      | bind, advertise, port_range, gossip_port, tvu_port, repair,   |
+! |              | repair_port                                                   |
+! | receiver     | batch_size, log_sample, drain_timeout_ms, slot_timeout_ms,    |
+! |              | max_tracked_slots, filter (shreds to parse at all, see        |
+! |              | filter.rs), drop_duplicates, quarantine_dir, quarantine_limit |
+! |              | (raw rejected packets on disk, see reject.rs)                 |
+! | verification | reachable (ip-echo check of our ports), shred_version (drop   |
+! |              | shreds with another version)                                  |
+! | logging      | format (text/json), filter (EnvFilter syntax)                 |
+! | endpoints    | metrics, admin (listen addresses)                             |
+! | plugins      | list of { name, enabled, library, filter, batch, supervision, |
+! |              | settings } (library loads the plugin from a shared library    |
+! |              | instead of the registry, see dynamic.rs; filter narrows what  |
+! |              | the plugin declares, see filter.rs; batch is { max_shreds,    |
+! |              | window_ms } for handle_batch(), see output.rs; supervision is |
+! |              | { on_error, max_consecutive_errors, restart_backoff_ms,       |
+! |              | max_backoff_ms, max_restarts }, see supervision.rs)           |
+! | routers      | list of { name, module, routes, fuel, max_memory_bytes }, WASM|
+! |              | modules deciding which plugins get a shred, see wasm.rs       |
+! +--------------+---------------------------------------------------------------+
+
+*  ** Example **
+: [network]
+: cluster = "testnet"
+:
+: [sockets]
+: advertise = "203.0.113.7"
+: port_range = [8000, 8100]
+:
+: [[plugins]]
+: name = "Console"
+: filter = { shred_type = "data" }
+: settings = { every = 100 }
+
+*  ** Live Reload **
+: On SIGHUP the file is read and validated again (`watch_reload()`); an invalid file is
+: logged and the running config is kept. `apply_reload()` then applies what can change
+: without a restart:
+
+! +-----------------------+------------------------------------------------------+
+! | Change                | Effect                                               |
+! +-----------------------+------------------------------------------------------+
+! | logging.filter        | new log filter                                       |
+! | plugin filter         | new ShredFilter for the running plugin               |
+! | plugin batch          | new BatchConfig for the running plugin               |
+! | plugin supervision    | new SupervisionConfig for the running plugin         |
+! | routers               | all modules are loaded again and replace the running |
+! |                       | routers, if any fails the running ones are kept      |
+! | plugin settings or    | plugin is stopped and started again with them        |
+! | library               |                                                      |
+! | plugin added/enabled  | plugin is started                                    |
+! | plugin removed/       | plugin is stopped                                    |
+! | disabled              |                                                      |
+! | anything else         | logged, applied on the next restart                  |
+! +-----------------------+------------------------------------------------------+
+
+: Plugins are matched by name, so a plugin's `name()` must be its registered name.
+: Plugins started through the admin endpoint are left alone.
+*/
+
+use std::{
+    collections::HashSet,
+    io,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use serde::Deserialize;
+use solana_net_utils::PortRange;
+use tokio::{
+    signal::unix::{SignalKind, signal},
+    sync::mpsc,
+};
+use tracing_subscriber::EnvFilter;
+
+use crate::{
+    admin::DEFAULT_ADMIN_ADDR,
+    dynamic::DynamicPlugin,
+    error::{BoxError, Error, report},
+    filter::ShredFilter,
+    logging::{DEFAULT_LOG_FILTER, LogFormat, LogHandle, LoggingConfig},
+    metrics::DEFAULT_METRICS_ADDR,
+    output::{BatchConfig, OutputPlugin, PluginRegistry, PluginRunner, PluginSettings},
+    reject::DEFAULT_QUARANTINE_LIMIT,
+    shred::DEFAULT_LOG_SAMPLE,
+    shutdown::{DEFAULT_DRAIN_TIMEOUT, ShutdownConfig},
+    slots::SlotTrackerConfig,
+    sockets::{DEFAULT_PORT_RANGE, SocketConfig},
+    supervision::SupervisionConfig,
+    types::{Network, NodeMode},
+    utils::{resolve_addresses, resolve_entrypoints},
+    wasm::{RouterConfig, WasmRouter},
+};
+
+// shreds handled per receive_batch span
+pub const DEFAULT_BATCH_SIZE: usize = 256;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub identity: IdentityConfig,
+    pub sockets: SocketsConfig,
+    pub receiver: ReceiverConfig,
+    pub verification: VerificationConfig,
+    pub logging: LogConfig,
+    pub endpoints: EndpointsConfig,
+    pub plugins: Vec<PluginConfig>,
+    pub routers: Vec<RouterConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub cluster: Network,
+    // host:port, the cluster's entrypoints are used if empty
+    pub entrypoints: Vec<String>,
+    pub mode: NodeMode,
+    // slot -> leader for latency labels and plugin filters on `leaders`
+    pub leader_schedule: Option<PathBuf>,
+}
+
+impl Default for NetworkConfig {
+    fn default() -> Self {
+        Self {
+            cluster: Network::Mainnet,
+            entrypoints: Vec::new(),
+            mode: NodeMode::Full,
+            leader_schedule: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdentityConfig {
+    pub keypair: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SocketsConfig {
+    pub bind: IpAddr,
+    pub advertise: Option<IpAddr>,
+    pub port_range: PortRange,
+    pub gossip_port: Option<u16>,
+    pub tvu_port: Option<u16>,
+    pub repair: bool,
+    pub repair_port: Option<u16>,
+}
+
+impl Default for SocketsConfig {
+    fn default() -> Self {
+        let sockets = SocketConfig::default();
+        Self {
+            bind: sockets.bind_address,
+            advertise: None,
+            port_range: DEFAULT_PORT_RANGE,
+            gossip_port: None,
+            tvu_port: None,
+            repair: false,
+            repair_port: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReceiverConfig {
+    pub batch_size: usize,
+    pub log_sample: u64,
+    pub drain_timeout_ms: u64,
+    pub slot_timeout_ms: u64,
+    pub max_tracked_slots: usize,
+    pub filter: ShredFilter,
+    pub drop_duplicates: bool,
+    pub quarantine_dir: Option<PathBuf>,
+    pub quarantine_limit: u64,
+}
+
+impl Default for ReceiverConfig {
+    fn default() -> Self {
+        let slots = SlotTrackerConfig::default();
+        Self {
+            batch_size: DEFAULT_BATCH_SIZE,
+            log_sample: DEFAULT_LOG_SAMPLE,
+            drain_timeout_ms: DEFAULT_DRAIN_TIMEOUT.as_millis() as u64,
+            slot_timeout_ms: slots.slot_timeout.as_millis() as u64,
+            max_tracked_slots: slots.max_tracked_slots,
+            filter: ShredFilter::default(),
+            drop_duplicates: false,
+            quarantine_dir: None,
+            quarantine_limit: DEFAULT_QUARANTINE_LIMIT,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VerificationConfig {
+    pub reachable: bool,
+    pub shred_version: Option<u16>,
+}
+
+impl Default for VerificationConfig {
+    fn default() -> Self {
+        Self {
+            reachable: true,
+            shred_version: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub format: LogFormat,
+    pub filter: String,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            filter: DEFAULT_LOG_FILTER.to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EndpointsConfig {
+    pub metrics: SocketAddr,
+    pub admin: SocketAddr,
+}
+
+impl Default for EndpointsConfig {
+    fn default() -> Self {
+        Self {
+            metrics: DEFAULT_METRICS_ADDR,
+            admin: DEFAULT_ADMIN_ADDR,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PluginConfig {
+    pub name: String,
+    #[serde(default = "enabled")]
+    pub enabled: bool,
+    // a shared library to load the plugin from instead of the registry, see dynamic.rs
+    #[serde(default)]
+    pub library: Option<PathBuf>,
+    #[serde(default)]
+    pub filter: ShredFilter,
+    // None keeps what the plugin declares, see output.rs
+    #[serde(default)]
+    pub batch: Option<BatchConfig>,
+    // None keeps what the plugin declares, see supervision.rs
+    #[serde(default)]
+    pub supervision: Option<SupervisionConfig>,
+    #[serde(default)]
+    pub settings: PluginSettings,
+}
+
+impl PluginConfig {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            enabled: true,
+            library: None,
+            filter: ShredFilter::default(),
+            batch: None,
+            supervision: None,
+            settings: PluginSettings::Null,
+        }
+    }
+
+    pub fn create(&self, registry: &PluginRegistry) -> Result<Box<dyn OutputPlugin>, Error> {
+        match &self.library {
+            Some(library) => Ok(Box::new(DynamicPlugin::load(
+                library,
+                &self.name,
+                &self.settings,
+            )?)),
+            None => registry.create(&self.name, &self.settings),
+        }
+    }
+
+    // what the runner applies on top of the plugin itself, live on reload
+    pub fn configure(&self, plugins: &mut PluginRunner) -> Result<(), Error> {
+        plugins.set_filter(&self.name, self.filter.clone())?;
+        plugins.set_batch(&self.name, self.batch)?;
+        plugins.set_supervision(&self.name, self.supervision)
+    }
+}
+
+fn enabled() -> bool {
+    true
+}
+
+impl Config {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path).map_err(|source| Error::ConfigRead {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        let yaml = matches!(
+            path.extension().and_then(|ext| ext.to_str()),
+            Some("yaml" | "yml")
+        );
+        let config: Config = if yaml {
+            serde_yaml::from_str(&contents).map_err(BoxError::from)
+        } else {
+            toml::from_str(&contents).map_err(BoxError::from)
+        }
+        .map_err(|source| Error::ConfigParse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        config.validate().map_err(|problems| Error::InvalidConfig {
+            path: path.to_path_buf(),
+            problems,
+        })?;
+        Ok(config)
+    }
+
+    // every problem on its own line, so one run shows everything that needs fixing
+    pub fn validate(&self) -> Result<(), String> {
+        let mut problems = Vec::new();
+
+        for entrypoint in &self.network.entrypoints {
+            let valid = entrypoint
+                .rsplit_once(':')
+                .is_some_and(|(host, port)| !host.is_empty() && port.parse::<u16>().is_ok());
+            if !valid {
+                problems.push(format!(
+                    "network.entrypoints: '{}' is not host:port",
+                    entrypoint
+                ));
+            }
+        }
+
+        if let Some(keypair) = &self.identity.keypair
+            && !keypair.is_file()
+        {
+            problems.push(format!(
+                "identity.keypair: {} does not exist",
+                keypair.display()
+            ));
+        }
+
+        let (start, end) = self.sockets.port_range;
+        if start >= end {
+            problems.push(format!("sockets.port_range: {}-{} is empty", start, end));
+        }
+        let ports = [
+            ("gossip_port", self.sockets.gossip_port),
+            ("tvu_port", self.sockets.tvu_port),
+            ("repair_port", self.sockets.repair_port),
+        ];
+        let mut used = HashSet::new();
+        for (field, port) in ports {
+            match port {
+                Some(0) => problems.push(format!("sockets.{}: must not be 0", field)),
+                Some(port) if !used.insert(port) => {
+                    problems.push(format!("sockets.{}: {} is used twice", field, port))
+                }
+                _ => {}
+            }
+        }
+        if self.sockets.repair && !self.network.mode.receives_shreds() {
+            problems.push(format!(
+                "sockets.repair: needs network.mode = \"full\", not {:?}",
+                self.network.mode
+            ));
+        }
+
+        let receiver = &self.receiver;
+        for (field, value) in [
+            ("batch_size", receiver.batch_size as u64),
+            ("log_sample", receiver.log_sample),
+            ("slot_timeout_ms", receiver.slot_timeout_ms),
+            ("max_tracked_slots", receiver.max_tracked_slots as u64),
+        ] {
+            if value == 0 {
+                problems.push(format!("receiver.{}: must be at least 1", field));
+            }
+        }
+        if let Err(e) = receiver.filter.validate_header_only() {
+            problems.push(format!("receiver.filter: {}", e));
+        }
+        if receiver.quarantine_dir.is_some() && receiver.quarantine_limit == 0 {
+            problems.push("receiver.quarantine_limit: must be at least 1".to_string());
+        }
+
+        if let Err(e) = EnvFilter::try_new(&self.logging.filter) {
+            problems.push(format!("logging.filter: {}", e));
+        }
+
+        let mut names = HashSet::new();
+        for (i, plugin) in self.plugins.iter().enumerate() {
+            if plugin.name.is_empty() {
+                problems.push(format!("plugins[{}].name: must not be empty", i));
+            } else if !names.insert(plugin.name.as_str()) {
+                problems.push(format!(
+                    "plugins[{}].name: {} is configured twice",
+                    i, plugin.name
+                ));
+            }
+            if let Err(e) = plugin.filter.validate() {
+                problems.push(format!("plugins[{}].filter: {}", i, e));
+            }
+            if let Some(Err(e)) = plugin.batch.map(|batch| batch.validate()) {
+                problems.push(format!("plugins[{}].batch: {}", i, e));
+            }
+            if let Some(Err(e)) = plugin.supervision.map(|supervision| supervision.validate()) {
+                problems.push(format!("plugins[{}].supervision: {}", i, e));
+            }
+        }
+
+        let mut names = HashSet::new();
+        for (i, router) in self.routers.iter().enumerate() {
+            if router.name.is_empty() {
+                problems.push(format!("routers[{}].name: must not be empty", i));
+            } else if !names.insert(router.name.as_str()) {
+                problems.push(format!(
+                    "routers[{}].name: {} is configured twice",
+                    i, router.name
+                ));
+            }
+            if let Err(e) = router.validate() {
+                problems.push(format!("routers[{}]: {}", i, e));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(problems.iter().map(|p| format!("\n  - {}", p)).collect())
+        }
+    }
+
+    // plugins that should be running
+    pub fn enabled_plugins(&self) -> impl Iterator<Item = &PluginConfig> {
+        self.plugins.iter().filter(|plugin| plugin.enabled)
+    }
+
+    pub fn load_routers(&self) -> Result<Vec<WasmRouter>, Error> {
+        self.routers.iter().map(RouterConfig::load).collect()
+    }
+
+    pub fn entrypoints(&self) -> Result<Vec<SocketAddr>, Error> {
+        if self.network.entrypoints.is_empty() {
+            resolve_entrypoints(self.network.cluster)
+        } else {
+            resolve_addresses(&self.network.entrypoints)
+        }
+    }
+
+    pub fn socket_config(&self) -> SocketConfig {
+        SocketConfig {
+            mode: self.network.mode,
+            bind_address: self.sockets.bind,
+            advertise_address: self.sockets.advertise,
+            port_range: self.sockets.port_range,
+            gossip_port: self.sockets.gossip_port,
+            tvu_port: self.sockets.tvu_port,
+            repair: self.sockets.repair,
+            repair_port: self.sockets.repair_port,
+            verify_reachable: self.verification.reachable,
+        }
+    }
+
+    pub fn logging_config(&self) -> LoggingConfig {
+        LoggingConfig {
+            format: self.logging.format,
+            filter: self.logging.filter.clone(),
+        }
+    }
+
+    pub fn slot_tracker_config(&self) -> SlotTrackerConfig {
+        SlotTrackerConfig {
+            slot_timeout: Duration::from_millis(self.receiver.slot_timeout_ms),
+            max_tracked_slots: self.receiver.max_tracked_slots,
+        }
+    }
+
+    pub fn shutdown_config(&self) -> ShutdownConfig {
+        ShutdownConfig {
+            drain_timeout: Duration::from_millis(self.receiver.drain_timeout_ms),
+        }
+    }
+}
+
+// reads and validates `path` again on every SIGHUP and hands valid configs to the main loop
+pub async fn watch_reload(path: PathBuf, sender: mpsc::UnboundedSender<Config>) -> io::Result<()> {
+    let mut hangup = signal(SignalKind::hangup())?;
+
+    while hangup.recv().await.is_some() {
+        info!("Received SIGHUP, reloading {}", path.display());
+        match Config::load(&path) {
+            Ok(config) => {
+                if sender.send(config).is_err() {
+                    break;
+                }
+            }
+            Err(e) => error!("Keeping the running config: {}", report(&e)),
+        }
+    }
+    Ok(())
+}
+
+// applies what can change live and returns the config now in effect
+pub async fn apply_reload(
+    current: &Config,
+    new: Config,
+    plugins: &mut PluginRunner,
+    registry: &PluginRegistry,
+    log_handle: Option<&LogHandle>,
+) -> Config {
+    let restart_only = [
+        ("network", current.network != new.network),
+        ("identity", current.identity != new.identity),
+        ("sockets", current.sockets != new.sockets),
+        ("receiver", current.receiver != new.receiver),
+        ("verification", current.verification != new.verification),
+        ("endpoints", current.endpoints != new.endpoints),
+        (
+            "logging.format",
+            current.logging.format != new.logging.format,
+        ),
+    ];
+    for (section, _) in restart_only.iter().filter(|(_, changed)| *changed) {
+        warn!("Config {} changed, restart to apply it", section);
+    }
+
+    let mut logging = current.logging.clone();
+    if new.logging.filter != current.logging.filter {
+        match log_handle.map(|handle| handle.set_filter(&new.logging.filter)) {
+            Some(Ok(())) => logging.filter = new.logging.filter.clone(),
+            Some(Err(e)) => error!("Failed to apply logging.filter: {}", report(&e)),
+            None => warn!("Config logging.filter changed, but logging isn't managed by this node"),
+        }
+    }
+
+    // plugins from the old config that should no longer run
+    for old in current.enabled_plugins() {
+        let keep = new.enabled_plugins().any(|plugin| plugin.name == old.name);
+        if !keep && let Err(e) = plugins.remove_plugin(&old.name).await {
+            error!("{}", report(&e));
+        }
+    }
+
+    for plugin in new.enabled_plugins() {
+        let previous = current
+            .enabled_plugins()
+            .find(|old| old.name == plugin.name);
+        let running = plugins
+            .status()
+            .iter()
+            .any(|status| status.name == plugin.name);
+
+        let unchanged = previous
+            .is_some_and(|old| old.settings == plugin.settings && old.library == plugin.library);
+        if running && unchanged {
+            if let Err(e) = plugin.configure(plugins) {
+                error!("Failed to configure plugin {}: {}", plugin.name, e);
+            }
+            continue;
+        }
+
+        // new plugin, library or settings, which plugins only read when they are built
+        if running && let Err(e) = plugins.remove_plugin(&plugin.name).await {
+            error!("{}", report(&e));
+            continue;
+        }
+        let started = match plugin.create(registry) {
+            Ok(instance) => plugins.start_plugin(instance).await,
+            Err(e) => Err(e),
+        };
+        match started {
+            Ok(()) => {
+                if let Err(e) = plugin.configure(plugins) {
+                    error!("Failed to configure plugin {}: {}", plugin.name, e);
+                }
+            }
+            Err(e) => error!("{}", report(&e)),
+        }
+    }
+
+    info!("Config reloaded");
+    // after the plugins, so routes are checked against the ones now running
+    let mut routers = current.routers.clone();
+    if new.routers != current.routers {
+        match new.load_routers() {
+            Ok(loaded) => {
+                plugins.set_routers(loaded);
+                routers = new.routers;
+            }
+            Err(e) => error!("Keeping the running routers: {}", report(&e)),
+        }
+    }
+
+    Config {
+        logging,
+        plugins: new.plugins,
+        routers,
+        ..current.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use solana_ledger::shred::Shred;
+
+    use super::*;
+    use crate::filter::ShredKind;
+
+    // "start <name> <settings>" and "stop <name>", in call order
+    type Events = Arc<Mutex<Vec<String>>>;
+
+    struct Recorder {
+        name: String,
+        settings: PluginSettings,
+        events: Events,
+    }
+
+    #[async_trait::async_trait]
+    impl OutputPlugin for Recorder {
+        async fn start(&mut self) -> Result<(), BoxError> {
+            let event = format!("start {} {}", self.name, self.settings);
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        async fn handle_shred(&mut self, _shred: Shred) -> Result<(), BoxError> {
+            Ok(())
+        }
+
+        async fn stop(&mut self) -> Result<(), BoxError> {
+            let event = format!("stop {}", self.name);
+            self.events.lock().unwrap().push(event);
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            &self.name
+        }
+    }
+
+    fn registry(events: &Events) -> PluginRegistry {
+        let mut registry = PluginRegistry::new();
+        for name in ["Console", "Counter", "Sink", "Extra"] {
+            let events = events.clone();
+            registry.register(
+                name,
+                Box::new(move |settings| {
+                    Ok(Box::new(Recorder {
+                        name: name.to_string(),
+                        settings: settings.clone(),
+                        events: events.clone(),
+                    }))
+                }),
+            );
+        }
+        registry
+    }
+
+    // the plugins `config` enables, started the way the node does it
+    async fn running(config: &Config, registry: &PluginRegistry) -> PluginRunner {
+        let mut plugins = PluginRunner::new();
+        for plugin in config.enabled_plugins() {
+            let instance = plugin.create(registry).unwrap();
+            plugins.start_plugin(instance).await.unwrap();
+            plugin.configure(&mut plugins).unwrap();
+        }
+        plugins
+    }
+
+    fn parse(toml: &str) -> Config {
+        let config: Config = toml::from_str(toml).unwrap();
+        config.validate().unwrap();
+        config
+    }
+
+    // written next to the other test files, so the extension picks the format
+    fn load(file_name: &str, contents: &str) -> Result<Config, Error> {
+        let path =
+            std::env::temp_dir().join(format!("chainsmoker-{}-{}", std::process::id(), file_name));
+        std::fs::write(&path, contents).unwrap();
+        let config = Config::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        config
+    }
+
+    #[test]
+    fn toml_and_yaml_give_the_same_config() {
+        let toml = load(
+            "same.toml",
+            r#"
+[network]
+cluster = "testnet"
+
+[sockets]
+advertise = "203.0.113.7"
+port_range = [8000, 8100]
+
+[[plugins]]
+name = "Console"
+filter = { shred_type = "data" }
+settings = { every = 100 }
+"#,
+        )
+        .unwrap();
+        let yaml = load(
+            "same.yaml",
+            r#"
+network:
+  cluster: testnet
+sockets:
+  advertise: 203.0.113.7
+  port_range: [8000, 8100]
+plugins:
+  - name: Console
+    filter:
+      shred_type: data
+    settings:
+      every: 100
+"#,
+        )
+        .unwrap();
+        assert_eq!(toml, yaml);
+
+        assert_eq!(toml.network.cluster, Network::Testnet);
+        assert_eq!(toml.sockets.port_range, (8000, 8100));
+        assert_eq!(toml.plugins.len(), 1);
+        let plugin = &toml.plugins[0];
+        assert!(plugin.enabled);
+        assert_eq!(plugin.filter.shred_type, Some(ShredKind::Data));
+        assert_eq!(plugin.settings["every"], 100);
+        // everything not in the file keeps its default
+        assert_eq!(toml.receiver, ReceiverConfig::default());
+        assert_eq!(toml.logging, LogConfig::default());
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        for contents in [
+            "[network]\nclustr = \"testnet\"\n",
+            "[recevier]\nbatch_size = 64\n",
+            "[[plugins]]\nname = \"Console\"\nenable = false\n",
+        ] {
+            assert!(
+                matches!(
+                    load("unknown.toml", contents),
+                    Err(Error::ConfigParse { .. })
+                ),
+                "{}",
+                contents
+            );
+        }
+        assert!(matches!(
+            load("unknown.yml", "sockets:\n  repiar: true\n"),
+            Err(Error::ConfigParse { .. })
+        ));
+    }
+
+    #[test]
+    fn validation_reports_every_problem() {
+        let mut config = Config::default();
+        config.sockets.port_range = (9000, 9000);
+        config.sockets.gossip_port = Some(8001);
+        config.sockets.tvu_port = Some(8001);
+        config.receiver.batch_size = 0;
+        config.logging.filter = "chainsmoker=loud".to_string();
+        let mut batched = PluginConfig::new("Console");
+        batched.batch = Some(BatchConfig {
+            max_shreds: 0,
+            ..BatchConfig::default()
+        });
+        config.plugins = vec![PluginConfig::new("Console"), batched];
+
+        let problems = config.validate().unwrap_err();
+        let problems: Vec<&str> = problems.lines().filter(|line| !line.is_empty()).collect();
+        let expected = [
+            "sockets.port_range",
+            "sockets.tvu_port: 8001 is used twice",
+            "receiver.batch_size",
+            "logging.filter",
+            "plugins[1].name: Console is configured twice",
+            "plugins[1].batch",
+        ];
+        assert_eq!(problems.len(), expected.len(), "{:?}", problems);
+        for (problem, field) in problems.iter().zip(expected) {
+            assert!(
+                problem.starts_with(&format!("  - {}", field)),
+                "{}",
+                problem
+            );
+        }
+
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn load_reports_invalid_files_with_all_problems() {
+        let error = load(
+            "invalid.toml",
+            "[receiver]\nbatch_size = 0\nlog_sample = 0\n",
+        )
+        .unwrap_err();
+        match error {
+            Error::InvalidConfig { problems, .. } => {
+                assert!(problems.contains("receiver.batch_size"));
+                assert!(problems.contains("receiver.log_sample"));
+            }
+            other => panic!("expected InvalidConfig, got {}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn reload_applies_plugin_changes_live() {
+        let events = Events::default();
+        let registry = registry(&events);
+        let current = parse(
+            r#"
+[[plugins]]
+name = "Console"
+
+[[plugins]]
+name = "Counter"
+settings = { every = 100 }
+
+[[plugins]]
+name = "Sink"
+"#,
+        );
+        let new = parse(
+            r#"
+[[plugins]]
+name = "Console"
+filter = { shred_type = "data" }
+batch = { max_shreds = 16 }
+
+[[plugins]]
+name = "Counter"
+settings = { every = 10 }
+
+[[plugins]]
+name = "Sink"
+enabled = false
+
+[[plugins]]
+name = "Extra"
+"#,
+        );
+        let mut plugins = running(&current, &registry).await;
+        events.lock().unwrap().clear();
+
+        let applied = apply_reload(&current, new.clone(), &mut plugins, &registry, None).await;
+        assert_eq!(applied, new);
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                "stop Sink",
+                "stop Counter",
+                "start Counter {\"every\":10}",
+                "start Extra null",
+            ]
+        );
+
+        let status = plugins.status();
+        let names: Vec<&str> = status.iter().map(|status| status.name.as_str()).collect();
+        assert_eq!(names, ["Console", "Counter", "Extra"]);
+        // reconfigured in place, not restarted
+        assert_eq!(status[0].filter.shred_type, Some(ShredKind::Data));
+        assert_eq!(status[0].batch.map(|batch| batch.max_shreds), Some(16));
+    }
+
+    #[tokio::test]
+    async fn reload_keeps_what_needs_a_restart() {
+        let events = Events::default();
+        let registry = registry(&events);
+        let current = parse("[[plugins]]\nname = \"Console\"\n");
+        let new = parse(
+            r#"
+[network]
+cluster = "testnet"
+
+[receiver]
+batch_size = 64
+
+[logging]
+format = "json"
+filter = "debug"
+
+[[routers]]
+name = "missing"
+module = "/nonexistent/router.wasm"
+routes = ["Console"]
+
+[[plugins]]
+name = "Console"
+enabled = false
+"#,
+        );
+        let mut plugins = running(&current, &registry).await;
+
+        let applied = apply_reload(&current, new.clone(), &mut plugins, &registry, None).await;
+        // without a LogHandle the filter can't change either
+        assert_eq!(applied.network, current.network);
+        assert_eq!(applied.receiver, current.receiver);
+        assert_eq!(applied.logging, current.logging);
+        // the module didn't load, the running (no) routers stay
+        assert!(applied.routers.is_empty());
+        assert!(plugins.router_status().is_empty());
+        // plugins change live
+        assert_eq!(applied.plugins, new.plugins);
+        assert_eq!(plugins.plugin_count(), 0);
+        assert_eq!(events.lock().unwrap().last().unwrap(), "stop Console");
+    }
+}
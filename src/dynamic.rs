@@ -0,0 +1,256 @@
+/*
+ ** Dynamic Plugins **
+: Plugins built in their own repos as shared libraries (`.so`, crate-type `cdylib`) and
+: loaded at startup, like Geyser plugins but over a plain C ABI, so the library doesn't
+: have to be built with the same compiler or chainsmoker version. Set `library` on a
+: plugin in the config file; `DynamicPlugin` wraps the loaded library into an
+: `OutputPlugin` and the runner treats it like any other plugin.
+
+*  ** Exported Symbols **
+! +--------------------------------+--------------------------------------------------+
+! | Symbol                         | Signature                                        |
+! +--------------------------------+--------------------------------------------------+
+! | chainsmoker_plugin_abi_version | () -> u32, must return PLUGIN_ABI_VERSION        |
+! | chainsmoker_plugin_create      | (settings: *const c_char, api: *mut PluginApi)   |
+! |                                | -> i32; settings are the plugin's [plugins]      |
+! |                                | settings as NUL-terminated JSON, fills `api` and |
+! |                                | returns 0, anything else is a failure            |
+! +--------------------------------+--------------------------------------------------+
+
+: The version is checked before anything else in the library is called, and once more
+: on the `PluginApi` it fills in. Any change to `PluginApi` or `CShred` bumps
+: `PLUGIN_ABI_VERSION`; `abi_version` stays the first field so it can be read from a
+: table of any version. On a mismatch there the table isn't called into at all, not even
+: `destroy`: the instance is leaked and the library stays loaded.
+
+*  ** PluginApi **
+: Function pointers all taking the `instance` pointer the library put into the struct.
+: Those returning i32 return 0 on success; on anything else `last_error` (if set) is
+: asked for a message. Returned strings stay owned by the library and must stay valid
+: until the next call on the instance.
+
+! +--------------+--------------------------------------------------------------+
+! | Field        | Called                                                       |
+! +--------------+--------------------------------------------------------------+
+! | name         | once after create, must match the plugin's name in config    |
+! | start        | by start_all() or when the plugin is added at runtime        |
+! | handle_shred | per shred, the `CShred` and its payload are only borrowed    |
+! | stop         | on shutdown, removal or before a supervision restart         |
+! | last_error   | optional (may be null), after a call returned non-zero       |
+! | destroy      | once when the plugin is dropped, frees the instance          |
+! +--------------+--------------------------------------------------------------+
+
+: Calls are made from the main loop's task, one at a time, and block it while they
+: run, so libraries should hand slow work off to their own threads.
+
+*  ** Example **
+: examples/dynamic_plugin.rs, built with `cargo build --example dynamic_plugin`:
+:
+: [[plugins]]
+: name = "ShredCounter"
+: library = "target/debug/examples/libdynamic_plugin.so"
+: settings = { every = 1000 }
+*/
+
+use std::{
+    ffi::{CStr, CString, c_char, c_void},
+    mem::MaybeUninit,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use libloading::{Library, Symbol};
+use log::info;
+use solana_ledger::shred::Shred;
+
+use crate::{
+    error::{BoxError, Error},
+    output::{OutputPlugin, PluginSettings},
+};
+
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+pub const ABI_VERSION_SYMBOL: &[u8] = b"chainsmoker_plugin_abi_version\0";
+pub const CREATE_SYMBOL: &[u8] = b"chainsmoker_plugin_create\0";
+
+pub type AbiVersionFn = unsafe extern "C" fn() -> u32;
+pub type CreateFn = unsafe extern "C" fn(settings: *const c_char, api: *mut PluginApi) -> i32;
+
+#[repr(C)]
+pub struct CShred {
+    pub slot: u64,
+    pub index: u32,
+    pub fec_set_index: u32,
+    pub version: u16,
+    pub is_data: bool,
+    pub payload: *const u8,
+    pub payload_len: usize,
+}
+
+#[repr(C)]
+pub struct PluginApi {
+    // first in every version, see above
+    pub abi_version: u32,
+    pub instance: *mut c_void,
+    pub name: unsafe extern "C" fn(instance: *mut c_void) -> *const c_char,
+    pub start: unsafe extern "C" fn(instance: *mut c_void) -> i32,
+    pub handle_shred: unsafe extern "C" fn(instance: *mut c_void, shred: *const CShred) -> i32,
+    pub stop: unsafe extern "C" fn(instance: *mut c_void) -> i32,
+    pub last_error: Option<unsafe extern "C" fn(instance: *mut c_void) -> *const c_char>,
+    pub destroy: unsafe extern "C" fn(instance: *mut c_void),
+}
+
+pub struct DynamicPlugin {
+    api: PluginApi,
+    name: String,
+    path: PathBuf,
+    // dropped after the instance is destroyed, the api points into it
+    _library: Library,
+}
+
+// the instance is only ever called through &mut self, one call at a time
+unsafe impl Send for DynamicPlugin {}
+unsafe impl Sync for DynamicPlugin {}
+
+impl DynamicPlugin {
+    // `name` is the plugin's name in the config, the library has to report the same
+    pub fn load(path: &Path, name: &str, settings: &PluginSettings) -> Result<Self, Error> {
+        let library_error = |source: BoxError| Error::PluginLibrary {
+            path: path.to_path_buf(),
+            source,
+        };
+        let settings = CString::new(settings.to_string()).map_err(|e| library_error(e.into()))?;
+
+        // SAFETY: loading runs the library's initializers, we trust the configured library
+        let library = unsafe { Library::new(path) }.map_err(|e| library_error(e.into()))?;
+        let api = unsafe {
+            let abi_version: Symbol<AbiVersionFn> = library
+                .get(ABI_VERSION_SYMBOL)
+                .map_err(|e| library_error(e.into()))?;
+            let found = abi_version();
+            if found != PLUGIN_ABI_VERSION {
+                return Err(Error::PluginAbi {
+                    path: path.to_path_buf(),
+                    found,
+                    expected: PLUGIN_ABI_VERSION,
+                });
+            }
+
+            let create: Symbol<CreateFn> = library
+                .get(CREATE_SYMBOL)
+                .map_err(|e| library_error(e.into()))?;
+            let mut api = MaybeUninit::<PluginApi>::uninit();
+            let code = create(settings.as_ptr(), api.as_mut_ptr());
+            if code != 0 {
+                return Err(library_error(
+                    format!("chainsmoker_plugin_create returned {}", code).into(),
+                ));
+            }
+            // only the version is read until it matches, the rest may be another ABI's layout
+            let found = std::ptr::addr_of!((*api.as_ptr()).abi_version).read();
+            if found != PLUGIN_ABI_VERSION {
+                // neither destroy the instance nor unload the code it may still run
+                std::mem::forget(library);
+                return Err(Error::PluginAbi {
+                    path: path.to_path_buf(),
+                    found,
+                    expected: PLUGIN_ABI_VERSION,
+                });
+            }
+            api.assume_init()
+        };
+
+        let mut plugin = Self {
+            api,
+            name: String::new(),
+            path: path.to_path_buf(),
+            _library: library,
+        };
+        plugin.name = unsafe { plugin.string((plugin.api.name)(plugin.api.instance)) }
+            .ok_or_else(|| library_error("name() returned null".into()))?;
+        if plugin.name != name {
+            return Err(library_error(
+                format!(
+                    "plugin calls itself {}, configured as {}",
+                    plugin.name, name
+                )
+                .into(),
+            ));
+        }
+
+        info!("Loaded {} plugin from {}", name, path.display());
+        Ok(plugin)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    // SAFETY: `string` is null or a NUL-terminated string owned by the library
+    unsafe fn string(&self, string: *const c_char) -> Option<String> {
+        if string.is_null() {
+            return None;
+        }
+        Some(
+            unsafe { CStr::from_ptr(string) }
+                .to_string_lossy()
+                .into_owned(),
+        )
+    }
+
+    fn check(&self, code: i32, call: &str) -> Result<(), BoxError> {
+        if code == 0 {
+            return Ok(());
+        }
+        let message = self
+            .api
+            .last_error
+            .and_then(|last_error| unsafe { self.string(last_error(self.api.instance)) });
+        Err(match message {
+            Some(message) => message.into(),
+            None => format!("{} returned {}", call, code).into(),
+        })
+    }
+}
+
+impl Drop for DynamicPlugin {
+    fn drop(&mut self) {
+        unsafe { (self.api.destroy)(self.api.instance) };
+    }
+}
+
+#[async_trait::async_trait]
+impl OutputPlugin for DynamicPlugin {
+    async fn start(&mut self) -> Result<(), BoxError> {
+        let code = unsafe { (self.api.start)(self.api.instance) };
+        self.check(code, "start")
+    }
+
+    async fn handle_shred(&mut self, shred: Shred) -> Result<(), BoxError> {
+        self.handle_shared(Arc::new(shred)).await
+    }
+
+    async fn handle_shared(&mut self, shred: Arc<Shred>) -> Result<(), BoxError> {
+        let payload: &[u8] = shred.payload();
+        let c_shred = CShred {
+            slot: shred.slot(),
+            index: shred.index(),
+            fec_set_index: shred.fec_set_index(),
+            version: shred.version(),
+            is_data: shred.is_data(),
+            payload: payload.as_ptr(),
+            payload_len: payload.len(),
+        };
+        let code = unsafe { (self.api.handle_shred)(self.api.instance, &c_shred) };
+        self.check(code, "handle_shred")
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        let code = unsafe { (self.api.stop)(self.api.instance) };
+        self.check(code, "stop")
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
@@ -0,0 +1,219 @@
+/*
+ ** Errors **
+: Library functions return `chainsmoker::Error` instead of `Box<dyn Error>`, so callers can
+: match on what went wrong and errors can be moved across tokio tasks (it is Send + Sync).
+: Each variant keeps the error it was raised from as its `source()`; the Display message
+: only says what chainsmoker was doing. `report()` joins the whole chain into one line.
+
+*  ** Variants **
+! +----------------------+-------------------------------------------------------------+
+! | Variant              | Raised by                                                   |
+! +----------------------+-------------------------------------------------------------+
+! | EntrypointResolution | utils::resolve_addresses, an entrypoint didn't resolve      |
+! | ShredVersion         | utils::get_cluster_shred_version, no entrypoint answered    |
+! | PublicIp             | utils::discover_public_ip, no entrypoint answered           |
+! | SocketBind           | NodeSockets::bind, with the socket (gossip/tvu/repair)      |
+! | Unreachable          | NodeSockets::bind, the ip-echo check of our ports failed    |
+! | GossipSetup          | GossipNode, ContactInfo or gossip state could not be set up |
+! | ShredParse           | utils::parse_shred, the packet is not a valid shred         |
+! | ShredTooShort        | inspect::inspect, no common header to decode                |
+! | Plugin               | PluginRunner/PluginRegistry, a plugin failed to load its    |
+! |                      | settings, start, stop or handle something                   |
+! | UnknownPlugin        | PluginRegistry::create, no factory under that name          |
+! | PluginRunning        | PluginRunner::start_plugin, a plugin of that name runs      |
+! | NoSuchPlugin         | PluginRunner, no running plugin of that name                |
+! | PluginFatal          | PluginRunner::supervise, a plugin with the fatal policy     |
+! |                      | failed too often (see supervision.rs)                       |
+! | PluginLibrary        | DynamicPlugin::load, the library or its symbols didn't load |
+! |                      | or creating the plugin failed                               |
+! | PluginAbi            | DynamicPlugin::load, the library has another ABI version    |
+! | WasmModule           | RouterConfig::load, the module didn't read, compile or      |
+! |                      | instantiate, or doesn't export the router ABI (see wasm.rs) |
+! | ConfigRead/Parse/    | Config::load                                                |
+! | InvalidConfig        |                                                             |
+! | Identity             | ChainSmokerBuilder::build, the keypair file didn't load     |
+! | LeaderSchedule       | ChainSmokerBuilder::build, network.leader_schedule didn't   |
+! |                      | read or parse (see latency.rs)                              |
+! | InvalidOptions       | ChainSmokerBuilder::build, same checks as InvalidConfig     |
+! | AlreadyRun           | ChainSmoker::run called a second time                       |
+! | LogFilter            | logging::init, LogHandle, the filter didn't parse or apply  |
+! | LoggingInit          | logging::init, a logger was already installed               |
+! | Io                   | anything else that failed on a socket or file               |
+! +----------------------+-------------------------------------------------------------+
+
+: Plugins themselves return `BoxError`, the runner wraps it into `Error::Plugin` with the
+: plugin's name.
+*/
+
+use std::{fmt::Write, io, net::IpAddr, path::PathBuf};
+
+// what plugins (and other code we don't own) hand back to us
+pub type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error("Failed to resolve entrypoint '{entrypoint}'")]
+    EntrypointResolution {
+        entrypoint: String,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("None of {entrypoints} entrypoints returned a shred version")]
+    ShredVersion {
+        entrypoints: usize,
+        // the last entrypoint's error, None if they all answered 0
+        #[source]
+        source: Option<BoxError>,
+    },
+
+    #[error("None of {entrypoints} entrypoints returned our public IP")]
+    PublicIp {
+        entrypoints: usize,
+        #[source]
+        source: Option<BoxError>,
+    },
+
+    #[error("Failed to bind the {socket} socket on {bind_address}")]
+    SocketBind {
+        socket: &'static str,
+        bind_address: IpAddr,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error(
+        "Gossip/TVU ports not reachable from the cluster at {advertise_address} (check firewall/NAT forwarding)"
+    )]
+    Unreachable { advertise_address: IpAddr },
+
+    #[error("Gossip setup failed: {context}")]
+    GossipSetup {
+        context: &'static str,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("Failed to parse shred")]
+    ShredParse(#[from] solana_ledger::shred::Error),
+
+    #[error("{len} bytes is shorter than the {min} byte common header")]
+    ShredTooShort { len: usize, min: usize },
+
+    #[error("Plugin {plugin} failed to {action}")]
+    Plugin {
+        plugin: String,
+        action: &'static str,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("No registered plugin named {name} (available: {available})")]
+    UnknownPlugin { name: String, available: String },
+
+    #[error("Plugin {name} already running")]
+    PluginRunning { name: String },
+
+    #[error("No plugin named {name}")]
+    NoSuchPlugin { name: String },
+
+    // reason is the plugin's last error
+    #[error("Plugin {plugin} tripped its fatal supervision policy: {reason}")]
+    PluginFatal { plugin: String, reason: String },
+
+    #[error("Failed to load plugin library {}", path.display())]
+    PluginLibrary {
+        path: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("Plugin library {} has ABI version {found}, expected {expected}", path.display())]
+    PluginAbi {
+        path: PathBuf,
+        found: u32,
+        expected: u32,
+    },
+
+    #[error("Failed to load WASM module {}", path.display())]
+    WasmModule {
+        path: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("Failed to read config {}", path.display())]
+    ConfigRead {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+
+    #[error("Failed to parse config {}", path.display())]
+    ConfigParse {
+        path: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("Failed to load leader schedule {}", path.display())]
+    LeaderSchedule {
+        path: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    // problems is the list from Config::validate()
+    #[error("Invalid config {}:{problems}", path.display())]
+    InvalidConfig { path: PathBuf, problems: String },
+
+    #[error("Failed to read identity {}", path.display())]
+    Identity {
+        path: PathBuf,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("Invalid options:{problems}")]
+    InvalidOptions { problems: String },
+
+    #[error("ChainSmoker node already ran, build a new one")]
+    AlreadyRun,
+
+    #[error("Failed to set log filter '{filter}'")]
+    LogFilter {
+        filter: String,
+        #[source]
+        source: BoxError,
+    },
+
+    #[error("Failed to install the logger")]
+    LoggingInit {
+        #[source]
+        source: BoxError,
+    },
+
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+impl Error {
+    pub fn plugin(plugin: &str, action: &'static str, source: BoxError) -> Self {
+        Self::Plugin {
+            plugin: plugin.to_string(),
+            action,
+            source,
+        }
+    }
+}
+
+// "outer: cause: root cause", for logs and the CLI
+pub fn report(error: &dyn std::error::Error) -> String {
+    let mut report = error.to_string();
+    let mut source = error.source();
+    while let Some(cause) = source {
+        let _ = write!(report, ": {}", cause);
+        source = cause.source();
+    }
+    report
+}
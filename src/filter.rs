@@ -0,0 +1,311 @@
+/*
+ ** Shred Filters **
+: A `ShredFilter` narrows down which shreds a plugin is handed. The runner checks it
+: before cloning the shred for the plugin, so a plugin that only cares about a few slots
+: or only data shreds costs next to nothing for the rest.
+: A plugin declares its own filter through `OutputPlugin::filter()`; the config file can
+: narrow it further per plugin (`[plugins.filter]`), a shred has to match both. The config
+: part can be changed live by reloading the config (SIGHUP, see config.rs).
+: `[receiver.filter]` applies the slot, type and sampling fields to the shred receiver,
+: which checks them on the packet's `ShredHeaderView` and never parses shreds that don't
+: match.
+
+*  ** Fields **
+! +-------------------+-------------------------------------------------------------+
+! | Field             | Matches                                                     |
+! +-------------------+-------------------------------------------------------------+
+! | min_slot          | shreds with slot >= min_slot                                |
+! | max_slot          | shreds with slot <= max_slot                                |
+! | shred_type        | "data" or "code" only                                       |
+! | leaders           | shreds of slots led by one of these identities (base58),    |
+! |                   | looked up with the runner's `LeaderLookup`; without one, or |
+! |                   | for slots it doesn't know, nothing matches                  |
+! | complete_fec_sets | only shreds of FEC sets whose data shreds all arrived; they |
+! |                   | are held back until the set completes (see output.rs)       |
+! | sample_rate       | fraction (0, 1] of shreds, picked by a hash of slot, index  |
+! |                   | and type so every plugin with the same rate sees the same   |
+! |                   | shreds and repeats of a shred are picked alike              |
+! | source            | "turbine" or "repair" only, repaired shreds are the ones    |
+! |                   | the repair client asked for (see repair.rs)                 |
+! +-------------------+-------------------------------------------------------------+
+
+: Unset fields match everything, so the default filter lets every shred through.
+: `leaders`, `complete_fec_sets` and `source` need state the receiver doesn't have and
+: are only valid on plugin filters.
+*/
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+use solana_ledger::shred::Shred;
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+
+use crate::{shred::ShredHeaderView, types::ShredSource};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShredKind {
+    Data,
+    Code,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShredFilter {
+    pub min_slot: Option<Slot>,
+    pub max_slot: Option<Slot>,
+    pub shred_type: Option<ShredKind>,
+    #[serde(with = "pubkey_set", skip_serializing_if = "Option::is_none")]
+    pub leaders: Option<BTreeSet<Pubkey>>,
+    pub complete_fec_sets: bool,
+    pub sample_rate: Option<f64>,
+    pub source: Option<ShredSource>,
+}
+
+impl ShredFilter {
+    // `leader` of the shred's slot, from the runner's LeaderLookup
+    pub fn matches(&self, shred: &Shred, source: ShredSource, leader: Option<&Pubkey>) -> bool {
+        let kind = if shred.is_data() {
+            ShredKind::Data
+        } else {
+            ShredKind::Code
+        };
+        self.matches_fields(shred.slot(), shred.index(), kind)
+            && self.source.is_none_or(|only| only == source)
+            && self
+                .leaders
+                .as_ref()
+                .is_none_or(|leaders| leader.is_some_and(|leader| leaders.contains(leader)))
+    }
+
+    // same check on the raw packet, before the shred is parsed
+    pub fn matches_header(&self, header: &ShredHeaderView) -> bool {
+        self.matches_fields(header.slot(), header.index(), header.shred_type())
+    }
+
+    fn matches_fields(&self, slot: Slot, index: u32, kind: ShredKind) -> bool {
+        !(self.min_slot.is_some_and(|min| slot < min)
+            || self.max_slot.is_some_and(|max| slot > max)
+            || self.shred_type.is_some_and(|shred_type| shred_type != kind)
+            || self
+                .sample_rate
+                .is_some_and(|rate| !sampled(slot, index, kind, rate)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if let (Some(min), Some(max)) = (self.min_slot, self.max_slot)
+            && min > max
+        {
+            return Err(format!("min_slot {} is above max_slot {}", min, max));
+        }
+        if let Some(rate) = self.sample_rate
+            && !(rate > 0.0 && rate <= 1.0)
+        {
+            return Err(format!("sample_rate {} is outside (0, 1]", rate));
+        }
+        if self.leaders.as_ref().is_some_and(BTreeSet::is_empty) {
+            return Err("leaders is empty, nothing would match".to_string());
+        }
+        Ok(())
+    }
+
+    // receiver filters are checked on the packet header, without leader schedule or FEC state
+    pub fn validate_header_only(&self) -> Result<(), String> {
+        self.validate()?;
+        if self.leaders.is_some() {
+            return Err("leaders only applies to plugin filters".to_string());
+        }
+        if self.complete_fec_sets {
+            return Err("complete_fec_sets only applies to plugin filters".to_string());
+        }
+        if self.source.is_some() {
+            return Err("source only applies to plugin filters".to_string());
+        }
+        Ok(())
+    }
+}
+
+// splitmix64 of the shred's position, compared against the rate
+fn sampled(slot: Slot, index: u32, kind: ShredKind, rate: f64) -> bool {
+    // hashes close to u64::MAX round up to rate * u64::MAX as f64 and would be dropped
+    if rate >= 1.0 {
+        return true;
+    }
+    let mut x = slot ^ ((index as u64) << 1 | kind as u64).rotate_left(32);
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^= x >> 31;
+    (x as f64) < rate * u64::MAX as f64
+}
+
+// leaders as base58 strings in config files instead of byte arrays
+mod pubkey_set {
+    use std::{collections::BTreeSet, str::FromStr};
+
+    use serde::{Deserialize, Deserializer, Serializer, de::Error};
+    use solana_sdk::pubkey::Pubkey;
+
+    pub fn serialize<S: Serializer>(
+        leaders: &Option<BTreeSet<Pubkey>>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match leaders {
+            Some(leaders) => serializer.collect_seq(leaders.iter().map(Pubkey::to_string)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<BTreeSet<Pubkey>>, D::Error> {
+        let Some(leaders) = Option::<Vec<String>>::deserialize(deserializer)? else {
+            return Ok(None);
+        };
+        leaders
+            .iter()
+            .map(|leader| {
+                Pubkey::from_str(leader)
+                    .map_err(|e| D::Error::custom(format!("leader '{}': {}", leader, e)))
+            })
+            .collect::<Result<_, _>>()
+            .map(Some)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{shred::test_data_shred, utils::parse_shred};
+
+    fn data_shred(slot: Slot, index: u32) -> Shred {
+        parse_shred(&test_data_shred(slot, index, 0, 0, b"entries")).unwrap()
+    }
+
+    #[test]
+    fn default_filter_matches_everything() {
+        let shred = data_shred(100, 3);
+        let filter = ShredFilter::default();
+        assert!(filter.is_empty());
+        assert!(filter.matches(&shred, ShredSource::Turbine, None));
+        assert!(filter.matches(&shred, ShredSource::Repair, None));
+    }
+
+    #[test]
+    fn slot_range_and_type() {
+        let shred = data_shred(100, 3);
+        let range = |min_slot, max_slot| ShredFilter {
+            min_slot,
+            max_slot,
+            ..ShredFilter::default()
+        };
+        assert!(range(Some(100), Some(100)).matches(&shred, ShredSource::Turbine, None));
+        assert!(!range(Some(101), None).matches(&shred, ShredSource::Turbine, None));
+        assert!(!range(None, Some(99)).matches(&shred, ShredSource::Turbine, None));
+
+        let kind = |shred_type| ShredFilter {
+            shred_type: Some(shred_type),
+            ..ShredFilter::default()
+        };
+        assert!(kind(ShredKind::Data).matches(&shred, ShredSource::Turbine, None));
+        assert!(!kind(ShredKind::Code).matches(&shred, ShredSource::Turbine, None));
+    }
+
+    #[test]
+    fn source_and_leaders() {
+        let shred = data_shred(100, 3);
+        let repair_only = ShredFilter {
+            source: Some(ShredSource::Repair),
+            ..ShredFilter::default()
+        };
+        assert!(repair_only.matches(&shred, ShredSource::Repair, None));
+        assert!(!repair_only.matches(&shred, ShredSource::Turbine, None));
+
+        let leader = Pubkey::new_unique();
+        let leaders = ShredFilter {
+            leaders: Some(BTreeSet::from([leader])),
+            ..ShredFilter::default()
+        };
+        assert!(leaders.matches(&shred, ShredSource::Turbine, Some(&leader)));
+        assert!(!leaders.matches(&shred, ShredSource::Turbine, Some(&Pubkey::new_unique())));
+        // unknown leader of the slot
+        assert!(!leaders.matches(&shred, ShredSource::Turbine, None));
+    }
+
+    #[test]
+    fn matches_header_agrees_with_matches() {
+        let payload = test_data_shred(100, 3, 0, 0, b"entries");
+        let shred = parse_shred(&payload).unwrap();
+        let header = ShredHeaderView::new(&payload).unwrap();
+        for filter in [
+            ShredFilter::default(),
+            ShredFilter {
+                min_slot: Some(101),
+                ..ShredFilter::default()
+            },
+            ShredFilter {
+                shred_type: Some(ShredKind::Code),
+                ..ShredFilter::default()
+            },
+            ShredFilter {
+                sample_rate: Some(0.5),
+                ..ShredFilter::default()
+            },
+        ] {
+            assert_eq!(
+                filter.matches_header(&header),
+                filter.matches(&shred, ShredSource::Turbine, None)
+            );
+        }
+    }
+
+    #[test]
+    fn sampled_is_stable_and_close_to_the_rate() {
+        for index in 0..1000 {
+            assert!(sampled(100, index, ShredKind::Data, 1.0));
+            assert_eq!(
+                sampled(100, index, ShredKind::Data, 0.25),
+                sampled(100, index, ShredKind::Data, 0.25)
+            );
+        }
+
+        let picked = (0..10_000)
+            .filter(|&index| sampled(100, index, ShredKind::Data, 0.25))
+            .count();
+        assert!((2_000..3_000).contains(&picked), "picked {}", picked);
+
+        // a lower rate picks a subset of a higher one
+        for index in 0..1000 {
+            if sampled(100, index, ShredKind::Code, 0.1) {
+                assert!(sampled(100, index, ShredKind::Code, 0.5));
+            }
+        }
+    }
+
+    #[test]
+    fn validate_rejects_bad_fields() {
+        let inverted = ShredFilter {
+            min_slot: Some(2),
+            max_slot: Some(1),
+            ..ShredFilter::default()
+        };
+        assert!(inverted.validate().is_err());
+        for rate in [0.0, -0.5, 1.5, f64::NAN] {
+            let sampling = ShredFilter {
+                sample_rate: Some(rate),
+                ..ShredFilter::default()
+            };
+            assert!(sampling.validate().is_err(), "sample_rate {}", rate);
+        }
+        let repair_only = ShredFilter {
+            source: Some(ShredSource::Repair),
+            ..ShredFilter::default()
+        };
+        assert!(repair_only.validate().is_ok());
+        assert!(repair_only.validate_header_only().is_err());
+    }
+}
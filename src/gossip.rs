@@ -1,6 +1,9 @@
 use std::{
-    net::{IpAddr, UdpSocket},
-    sync::{Arc, atomic::AtomicBool},
+    net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
     thread,
     time::Duration,
 };
@@ -12,10 +15,19 @@ use solana_gossip::{
 };
 use solana_sdk::signer::{Signer, keypair::Keypair};
 
-use log::{debug, info};
+use log::{debug, info, warn};
 use solana_streamer::socket::SocketAddrSpace;
 
-use crate::{types::Network, utils::*};
+use crate::{
+    error::{Error, report},
+    gossip_events::{GossipEventConfig, GossipEventTap},
+    sockets::advertised_addr,
+    types::PeerInfo,
+    utils::*,
+};
+
+// advertised when no entrypoint tells us the cluster's shred version
+const DEFAULT_SHRED_VERSION: u16 = 9065;
 
 pub struct GossipNode {
     pub cluster_info: Arc<ClusterInfo>,
@@ -28,37 +40,113 @@ impl GossipNode {
         identity_keypair: Arc<Keypair>,
         gossip_socket: UdpSocket,
         tvu_socket: &UdpSocket,
+        repair_socket: Option<&UdpSocket>,
+        advertise_address: IpAddr,
         bind_address: IpAddr,
-        network: Network,
-    ) -> Result<Self, Box<dyn std::error::Error>> {
+        entrypoints: &[SocketAddr],
+    ) -> Result<Self, Error> {
         let pubkey = identity_keypair.pubkey();
-        let gossip_addr = gossip_socket.local_addr()?;
-        let tvu_addr = tvu_socket.local_addr()?;
+        let gossip_addr = advertised_addr(advertise_address, &gossip_socket);
+        let tvu_addr = advertised_addr(advertise_address, tvu_socket);
 
         debug!("Node identity: {}", pubkey);
-        debug!("Gossip address: {}", gossip_addr);
-        debug!("TVU address: {}", tvu_addr);
+        debug!(
+            "Gossip address: {} (bound {})",
+            gossip_addr,
+            gossip_socket.local_addr()?
+        );
+        debug!(
+            "TVU address: {} (bound {})",
+            tvu_addr,
+            tvu_socket.local_addr()?
+        );
 
-        let entrypoints = resolve_entrypoints(network)?;
-        let shred_version = get_cluster_shred_version(&entrypoints, bind_address)?;
+        let shred_version = cluster_shred_version(entrypoints, bind_address);
 
         let mut contact_info = ClusterInfo::gossip_contact_info(pubkey, gossip_addr, shred_version);
 
         // Set TVU address
-        contact_info.set_tvu(Protocol::UDP, tvu_addr)?;
+        contact_info
+            .set_tvu(Protocol::UDP, tvu_addr)
+            .map_err(|e| Error::GossipSetup {
+                context: "TVU address",
+                source: e.into(),
+            })?;
+
+        // Repair responses (and pings from serve-repair peers) come back to this socket
+        if let Some(repair_socket) = repair_socket {
+            let repair_addr = advertised_addr(advertise_address, repair_socket);
+            debug!("Repair address: {}", repair_addr);
+            contact_info
+                .set_serve_repair(Protocol::UDP, repair_addr)
+                .map_err(|e| Error::GossipSetup {
+                    context: "repair address",
+                    source: e.into(),
+                })?;
+        }
+
+        Self::join(identity_keypair, contact_info, gossip_socket, entrypoints)
+    }
+
+    // Joins gossip with a gossip-only ContactInfo: no TVU is advertised, so no shreds are
+    // sent to us. In spy mode the advertised gossip address is unroutable (localhost), so
+    // peers can't push to us either and we only learn the cluster through pull responses.
+    pub fn new_observer(
+        identity_keypair: Arc<Keypair>,
+        gossip_socket: UdpSocket,
+        advertise_address: IpAddr,
+        bind_address: IpAddr,
+        entrypoints: &[SocketAddr],
+        spy: bool,
+    ) -> Result<Self, Error> {
+        let pubkey = identity_keypair.pubkey();
+        let gossip_addr = if spy {
+            SocketAddr::new(
+                IpAddr::V4(Ipv4Addr::LOCALHOST),
+                gossip_socket.local_addr()?.port(),
+            )
+        } else {
+            advertised_addr(advertise_address, &gossip_socket)
+        };
+
+        debug!("Node identity: {}", pubkey);
+        debug!(
+            "Gossip address: {} (bound {}, {})",
+            gossip_addr,
+            gossip_socket.local_addr()?,
+            if spy { "spy" } else { "observer" }
+        );
+
+        let shred_version = cluster_shred_version(entrypoints, bind_address);
+
+        let contact_info = ClusterInfo::gossip_contact_info(pubkey, gossip_addr, shred_version);
+
+        Self::join(identity_keypair, contact_info, gossip_socket, entrypoints)
+    }
+
+    fn join(
+        identity_keypair: Arc<Keypair>,
+        contact_info: ContactInfo,
+        gossip_socket: UdpSocket,
+        entrypoints: &[SocketAddr],
+    ) -> Result<Self, Error> {
+        let pubkey = identity_keypair.pubkey();
 
         let mut cluster_info =
             ClusterInfo::new(contact_info, identity_keypair, SocketAddrSpace::Unspecified);
 
         let mut entrypoint_contacts = Vec::new();
         for addr in entrypoints {
-            let contact = ContactInfo::new_gossip_entry_point(&addr);
+            let contact = ContactInfo::new_gossip_entry_point(addr);
             entrypoint_contacts.push(contact);
         }
         cluster_info.set_entrypoints(entrypoint_contacts);
 
         let temp_dir = std::env::temp_dir().join(format!("solana-gossip-{}", pubkey));
-        std::fs::create_dir_all(&temp_dir)?;
+        std::fs::create_dir_all(&temp_dir).map_err(|e| Error::GossipSetup {
+            context: "contact info directory",
+            source: e.into(),
+        })?;
         cluster_info.restore_contact_info(&temp_dir, 0);
 
         let cluster_info = Arc::new(cluster_info);
@@ -82,12 +170,13 @@ impl GossipNode {
     }
 
     pub fn start_discovery(&self) {
+        let _span = tracing::info_span!("gossip_discovery").entered();
         info!("Starting gossip discovery...");
 
         let cluster_info = self.cluster_info.clone();
 
         let mut iteration = 0;
-        loop {
+        while !self.exit.load(Ordering::Relaxed) {
             thread::sleep(Duration::from_secs(1));
 
             let peers = cluster_info.all_peers();
@@ -114,4 +203,53 @@ impl GossipNode {
             }
         }
     }
+
+    pub fn peer_count(&self) -> usize {
+        self.cluster_info.all_peers().len()
+    }
+
+    // snapshot of every node we know about through gossip
+    pub fn peer_directory(&self) -> Vec<PeerInfo> {
+        peer_directory(&self.cluster_info)
+    }
+
+    // votes, EpochSlots, LowestSlot and DuplicateShred values as a typed event stream
+    pub fn event_tap(&self, config: GossipEventConfig) -> GossipEventTap {
+        GossipEventTap::new(self.cluster_info.clone(), config, self.exit.clone())
+    }
+
+    pub fn shutdown(self) -> thread::Result<()> {
+        info!("Stopping gossip service...");
+        self.exit.store(true, Ordering::Relaxed);
+        self.gossip_service.join()
+    }
+}
+
+fn cluster_shred_version(entrypoints: &[SocketAddr], bind_address: IpAddr) -> u16 {
+    get_cluster_shred_version(entrypoints, bind_address).unwrap_or_else(|e| {
+        warn!(
+            "{}, using default shred version {}",
+            report(&e),
+            DEFAULT_SHRED_VERSION
+        );
+        DEFAULT_SHRED_VERSION
+    })
+}
+
+pub fn peer_directory(cluster_info: &ClusterInfo) -> Vec<PeerInfo> {
+    cluster_info
+        .all_peers()
+        .into_iter()
+        .map(|(contact_info, last_updated)| PeerInfo {
+            pubkey: *contact_info.pubkey(),
+            gossip: contact_info.gossip(),
+            tvu: contact_info.tvu(Protocol::UDP),
+            serve_repair: contact_info.serve_repair(Protocol::UDP),
+            shred_version: contact_info.shred_version(),
+            version: cluster_info
+                .get_node_version(contact_info.pubkey())
+                .map(|version| version.to_string()),
+            last_updated,
+        })
+        .collect()
 }
@@ -0,0 +1,411 @@
+/*
+ ** Gossip Event Tap **
+: GossipService already pulls votes, EpochSlots, LowestSlot and DuplicateShred values into
+: the CRDS table. The tap polls the table with cursors (only new entries are returned) and
+: turns those values into typed `GossipEvent`s on a channel, the same way ShredReceiver
+: turns TVU packets into shreds.
+
+*  ** Events **
+! +----------------+---------------------------------------------------------------+
+! | Event          | Source                                                        |
+! +----------------+---------------------------------------------------------------+
+! | Vote           | CrdsData::Vote, the vote transaction and the voting node      |
+! | EpochSlots     | CrdsData::EpochSlots, slots a node has completed (same shred  |
+! |                | version only)                                                 |
+! | LowestSlot     | CrdsData::LowestSlot, emitted when a node's value changes     |
+! | DuplicateShred | DuplicateShred chunks, emitted once all chunks of a proof are |
+! |                | received and both shreds decode                               |
+! +----------------+---------------------------------------------------------------+
+
+*  ** Usage **
+: let mut tap = gossip_node.event_tap(GossipEventConfig::default());
+: let events = tap.take_receiver();
+: let handles = tap.start();
+:
+: Events are forwarded to plugins with `PluginRunner::handle_gossip_event()`.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+        mpsc,
+    },
+    thread,
+    time::Duration,
+};
+
+use log::{debug, info};
+use serde::Deserialize;
+use solana_gossip::{
+    cluster_info::ClusterInfo,
+    crds::Cursor,
+    crds_data::LowestSlot,
+    duplicate_shred::DuplicateShred,
+    duplicate_shred_listener::{DuplicateShredHandlerTrait, DuplicateShredListener},
+};
+use solana_ledger::{blockstore_meta::DuplicateSlotProof, shred::Shred};
+use solana_sdk::{clock::Slot, pubkey::Pubkey, transaction::Transaction};
+
+// incomplete proofs older than this many slots behind the newest chunk are dropped
+const MAX_PENDING_PROOFS: usize = 64;
+const PENDING_PROOF_SLOT_WINDOW: Slot = 512;
+
+#[derive(Debug, Clone)]
+pub enum GossipEvent {
+    Vote {
+        from: Pubkey,
+        transaction: Transaction,
+    },
+    EpochSlots {
+        from: Pubkey,
+        slots: Vec<Slot>,
+        wallclock: u64,
+    },
+    LowestSlot {
+        from: Pubkey,
+        lowest: Slot,
+    },
+    DuplicateShred {
+        from: Pubkey,
+        slot: Slot,
+        shred1: Box<Shred>,
+        shred2: Box<Shred>,
+    },
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct GossipEventConfig {
+    pub votes: bool,
+    pub epoch_slots: bool,
+    pub lowest_slot: bool,
+    pub duplicate_shreds: bool,
+    pub poll_interval: Duration,
+}
+
+impl Default for GossipEventConfig {
+    fn default() -> Self {
+        Self {
+            votes: true,
+            epoch_slots: true,
+            lowest_slot: true,
+            duplicate_shreds: true,
+            poll_interval: Duration::from_millis(200),
+        }
+    }
+}
+
+pub struct GossipEventTap {
+    cluster_info: Arc<ClusterInfo>,
+    config: GossipEventConfig,
+    sender: mpsc::Sender<GossipEvent>,
+    receiver: Option<mpsc::Receiver<GossipEvent>>,
+    exit: Arc<AtomicBool>,
+}
+
+impl GossipEventTap {
+    pub fn new(
+        cluster_info: Arc<ClusterInfo>,
+        config: GossipEventConfig,
+        exit: Arc<AtomicBool>,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        Self {
+            cluster_info,
+            config,
+            sender,
+            receiver: Some(receiver),
+            exit,
+        }
+    }
+
+    pub fn take_receiver(&mut self) -> mpsc::Receiver<GossipEvent> {
+        self.receiver.take().expect("Receiver already taken")
+    }
+
+    pub fn start(&mut self) -> Vec<thread::JoinHandle<()>> {
+        let mut handles = Vec::new();
+
+        let cluster_info = self.cluster_info.clone();
+        let config = self.config;
+        let sender = self.sender.clone();
+        let exit = self.exit.clone();
+        handles.push(thread::spawn(move || {
+            info!("Starting gossip event tap...");
+            Self::poll_loop(&cluster_info, config, &sender, &exit);
+        }));
+
+        if self.config.duplicate_shreds {
+            let handler = DuplicateProofAssembler {
+                sender: self.sender.clone(),
+                pending: HashMap::new(),
+            };
+            let listener =
+                DuplicateShredListener::new(self.exit.clone(), self.cluster_info.clone(), handler);
+            handles.push(thread::spawn(move || {
+                let _ = listener.join();
+            }));
+        }
+
+        handles
+    }
+
+    fn poll_loop(
+        cluster_info: &ClusterInfo,
+        config: GossipEventConfig,
+        sender: &mpsc::Sender<GossipEvent>,
+        exit: &AtomicBool,
+    ) {
+        let mut vote_cursor = Cursor::default();
+        let mut epoch_slots_cursor = Cursor::default();
+        let mut lowest_slots: HashMap<Pubkey, Slot> = HashMap::new();
+
+        while !exit.load(Ordering::Relaxed) {
+            let mut events = Vec::new();
+
+            if config.votes {
+                let (labels, votes) = cluster_info.get_votes_with_labels(&mut vote_cursor);
+                events.extend(labels.into_iter().zip(votes).map(|(label, transaction)| {
+                    GossipEvent::Vote {
+                        from: label.pubkey(),
+                        transaction,
+                    }
+                }));
+            }
+
+            if config.epoch_slots {
+                events.extend(
+                    cluster_info
+                        .get_epoch_slots(&mut epoch_slots_cursor)
+                        .into_iter()
+                        .map(|epoch_slots| GossipEvent::EpochSlots {
+                            from: epoch_slots.from,
+                            slots: epoch_slots.to_slots(0).collect(),
+                            wallclock: epoch_slots.wallclock,
+                        }),
+                );
+            }
+
+            if config.lowest_slot {
+                let peers = cluster_info.all_peers();
+                let crds = cluster_info.gossip.crds.read().unwrap();
+                for (peer, _) in &peers {
+                    let Some(lowest) = crds.get::<&LowestSlot>(*peer.pubkey()).map(|v| v.lowest)
+                    else {
+                        continue;
+                    };
+                    if lowest_slots.insert(*peer.pubkey(), lowest) != Some(lowest) {
+                        events.push(GossipEvent::LowestSlot {
+                            from: *peer.pubkey(),
+                            lowest,
+                        });
+                    }
+                }
+            }
+
+            for event in events {
+                if sender.send(event).is_err() {
+                    info!("Gossip event channel closed, stopping tap");
+                    return;
+                }
+            }
+
+            thread::sleep(config.poll_interval);
+        }
+    }
+}
+
+// DuplicateShred fields are private to solana-gossip, so chunks are re-decoded from
+// their bincode encoding (field order matches the wire format)
+#[derive(Deserialize)]
+struct DuplicateShredChunk {
+    from: Pubkey,
+    _wallclock: u64,
+    slot: Slot,
+    _unused: u32,
+    _shred_type: u8,
+    num_chunks: u8,
+    chunk_index: u8,
+    chunk: Vec<u8>,
+}
+
+struct DuplicateProofAssembler {
+    sender: mpsc::Sender<GossipEvent>,
+    pending: HashMap<(Pubkey, Slot), HashMap<u8, Vec<u8>>>,
+}
+
+impl DuplicateProofAssembler {
+    fn assemble(from: Pubkey, slot: Slot, chunks: HashMap<u8, Vec<u8>>) -> Option<GossipEvent> {
+        let mut indices: Vec<_> = chunks.keys().copied().collect();
+        indices.sort_unstable();
+        let data: Vec<u8> = indices
+            .iter()
+            .flat_map(|index| chunks[index].iter().copied())
+            .collect();
+
+        let proof: DuplicateSlotProof = bincode::deserialize(&data).ok()?;
+        let shred1 = Shred::new_from_serialized_shred(proof.shred1).ok()?;
+        let shred2 = Shred::new_from_serialized_shred(proof.shred2).ok()?;
+
+        Some(GossipEvent::DuplicateShred {
+            from,
+            slot,
+            shred1: Box::new(shred1),
+            shred2: Box::new(shred2),
+        })
+    }
+}
+
+impl DuplicateShredHandlerTrait for DuplicateProofAssembler {
+    fn handle(&mut self, data: DuplicateShred) {
+        let Ok(chunk) = bincode::serialize(&data)
+            .and_then(|bytes| bincode::deserialize::<DuplicateShredChunk>(&bytes))
+        else {
+            return;
+        };
+
+        let key = (chunk.from, chunk.slot);
+        if self.pending.len() >= MAX_PENDING_PROOFS {
+            self.pending
+                .retain(|(_, slot), _| slot + PENDING_PROOF_SLOT_WINDOW >= chunk.slot);
+        }
+        let chunks = self.pending.entry(key).or_default();
+        chunks.insert(chunk.chunk_index, chunk.chunk);
+        if chunks.len() < chunk.num_chunks as usize {
+            return;
+        }
+
+        let chunks = self.pending.remove(&key).unwrap_or_default();
+        match Self::assemble(chunk.from, chunk.slot, chunks) {
+            Some(event) => {
+                let _ = self.sender.send(event);
+            }
+            None => debug!(
+                "Dropping undecodable duplicate shred proof for slot {} from {}",
+                chunk.slot, chunk.from
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use solana_ledger::shred::Payload;
+
+    use super::*;
+    use crate::shred::test_data_shred;
+
+    // a DuplicateShred value as gossip delivers it, built through its wire format
+    fn chunk(
+        from: Pubkey,
+        slot: Slot,
+        num_chunks: u8,
+        chunk_index: u8,
+        chunk: &[u8],
+    ) -> DuplicateShred {
+        let wallclock = 1_700_000_000_000u64;
+        let bytes = bincode::serialize(&(
+            from,
+            wallclock,
+            slot,
+            0u32,
+            0u8,
+            num_chunks,
+            chunk_index,
+            chunk.to_vec(),
+        ))
+        .unwrap();
+        bincode::deserialize(&bytes).unwrap()
+    }
+
+    // two different shreds for the same slot and index
+    fn proof(slot: Slot) -> Vec<u8> {
+        let proof = DuplicateSlotProof {
+            shred1: Payload::from(test_data_shred(slot, 3, 0, 0, b"one")),
+            shred2: Payload::from(test_data_shred(slot, 3, 0, 0, b"two")),
+        };
+        bincode::serialize(&proof).unwrap()
+    }
+
+    fn assembler() -> (DuplicateProofAssembler, mpsc::Receiver<GossipEvent>) {
+        let (sender, receiver) = mpsc::channel();
+        let assembler = DuplicateProofAssembler {
+            sender,
+            pending: HashMap::new(),
+        };
+        (assembler, receiver)
+    }
+
+    #[test]
+    fn test_duplicate_proof_from_chunks_in_any_order() {
+        let (mut assembler, events) = assembler();
+        let from = Pubkey::new_unique();
+        let proof = proof(100);
+        let chunks: Vec<&[u8]> = proof.chunks(proof.len().div_ceil(3)).collect();
+        assert_eq!(chunks.len(), 3);
+
+        for index in [2, 0] {
+            assembler.handle(chunk(from, 100, 3, index, chunks[index as usize]));
+        }
+        assert!(events.try_recv().is_err());
+        assembler.handle(chunk(from, 100, 3, 1, chunks[1]));
+
+        let GossipEvent::DuplicateShred {
+            from: sender,
+            slot,
+            shred1,
+            shred2,
+        } = events.try_recv().unwrap()
+        else {
+            panic!("expected a duplicate shred event");
+        };
+        assert_eq!((sender, slot), (from, 100));
+        assert_eq!((shred1.slot(), shred1.index()), (100, 3));
+        assert_eq!((shred2.slot(), shred2.index()), (100, 3));
+        assert_ne!(shred1, shred2);
+        assert!(assembler.pending.is_empty());
+    }
+
+    #[test]
+    fn test_chunks_of_different_proofs_dont_mix() {
+        let (mut assembler, events) = assembler();
+        let (first, second) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let proof = proof(100);
+        let (head, tail) = proof.split_at(proof.len() / 2);
+
+        assembler.handle(chunk(first, 100, 2, 0, head));
+        assembler.handle(chunk(second, 100, 2, 1, tail));
+        assembler.handle(chunk(first, 101, 2, 1, tail));
+        assert!(events.try_recv().is_err());
+        assert_eq!(assembler.pending.len(), 3);
+
+        assembler.handle(chunk(first, 100, 2, 1, tail));
+        assert!(matches!(
+            events.try_recv(),
+            Ok(GossipEvent::DuplicateShred { from, slot: 100, .. }) if from == first
+        ));
+    }
+
+    #[test]
+    fn test_undecodable_proof_is_dropped() {
+        let (mut assembler, events) = assembler();
+        assembler.handle(chunk(Pubkey::new_unique(), 100, 1, 0, b"not a proof"));
+        assert!(events.try_recv().is_err());
+        assert!(assembler.pending.is_empty());
+    }
+
+    #[test]
+    fn test_old_incomplete_proofs_are_dropped_when_full() {
+        let (mut assembler, _events) = assembler();
+        for slot in 0..MAX_PENDING_PROOFS as Slot {
+            assembler.handle(chunk(Pubkey::new_unique(), slot, 2, 0, b"half"));
+        }
+        assert_eq!(assembler.pending.len(), MAX_PENDING_PROOFS);
+
+        // every pending slot is more than PENDING_PROOF_SLOT_WINDOW behind
+        let slot = MAX_PENDING_PROOFS as Slot + PENDING_PROOF_SLOT_WINDOW;
+        assembler.handle(chunk(Pubkey::new_unique(), slot, 2, 0, b"half"));
+        assert_eq!(assembler.pending.len(), 1);
+    }
+}
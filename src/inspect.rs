@@ -0,0 +1,656 @@
+/*
+ ** Shred Inspector **
+: Decodes raw shred bytes field by field using the layout documented in shred.rs, for
+: debugging packets that don't parse or look wrong. Unlike `parse_shred()` it doesn't
+: stop at the first problem: whatever can be read is decoded and anything inconsistent
+: (payload size, size field, erasure index, proof offsets) is listed under `problems`.
+: Only packets shorter than the common header are rejected.
+: Used by `chainsmoker inspect`, printed through `Display` (human) or serde (JSON).
+
+*  ** Shred Variants **
+! +--------+-------+--------+---------+----------+
+! | Byte   | Type  | Auth   | Chained | Resigned |
+! +--------+-------+--------+---------+----------+
+! | 0x5a   | Code  | Legacy | -       | -        |
+! | 0xa5   | Data  | Legacy | -       | -        |
+! | 0x4?   | Code  | Merkle | no      | no       |
+! | 0x6?   | Code  | Merkle | yes     | no       |
+! | 0x7?   | Code  | Merkle | yes     | yes      |
+! | 0x8?   | Data  | Merkle | no      | no       |
+! | 0x9?   | Data  | Merkle | yes     | no       |
+! | 0xb?   | Data  | Merkle | yes     | yes      |
+! +--------+-------+--------+---------+----------+
+: ? = number of Merkle proof entries.
+
+*  ** Merkle Payload Layout **
+: Data shreds are 1203 bytes, code shreds 1228. The tail of the payload is, in order:
+
+! +---------------------+--------------------+-----------------------------------------+
+! | Field               | Size               | Notes                                   |
+! +---------------------+--------------------+-----------------------------------------+
+! | headers             | 0x58 data/0x59 code| common + data/code header               |
+! | data / erasure shard| rest               | data shreds use `size` of it            |
+! | chained merkle root | 32B if chained     | root of the previous FEC set            |
+! | merkle proof        | 20B per entry      | sibling hashes, leaf to root            |
+! | retransmitter sig   | 64B if resigned    | signs the root, set by the last hop     |
+! +---------------------+--------------------+-----------------------------------------+
+
+*  ** Merkle Root **
+: leaf = sha256("\x00SOLANA_MERKLE_SHREDS_LEAF" || payload[64..proof_offset])
+: node = sha256("\x01SOLANA_MERKLE_SHREDS_NODE" || left[..20] || right[..20])
+: The leaf is joined with each proof entry in turn; the side is picked by the shred's
+: index in the erasure batch (index - fec_set_index for data, num_data + position for
+: code). The leader's signature (first 64 bytes) signs the resulting root.
+*/
+
+use std::fmt;
+
+use serde::{Serialize, Serializer};
+use solana_sdk::{
+    clock::Slot,
+    hash::{Hash, hashv},
+    signature::Signature,
+};
+
+use crate::{error::Error, filter::ShredKind, shred::SIZE_OF_COMMON_HEADER};
+
+const SIZE_OF_SIGNATURE: usize = 64;
+const SIZE_OF_DATA_HEADERS: usize = 0x58;
+const SIZE_OF_CODE_HEADERS: usize = 0x59;
+const SIZE_OF_MERKLE_ROOT: usize = 32;
+const SIZE_OF_PROOF_ENTRY: usize = 20;
+const MERKLE_DATA_PAYLOAD: usize = 1203;
+const CODE_PAYLOAD: usize = 1228;
+const LEGACY_PAYLOAD: usize = 1228;
+
+const MERKLE_HASH_PREFIX_LEAF: &[u8] = b"\x00SOLANA_MERKLE_SHREDS_LEAF";
+const MERKLE_HASH_PREFIX_NODE: &[u8] = b"\x01SOLANA_MERKLE_SHREDS_NODE";
+
+const DATA_COMPLETE_SHRED: u8 = 0b0100_0000;
+const LAST_SHRED_IN_SLOT: u8 = 0b1100_0000;
+const SHRED_TICK_REFERENCE_MASK: u8 = 0b0011_1111;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct ShredVariant {
+    pub byte: u8,
+    // None for bytes that aren't a known variant
+    pub shred_type: Option<ShredKind>,
+    pub merkle: bool,
+    pub proof_size: u8,
+    pub chained: bool,
+    pub resigned: bool,
+}
+
+impl ShredVariant {
+    pub fn decode(byte: u8) -> Self {
+        let legacy = |shred_type| Self {
+            byte,
+            shred_type: Some(shred_type),
+            merkle: false,
+            proof_size: 0,
+            chained: false,
+            resigned: false,
+        };
+        let merkle = |shred_type, chained, resigned| Self {
+            byte,
+            shred_type: Some(shred_type),
+            merkle: true,
+            proof_size: byte & 0x0f,
+            chained,
+            resigned,
+        };
+
+        match byte {
+            0x5a => legacy(ShredKind::Code),
+            0xa5 => legacy(ShredKind::Data),
+            _ => match byte & 0xf0 {
+                0x40 => merkle(ShredKind::Code, false, false),
+                0x60 => merkle(ShredKind::Code, true, false),
+                0x70 => merkle(ShredKind::Code, true, true),
+                0x80 => merkle(ShredKind::Data, false, false),
+                0x90 => merkle(ShredKind::Data, true, false),
+                0xb0 => merkle(ShredKind::Data, true, true),
+                _ => Self {
+                    byte,
+                    shred_type: None,
+                    merkle: false,
+                    proof_size: 0,
+                    chained: false,
+                    resigned: false,
+                },
+            },
+        }
+    }
+
+    // full payload size on the wire, None for unknown variants
+    pub fn payload_size(&self) -> Option<usize> {
+        match (self.shred_type?, self.merkle) {
+            (ShredKind::Data, true) => Some(MERKLE_DATA_PAYLOAD),
+            (ShredKind::Code, true) => Some(CODE_PAYLOAD),
+            (_, false) => Some(LEGACY_PAYLOAD),
+        }
+    }
+
+    fn headers_size(&self) -> usize {
+        match self.shred_type {
+            Some(ShredKind::Code) => SIZE_OF_CODE_HEADERS,
+            _ => SIZE_OF_DATA_HEADERS,
+        }
+    }
+}
+
+impl fmt::Display for ShredVariant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let Some(shred_type) = self.shred_type else {
+            return write!(f, "0x{:02x} unknown", self.byte);
+        };
+        let shred_type = match shred_type {
+            ShredKind::Data => "data",
+            ShredKind::Code => "code",
+        };
+        if !self.merkle {
+            return write!(f, "0x{:02x} legacy {}", self.byte, shred_type);
+        }
+        write!(
+            f,
+            "0x{:02x} merkle {}, {} proof entries",
+            self.byte, shred_type, self.proof_size
+        )?;
+        if self.chained {
+            write!(f, ", chained")?;
+        }
+        if self.resigned {
+            write!(f, ", resigned")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DataHeader {
+    pub parent_offset: u16,
+    pub parent_slot: Option<Slot>,
+    pub flags: u8,
+    pub reference_tick: u8,
+    pub data_complete: bool,
+    pub last_in_slot: bool,
+    // headers + data, as written by the leader
+    pub size: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CodeHeader {
+    pub num_data_shreds: u16,
+    pub num_coding_shreds: u16,
+    pub position: u16,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct MerkleInfo {
+    // index of the shred in its erasure batch, the leaf it is in the tree
+    pub erasure_index: Option<usize>,
+    pub chained_merkle_root: Option<String>,
+    // proof entries as hex, leaf to root
+    pub proof: Vec<String>,
+    pub root: Option<String>,
+    pub retransmitter_signature: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ShredInspection {
+    pub len: usize,
+    pub signature: String,
+    pub variant: ShredVariant,
+    pub slot: Slot,
+    pub index: u32,
+    pub shred_version: u16,
+    pub fec_set_index: u32,
+    pub data_header: Option<DataHeader>,
+    pub code_header: Option<CodeHeader>,
+    pub merkle: Option<MerkleInfo>,
+    pub problems: Vec<String>,
+    #[serde(serialize_with = "serialize_hex")]
+    pub payload: Vec<u8>,
+}
+
+pub fn inspect(payload: &[u8]) -> Result<ShredInspection, Error> {
+    if payload.len() < SIZE_OF_COMMON_HEADER {
+        return Err(Error::ShredTooShort {
+            len: payload.len(),
+            min: SIZE_OF_COMMON_HEADER,
+        });
+    }
+
+    let mut problems = Vec::new();
+    let variant = ShredVariant::decode(payload[0x40]);
+    let slot = u64::from_le_bytes(array(payload, 0x41).unwrap());
+    let index = u32::from_le_bytes(array(payload, 0x49).unwrap());
+    let shred_version = u16::from_le_bytes(array(payload, 0x4d).unwrap());
+    let fec_set_index = u32::from_le_bytes(array(payload, 0x4f).unwrap());
+
+    match variant.payload_size() {
+        None => problems.push(format!("unknown shred variant 0x{:02x}", variant.byte)),
+        Some(expected) if variant.merkle && payload.len() != expected => problems.push(format!(
+            "payload is {} bytes, merkle {} shreds are {}",
+            payload.len(),
+            if variant.shred_type == Some(ShredKind::Data) {
+                "data"
+            } else {
+                "code"
+            },
+            expected
+        )),
+        Some(expected) if payload.len() > expected => problems.push(format!(
+            "payload is {} bytes, legacy shreds are at most {}",
+            payload.len(),
+            expected
+        )),
+        Some(_) => {}
+    }
+
+    let mut data_header = None;
+    let mut code_header = None;
+    let mut erasure_index = None;
+    match variant.shred_type {
+        Some(ShredKind::Data) => {
+            if let (Some(parent_offset), Some(flags), Some(size)) = (
+                array(payload, 0x53).map(u16::from_le_bytes),
+                payload.get(0x55).copied(),
+                array(payload, 0x56).map(u16::from_le_bytes),
+            ) {
+                let parent_slot = slot.checked_sub(u64::from(parent_offset));
+                if parent_slot.is_none() || (parent_offset == 0 && slot != 0) {
+                    problems.push(format!(
+                        "parent_offset {} is invalid for slot {}",
+                        parent_offset, slot
+                    ));
+                }
+                if flags & LAST_SHRED_IN_SLOT == 0b1000_0000 {
+                    problems.push("last_in_slot set without data_complete".to_string());
+                }
+                if usize::from(size) < SIZE_OF_DATA_HEADERS || usize::from(size) > payload.len() {
+                    problems.push(format!(
+                        "size field {} is outside the payload ({} bytes)",
+                        size,
+                        payload.len()
+                    ));
+                }
+                data_header = Some(DataHeader {
+                    parent_offset,
+                    parent_slot,
+                    flags,
+                    reference_tick: flags & SHRED_TICK_REFERENCE_MASK,
+                    data_complete: flags & DATA_COMPLETE_SHRED != 0,
+                    last_in_slot: flags & LAST_SHRED_IN_SLOT == LAST_SHRED_IN_SLOT,
+                    size,
+                });
+            } else {
+                problems.push("payload ends inside the data header".to_string());
+            }
+            erasure_index = index.checked_sub(fec_set_index).map(|i| i as usize);
+            if erasure_index.is_none() {
+                problems.push(format!(
+                    "index {} is below fec_set_index {}",
+                    index, fec_set_index
+                ));
+            }
+        }
+        Some(ShredKind::Code) => {
+            if let (Some(num_data_shreds), Some(num_coding_shreds), Some(position)) = (
+                array(payload, 0x53).map(u16::from_le_bytes),
+                array(payload, 0x55).map(u16::from_le_bytes),
+                array(payload, 0x57).map(u16::from_le_bytes),
+            ) {
+                if position >= num_coding_shreds {
+                    problems.push(format!(
+                        "position {} is outside {} coding shreds",
+                        position, num_coding_shreds
+                    ));
+                }
+                erasure_index = Some(usize::from(num_data_shreds) + usize::from(position));
+                code_header = Some(CodeHeader {
+                    num_data_shreds,
+                    num_coding_shreds,
+                    position,
+                });
+            } else {
+                problems.push("payload ends inside the code header".to_string());
+            }
+        }
+        None => {}
+    }
+
+    let merkle = variant
+        .merkle
+        .then(|| inspect_merkle(payload, &variant, erasure_index, &mut problems));
+
+    Ok(ShredInspection {
+        len: payload.len(),
+        signature: Signature::from(array::<64>(payload, 0).unwrap()).to_string(),
+        variant,
+        slot,
+        index,
+        shred_version,
+        fec_set_index,
+        data_header,
+        code_header,
+        merkle,
+        problems,
+        payload: payload.to_vec(),
+    })
+}
+
+fn inspect_merkle(
+    payload: &[u8],
+    variant: &ShredVariant,
+    erasure_index: Option<usize>,
+    problems: &mut Vec<String>,
+) -> MerkleInfo {
+    let mut info = MerkleInfo {
+        erasure_index,
+        chained_merkle_root: None,
+        proof: Vec::new(),
+        root: None,
+        retransmitter_signature: None,
+    };
+
+    // offsets are fixed by the variant, measured from the end of a full size payload
+    let Some(payload_size) = variant.payload_size() else {
+        return info;
+    };
+    let proof_bytes = usize::from(variant.proof_size) * SIZE_OF_PROOF_ENTRY;
+    let tail = proof_bytes
+        + if variant.resigned {
+            SIZE_OF_SIGNATURE
+        } else {
+            0
+        }
+        + if variant.chained {
+            SIZE_OF_MERKLE_ROOT
+        } else {
+            0
+        };
+    let Some(proof_offset) = payload_size
+        .checked_sub(tail)
+        .filter(|offset| *offset >= variant.headers_size())
+        .map(|offset| {
+            offset
+                + if variant.chained {
+                    SIZE_OF_MERKLE_ROOT
+                } else {
+                    0
+                }
+        })
+    else {
+        problems.push(format!(
+            "proof size {} leaves no room for data",
+            variant.proof_size
+        ));
+        return info;
+    };
+
+    if variant.chained {
+        info.chained_merkle_root = array::<32>(payload, proof_offset - SIZE_OF_MERKLE_ROOT)
+            .map(|root| Hash::new_from_array(root).to_string());
+    }
+    let proof: Vec<&[u8]> = payload
+        .get(proof_offset..proof_offset + proof_bytes)
+        .map(|proof| proof.chunks(SIZE_OF_PROOF_ENTRY).collect())
+        .unwrap_or_default();
+    info.proof = proof.iter().map(hex::encode).collect();
+    if variant.resigned {
+        info.retransmitter_signature = array::<64>(payload, proof_offset + proof_bytes)
+            .map(|signature| Signature::from(signature).to_string());
+    }
+
+    if payload.len() < payload_size {
+        problems.push("payload too short to compute the merkle root".to_string());
+        return info;
+    }
+    let Some(mut index) = erasure_index else {
+        return info;
+    };
+
+    let mut node = hashv(&[
+        MERKLE_HASH_PREFIX_LEAF,
+        &payload[SIZE_OF_SIGNATURE..proof_offset],
+    ]);
+    for entry in &proof {
+        let node_entry = &node.as_ref()[..SIZE_OF_PROOF_ENTRY];
+        node = if index.is_multiple_of(2) {
+            hashv(&[MERKLE_HASH_PREFIX_NODE, node_entry, entry])
+        } else {
+            hashv(&[MERKLE_HASH_PREFIX_NODE, entry, node_entry])
+        };
+        index >>= 1;
+    }
+    if index == 0 {
+        info.root = Some(node.to_string());
+    } else {
+        problems.push(format!(
+            "erasure index {} doesn't fit a proof of {} entries",
+            erasure_index.unwrap_or_default(),
+            proof.len()
+        ));
+    }
+    info
+}
+
+impl ShredInspection {
+    // one line, for listing many shreds
+    pub fn summary(&self) -> String {
+        let mut line = format!(
+            "slot {} index {} {} version {} fec_set {}",
+            self.slot, self.index, self.variant, self.shred_version, self.fec_set_index
+        );
+        if let Some(data) = &self.data_header {
+            line.push_str(&format!(
+                " parent {} data_complete {} last_in_slot {}",
+                data.parent_slot
+                    .map(|slot| slot.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                data.data_complete,
+                data.last_in_slot
+            ));
+        }
+        line.push_str(&format!(" {} bytes", self.len));
+        if !self.problems.is_empty() {
+            line.push_str(&format!(" ({} problems)", self.problems.len()));
+        }
+        line
+    }
+
+    pub fn hex_dump(&self) -> String {
+        hex_dump(&self.payload)
+    }
+}
+
+impl fmt::Display for ShredInspection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Common header ({} bytes)", self.len)?;
+        writeln!(f, "  signature          {}", self.signature)?;
+        writeln!(f, "  variant            {}", self.variant)?;
+        writeln!(f, "  slot               {}", self.slot)?;
+        writeln!(f, "  index              {}", self.index)?;
+        writeln!(f, "  shred_version      {}", self.shred_version)?;
+        writeln!(f, "  fec_set_index      {}", self.fec_set_index)?;
+
+        if let Some(data) = &self.data_header {
+            writeln!(f, "Data header")?;
+            match data.parent_slot {
+                Some(parent) => writeln!(
+                    f,
+                    "  parent_offset      {} (parent slot {})",
+                    data.parent_offset, parent
+                )?,
+                None => writeln!(f, "  parent_offset      {}", data.parent_offset)?,
+            }
+            writeln!(
+                f,
+                "  flags              0b{:08b} reference_tick {}{}{}",
+                data.flags,
+                data.reference_tick,
+                if data.data_complete {
+                    ", data_complete"
+                } else {
+                    ""
+                },
+                if data.last_in_slot {
+                    ", last_in_slot"
+                } else {
+                    ""
+                }
+            )?;
+            writeln!(
+                f,
+                "  size               {} ({} bytes of data)",
+                data.size,
+                usize::from(data.size).saturating_sub(SIZE_OF_DATA_HEADERS)
+            )?;
+        }
+
+        if let Some(code) = &self.code_header {
+            writeln!(f, "Code header")?;
+            writeln!(f, "  num_data_shreds    {}", code.num_data_shreds)?;
+            writeln!(f, "  num_coding_shreds  {}", code.num_coding_shreds)?;
+            writeln!(f, "  position           {}", code.position)?;
+        }
+
+        if let Some(merkle) = &self.merkle {
+            writeln!(f, "Merkle")?;
+            if let Some(erasure_index) = merkle.erasure_index {
+                writeln!(f, "  erasure_index      {}", erasure_index)?;
+            }
+            if let Some(root) = &merkle.chained_merkle_root {
+                writeln!(f, "  chained_root       {}", root)?;
+            }
+            for (i, entry) in merkle.proof.iter().enumerate() {
+                writeln!(f, "  proof[{:>2}]          {}", i, entry)?;
+            }
+            if let Some(root) = &merkle.root {
+                writeln!(f, "  root (computed)    {}", root)?;
+            }
+            if let Some(signature) = &merkle.retransmitter_signature {
+                writeln!(f, "  retransmitter_sig  {}", signature)?;
+            }
+        }
+
+        if !self.problems.is_empty() {
+            writeln!(f, "Problems")?;
+            for problem in &self.problems {
+                writeln!(f, "  - {}", problem)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// 16 bytes per line: offset, hex, printable ascii
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (line, chunk) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        out.push_str(&format!(
+            "{:04x}  {:<47}  |{}|\n",
+            line * 16,
+            hex.join(" "),
+            ascii
+        ));
+    }
+    out
+}
+
+fn array<const N: usize>(payload: &[u8], offset: usize) -> Option<[u8; N]> {
+    payload.get(offset..offset + N)?.try_into().ok()
+}
+
+fn serialize_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        shred::{TEST_SHRED_VERSION, test_data_shred},
+        utils::parse_shred,
+    };
+
+    #[test]
+    fn decodes_a_data_shred() {
+        let payload = test_data_shred(100, 7, 4, DATA_COMPLETE_SHRED | 3, b"entries");
+        let inspection = inspect(&payload).unwrap();
+
+        assert!(inspection.problems.is_empty(), "{:?}", inspection.problems);
+        assert_eq!(inspection.len, MERKLE_DATA_PAYLOAD);
+        assert_eq!(inspection.variant.shred_type, Some(ShredKind::Data));
+        assert!(inspection.variant.merkle && inspection.variant.chained);
+        assert!(!inspection.variant.resigned);
+        assert_eq!(inspection.slot, 100);
+        assert_eq!(inspection.index, 7);
+        assert_eq!(inspection.shred_version, TEST_SHRED_VERSION);
+        assert_eq!(inspection.fec_set_index, 4);
+        assert!(inspection.code_header.is_none());
+
+        let data = inspection.data_header.as_ref().unwrap();
+        assert_eq!(data.parent_offset, 1);
+        assert_eq!(data.parent_slot, Some(99));
+        assert_eq!(data.reference_tick, 3);
+        assert!(data.data_complete);
+        assert!(!data.last_in_slot);
+        assert_eq!(
+            usize::from(data.size),
+            SIZE_OF_DATA_HEADERS + b"entries".len()
+        );
+    }
+
+    #[test]
+    fn merkle_root_matches_solana_ledger() {
+        let payload = test_data_shred(100, 7, 4, 0, b"entries");
+        let shred = parse_shred(&payload).unwrap();
+        let merkle = inspect(&payload).unwrap().merkle.unwrap();
+
+        assert_eq!(merkle.erasure_index, Some(3));
+        assert_eq!(merkle.proof.len(), 6);
+        assert_eq!(merkle.root, Some(shred.merkle_root().unwrap().to_string()));
+        assert_eq!(
+            merkle.chained_merkle_root,
+            Some(shred.chained_merkle_root().unwrap().to_string())
+        );
+        assert!(merkle.retransmitter_signature.is_none());
+    }
+
+    #[test]
+    fn lists_problems_instead_of_failing() {
+        // last_in_slot without data_complete, index below fec_set_index
+        let mut payload = test_data_shred(100, 2, 4, 0b1000_0000, b"entries");
+        payload.truncate(1000);
+        let inspection = inspect(&payload).unwrap();
+
+        assert_eq!(inspection.slot, 100);
+        let problems = inspection.problems.join("\n");
+        assert!(problems.contains("payload is 1000 bytes"), "{}", problems);
+        assert!(problems.contains("last_in_slot set without data_complete"));
+        assert!(problems.contains("index 2 is below fec_set_index 4"));
+    }
+
+    #[test]
+    fn rejects_packets_shorter_than_the_common_header() {
+        let payload = test_data_shred(100, 7, 4, 0, b"entries");
+        assert!(matches!(
+            inspect(&payload[..SIZE_OF_COMMON_HEADER - 1]),
+            Err(Error::ShredTooShort { len: 82, min: 83 })
+        ));
+        assert!(inspect(&payload[..SIZE_OF_COMMON_HEADER]).is_ok());
+    }
+}
@@ -0,0 +1,914 @@
+/*
+ ** Kafka Plugin **
+: Publishes shreds, and the transactions deshredded from them, to Kafka topics for
+: analytics pipelines. Registered as `Kafka` in the binary's plugin registry, configured
+: through the plugin's `settings` in the config file.
+: The broker connection is a `KafkaProducer`; `RdKafkaProducer` (librdkafka, behind the
+: `kafka` cargo feature) is built on start() from the settings, tests and embedders can
+: hand in their own with `KafkaPlugin::with_producer()`.
+
+*  ** Records **
+: Every record is keyed by its slot (decimal string), so a slot's shreds and transactions
+: land in one partition, in the order they were published.
+
+! +-------------+------------------------------+---------------------------------------+
+! | Record      | Value                        | Headers                               |
+! +-------------+------------------------------+---------------------------------------+
+! | shred       | the shred's raw payload, as  | kind, slot, index, fec_set_index,     |
+! |             | received                     | shred_type, shred_version, flags      |
+! |             |                              | (data shreds), published_at_us        |
+! | transaction | the bincode VersionedTrans-  | kind, slot, signature, batch_index,   |
+! |             | action, as in an Entry       | entry_index, transaction_index,       |
+! |             |                              | published_at_us                       |
+! +-------------+------------------------------+---------------------------------------+
+
+: Header values are UTF-8 strings. batch_index is the first data shred of the entry
+: batch the transaction was in, entry_index the entry within that batch and
+: transaction_index the transaction within the entry.
+
+*  ** Transactions **
+: With `transactions_topic` set, data shreds are kept per slot until a whole entry batch
+: arrived, from the shred after the previous DATA_COMPLETE one (or index 0) up to the next
+: DATA_COMPLETE one. The batch is deshredded into entries and every transaction published.
+: Batches never completed (a shred missing, no repair) publish nothing; at most
+: `MAX_DESHRED_SLOTS` slots are kept, oldest dropped first. Transactions are only
+: available for data shreds the plugin gets, so filters must not drop those.
+
+*  ** Delivery **
+: At least once: a handle call returns once the broker acknowledged every record, a record
+: the producer returned no result for counts as failed. From the first failed record on
+: everything is sent again, in order, up to `retries` times with a growing backoff, so a
+: slot's records stay in the order they were published; records after the failed one
+: that were acknowledged the first time are there twice. A record that still failed fails
+: the call, which the plugin's supervision policy handles (see supervision.rs), records
+: are lost only then. Shreds arrive through handle_batch() in batches of
+: `DEFAULT_BATCH_MAX_SHREDS`, `[plugins.batch]` changes that.
+
+*  ** Settings **
+! +---------------------+---------------------+----------------------------------------+
+! | Field               | Default             | Meaning                                |
+! +---------------------+---------------------+----------------------------------------+
+! | brokers             | localhost:9092      | bootstrap.servers                      |
+! | shreds_topic        | chainsmoker.shreds  | topic for shreds, null for none        |
+! | transactions_topic  | null                | topic for transactions, null for none  |
+! | retries             | 5                   | sends again of a failed record         |
+! | retry_backoff_ms    | 100                 | wait before the first retry, grows     |
+! |                     |                     | linearly with each one                 |
+! | delivery_timeout_ms | 30000               | message.timeout.ms, per send           |
+! | properties          | {}                  | other librdkafka producer properties   |
+! +---------------------+---------------------+----------------------------------------+
+
+*  ** Example **
+: [[plugins]]
+: name = "Kafka"
+: supervision = { on_error = "restart" }
+: settings = { brokers = "kafka-1:9092,kafka-2:9092", transactions_topic = "solana.txs" }
+*/
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::{info, warn};
+use serde::Deserialize;
+use solana_entry::entry::Entry;
+use solana_ledger::shred::Shred;
+use solana_sdk::{clock::Slot, transaction::VersionedTransaction};
+
+use crate::{
+    error::BoxError,
+    output::{BatchConfig, OutputPlugin, PluginSettings, plugin_settings},
+    supervision::PluginHealth,
+};
+
+pub const DEFAULT_BROKERS: &str = "localhost:9092";
+pub const DEFAULT_SHREDS_TOPIC: &str = "chainsmoker.shreds";
+pub const DEFAULT_RETRIES: u32 = 5;
+pub const DEFAULT_RETRY_BACKOFF_MS: u64 = 100;
+pub const DEFAULT_DELIVERY_TIMEOUT_MS: u64 = 30_000;
+
+// slots with data shreds kept back for deshredding, oldest dropped first
+pub const MAX_DESHRED_SLOTS: usize = 32;
+
+// where the data of a data shred starts and its size field, see shred.rs
+const DATA_OFFSET: usize = 0x58;
+const DATA_SIZE_OFFSET: usize = 0x56;
+const DATA_FLAGS_OFFSET: usize = 0x55;
+
+// flushed on stop(), so a restart or shutdown doesn't lose what is in flight
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KafkaSettings {
+    pub brokers: String,
+    pub shreds_topic: Option<String>,
+    pub transactions_topic: Option<String>,
+    pub retries: u32,
+    pub retry_backoff_ms: u64,
+    pub delivery_timeout_ms: u64,
+    pub properties: BTreeMap<String, String>,
+}
+
+impl Default for KafkaSettings {
+    fn default() -> Self {
+        Self {
+            brokers: DEFAULT_BROKERS.to_string(),
+            shreds_topic: Some(DEFAULT_SHREDS_TOPIC.to_string()),
+            transactions_topic: None,
+            retries: DEFAULT_RETRIES,
+            retry_backoff_ms: DEFAULT_RETRY_BACKOFF_MS,
+            delivery_timeout_ms: DEFAULT_DELIVERY_TIMEOUT_MS,
+            properties: BTreeMap::new(),
+        }
+    }
+}
+
+impl KafkaSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.brokers.is_empty() {
+            return Err("brokers must not be empty".to_string());
+        }
+        if self.shreds_topic.is_none() && self.transactions_topic.is_none() {
+            return Err("one of shreds_topic and transactions_topic must be set".to_string());
+        }
+        if [&self.shreds_topic, &self.transactions_topic]
+            .into_iter()
+            .flatten()
+            .any(String::is_empty)
+        {
+            return Err("topics must not be empty".to_string());
+        }
+        if self.delivery_timeout_ms == 0 {
+            return Err("delivery_timeout_ms must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    // before the retry after `attempt` failed sends
+    fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.retry_backoff_ms.saturating_mul(u64::from(attempt)))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KafkaRecord {
+    pub topic: String,
+    pub key: String,
+    pub payload: Vec<u8>,
+    pub headers: Vec<(&'static str, String)>,
+}
+
+// the broker side of the plugin, RdKafkaProducer or a stand-in
+#[async_trait::async_trait]
+pub trait KafkaProducer: Send + Sync {
+    // resolves once the broker answered for every record, one result per record in order
+    async fn send(&self, records: &[KafkaRecord]) -> Vec<Result<(), BoxError>>;
+
+    async fn flush(&self, timeout: Duration) -> Result<(), BoxError>;
+}
+
+pub struct KafkaPlugin {
+    settings: KafkaSettings,
+    // built on start() unless handed in
+    producer: Option<Arc<dyn KafkaProducer>>,
+    deshredder: Deshredder,
+    published: u64,
+    retried: u64,
+    // what the last handle call ran into, for health()
+    last_retry: Option<String>,
+    last_failure: Option<String>,
+}
+
+impl KafkaPlugin {
+    pub fn new(settings: KafkaSettings) -> Result<Self, BoxError> {
+        settings.validate()?;
+        Ok(Self {
+            settings,
+            producer: None,
+            deshredder: Deshredder::default(),
+            published: 0,
+            retried: 0,
+            last_retry: None,
+            last_failure: None,
+        })
+    }
+
+    pub fn from_settings(settings: &PluginSettings) -> Result<Self, BoxError> {
+        Self::new(plugin_settings(settings)?)
+    }
+
+    pub fn with_producer(
+        settings: KafkaSettings,
+        producer: Arc<dyn KafkaProducer>,
+    ) -> Result<Self, BoxError> {
+        let mut plugin = Self::new(settings)?;
+        plugin.producer = Some(producer);
+        Ok(plugin)
+    }
+
+    pub fn published(&self) -> u64 {
+        self.published
+    }
+
+    fn records(&mut self, shred: &Arc<Shred>, records: &mut Vec<KafkaRecord>) {
+        if let Some(topic) = &self.settings.shreds_topic {
+            records.push(shred_record(topic, shred));
+        }
+        let Some(topic) = &self.settings.transactions_topic else {
+            return;
+        };
+        if !shred.is_data() {
+            return;
+        }
+        for batch in self.deshredder.insert(shred) {
+            match batch.entries() {
+                Ok(entries) => records.extend(transaction_records(topic, &batch, &entries)),
+                Err(e) => warn!(
+                    "Failed to deshred slot {} shreds {}..={}: {}",
+                    batch.slot, batch.start, batch.end, e
+                ),
+            }
+        }
+    }
+
+    // sends `records` until all are acknowledged or `retries` ran out, each retry from
+    // the first record that failed on
+    async fn publish(&mut self, mut records: Vec<KafkaRecord>) -> Result<(), BoxError> {
+        let producer = self.producer.clone().ok_or("Kafka plugin is not started")?;
+        self.last_retry = None;
+
+        let mut attempt = 0;
+        loop {
+            let results = producer.send(&records).await;
+            // records past the end of `results` weren't answered for, they failed as well
+            let failed_at = results
+                .iter()
+                .position(Result::is_err)
+                .unwrap_or(results.len())
+                .min(records.len());
+            self.published += failed_at as u64;
+            if failed_at == records.len() {
+                self.last_failure = None;
+                return Ok(());
+            }
+            let error = match results.into_iter().nth(failed_at) {
+                Some(Err(e)) => e.to_string(),
+                _ => format!("no result for {} records", records.len() - failed_at),
+            };
+            records.drain(..failed_at);
+
+            if attempt >= self.settings.retries {
+                let message = format!(
+                    "{} records failed after {} retries: {}",
+                    records.len(),
+                    attempt,
+                    error
+                );
+                self.last_failure = Some(message.clone());
+                return Err(message.into());
+            }
+
+            attempt += 1;
+            self.retried += records.len() as u64;
+            self.last_retry = Some(format!("{} records retried: {}", records.len(), error));
+            warn!(
+                "Kafka retry {} of {} for {} records: {}",
+                attempt,
+                self.settings.retries,
+                records.len(),
+                error
+            );
+            tokio::time::sleep(self.settings.backoff(attempt)).await;
+        }
+    }
+}
+
+#[async_trait::async_trait]
+impl OutputPlugin for KafkaPlugin {
+    async fn start(&mut self) -> Result<(), BoxError> {
+        if self.producer.is_none() {
+            self.producer = Some(connect(&self.settings)?);
+        }
+        info!(
+            "Kafka plugin publishing to {} (shreds: {}, transactions: {})",
+            self.settings.brokers,
+            self.settings.shreds_topic.as_deref().unwrap_or("-"),
+            self.settings.transactions_topic.as_deref().unwrap_or("-")
+        );
+        Ok(())
+    }
+
+    async fn handle_shred(&mut self, shred: Shred) -> Result<(), BoxError> {
+        self.handle_shared(Arc::new(shred)).await
+    }
+
+    async fn handle_shared(&mut self, shred: Arc<Shred>) -> Result<(), BoxError> {
+        self.handle_batch(&[shred]).await
+    }
+
+    async fn handle_batch(&mut self, shreds: &[Arc<Shred>]) -> Result<(), BoxError> {
+        let mut records = Vec::new();
+        for shred in shreds {
+            self.records(shred, &mut records);
+        }
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.publish(records).await
+    }
+
+    // one acknowledgement round trip per batch instead of per shred
+    fn batch(&self) -> Option<BatchConfig> {
+        Some(BatchConfig::default())
+    }
+
+    async fn stop(&mut self) -> Result<(), BoxError> {
+        if let Some(producer) = &self.producer {
+            producer.flush(FLUSH_TIMEOUT).await?;
+        }
+        info!(
+            "Kafka plugin stopped, {} records published, {} retried",
+            self.published, self.retried
+        );
+        Ok(())
+    }
+
+    fn name(&self) -> &str {
+        "Kafka"
+    }
+
+    fn health(&self) -> PluginHealth {
+        match (&self.last_failure, &self.last_retry) {
+            (Some(failure), _) => PluginHealth::Unhealthy(failure.clone()),
+            (None, Some(retry)) => PluginHealth::Degraded(retry.clone()),
+            (None, None) => PluginHealth::Healthy,
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+fn connect(settings: &KafkaSettings) -> Result<Arc<dyn KafkaProducer>, BoxError> {
+    Ok(Arc::new(RdKafkaProducer::new(settings)?))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn connect(_settings: &KafkaSettings) -> Result<Arc<dyn KafkaProducer>, BoxError> {
+    Err("chainsmoker was built without the kafka feature, rebuild with --features kafka".into())
+}
+
+fn shred_record(topic: &str, shred: &Shred) -> KafkaRecord {
+    let payload: &[u8] = shred.payload();
+    let mut headers = vec![
+        ("kind", "shred".to_string()),
+        ("slot", shred.slot().to_string()),
+        ("index", shred.index().to_string()),
+        ("fec_set_index", shred.fec_set_index().to_string()),
+        (
+            "shred_type",
+            if shred.is_data() { "data" } else { "coding" }.to_string(),
+        ),
+        ("shred_version", shred.version().to_string()),
+    ];
+    if shred.is_data()
+        && let Some(flags) = payload.get(DATA_FLAGS_OFFSET)
+    {
+        headers.push(("flags", flags.to_string()));
+    }
+    headers.push(("published_at_us", now_us().to_string()));
+    KafkaRecord {
+        topic: topic.to_string(),
+        key: shred.slot().to_string(),
+        payload: payload.to_vec(),
+        headers,
+    }
+}
+
+fn transaction_records(topic: &str, batch: &EntryBatch, entries: &[Entry]) -> Vec<KafkaRecord> {
+    let published_at = now_us().to_string();
+    let mut records = Vec::new();
+    for (entry_index, entry) in entries.iter().enumerate() {
+        for (transaction_index, transaction) in entry.transactions.iter().enumerate() {
+            let payload = match bincode::serialize::<VersionedTransaction>(transaction) {
+                Ok(payload) => payload,
+                Err(e) => {
+                    warn!(
+                        "Failed to serialize a slot {} transaction: {}",
+                        batch.slot, e
+                    );
+                    continue;
+                }
+            };
+            let signature = transaction
+                .signatures
+                .first()
+                .map(ToString::to_string)
+                .unwrap_or_default();
+            records.push(KafkaRecord {
+                topic: topic.to_string(),
+                key: batch.slot.to_string(),
+                payload,
+                headers: vec![
+                    ("kind", "transaction".to_string()),
+                    ("slot", batch.slot.to_string()),
+                    ("signature", signature),
+                    ("batch_index", batch.start.to_string()),
+                    ("entry_index", entry_index.to_string()),
+                    ("transaction_index", transaction_index.to_string()),
+                    ("published_at_us", published_at.clone()),
+                ],
+            });
+        }
+    }
+    records
+}
+
+fn now_us() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_micros())
+        .unwrap_or_default()
+}
+
+// the entry data a data shred carries, None if its size field doesn't fit the payload
+pub fn data_payload(shred: &Shred) -> Option<&[u8]> {
+    let payload: &[u8] = shred.payload();
+    let bytes = payload.get(DATA_SIZE_OFFSET..DATA_SIZE_OFFSET + 2)?;
+    let size = usize::from(u16::from_le_bytes([bytes[0], bytes[1]]));
+    payload.get(DATA_OFFSET..size)
+}
+
+// data shreds `start..=end` of a slot, a whole entry batch
+pub struct EntryBatch {
+    pub slot: Slot,
+    pub start: u32,
+    pub end: u32,
+    shreds: Vec<Arc<Shred>>,
+}
+
+impl EntryBatch {
+    pub fn entries(&self) -> Result<Vec<Entry>, BoxError> {
+        let mut data = Vec::new();
+        for shred in &self.shreds {
+            let bytes = data_payload(shred)
+                .ok_or_else(|| format!("shred {} has a size outside its payload", shred.index()))?;
+            data.extend_from_slice(bytes);
+        }
+        Ok(bincode::deserialize(&data)?)
+    }
+}
+
+#[derive(Default)]
+struct SlotShreds {
+    data: BTreeMap<u32, Arc<Shred>>,
+    // indices of DATA_COMPLETE shreds seen, each ends a batch
+    batch_ends: BTreeSet<u32>,
+    // end -> start of the batches already taken, their shreds arriving again are ignored
+    taken: BTreeMap<u32, u32>,
+}
+
+impl SlotShreds {
+    fn was_taken(&self, index: u32) -> bool {
+        self.taken
+            .range(index..)
+            .next()
+            .is_some_and(|(_, &start)| start <= index)
+    }
+
+    // the batch ending at `end`, if its shreds and the end of the one before are all here
+    fn take_batch(&mut self, slot: Slot, end: u32) -> Option<EntryBatch> {
+        if !self.batch_ends.contains(&end) {
+            return None;
+        }
+        let start = match self.batch_ends.range(..end).next_back() {
+            Some(previous) => previous + 1,
+            None => 0,
+        };
+        // with the previous batch's last shred missing, `start` is that batch's start and
+        // the count fails until it arrives
+        if self.data.range(start..=end).count() as u32 != end - start + 1 {
+            return None;
+        }
+        let indices: Vec<u32> = self.data.range(start..=end).map(|(&i, _)| i).collect();
+        let shreds = indices
+            .iter()
+            .filter_map(|index| self.data.remove(index))
+            .collect();
+        self.taken.insert(end, start);
+        Some(EntryBatch {
+            slot,
+            start,
+            end,
+            shreds,
+        })
+    }
+}
+
+// data shreds kept back per slot until they make a whole entry batch
+#[derive(Default)]
+struct Deshredder {
+    slots: BTreeMap<Slot, SlotShreds>,
+}
+
+impl Deshredder {
+    // batches `shred` completed, the one it is in and for a DATA_COMPLETE shred the next
+    fn insert(&mut self, shred: &Arc<Shred>) -> Vec<EntryBatch> {
+        let slot = shred.slot();
+        let index = shred.index();
+        let shreds = self.slots.entry(slot).or_default();
+        if shreds.was_taken(index) || shreds.data.insert(index, shred.clone()).is_some() {
+            return Vec::new();
+        }
+        if shred.data_complete() || shred.last_in_slot() {
+            shreds.batch_ends.insert(index);
+        }
+
+        let mut ready = Vec::new();
+        let end = shreds.batch_ends.range(index..).next().copied();
+        if let Some(batch) = end.and_then(|end| shreds.take_batch(slot, end)) {
+            ready.push(batch);
+        }
+        if end == Some(index)
+            && let Some(&next) = shreds.batch_ends.range(index + 1..).next()
+            && let Some(batch) = shreds.take_batch(slot, next)
+        {
+            ready.push(batch);
+        }
+
+        while self.slots.len() > MAX_DESHRED_SLOTS {
+            self.slots.pop_first();
+        }
+        ready
+    }
+}
+
+#[cfg(feature = "kafka")]
+pub use rdkafka_producer::RdKafkaProducer;
+
+#[cfg(feature = "kafka")]
+mod rdkafka_producer {
+    use std::time::Duration;
+
+    use rdkafka::{
+        ClientConfig,
+        message::{Header, OwnedHeaders},
+        producer::{FutureProducer, FutureRecord, Producer},
+    };
+
+    use super::{KafkaProducer, KafkaRecord, KafkaSettings};
+    use crate::error::BoxError;
+
+    // librdkafka with acks from all in-sync replicas and idempotence, so its own
+    // retries don't duplicate or reorder records within a partition
+    pub struct RdKafkaProducer {
+        producer: FutureProducer,
+    }
+
+    impl RdKafkaProducer {
+        pub fn new(settings: &KafkaSettings) -> Result<Self, BoxError> {
+            let mut config = ClientConfig::new();
+            config
+                .set("bootstrap.servers", &settings.brokers)
+                .set("acks", "all")
+                .set("enable.idempotence", "true")
+                .set(
+                    "message.timeout.ms",
+                    settings.delivery_timeout_ms.to_string(),
+                );
+            for (key, value) in &settings.properties {
+                config.set(key, value);
+            }
+            Ok(Self {
+                producer: config.create()?,
+            })
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl KafkaProducer for RdKafkaProducer {
+        async fn send(&self, records: &[KafkaRecord]) -> Vec<Result<(), BoxError>> {
+            // everything is queued first, then the acknowledgements are awaited
+            let deliveries: Vec<_> = records
+                .iter()
+                .map(|record| {
+                    self.producer
+                        .send_result(
+                            FutureRecord::to(&record.topic)
+                                .key(&record.key)
+                                .payload(&record.payload)
+                                .headers(owned_headers(record)),
+                        )
+                        .map_err(|(e, _)| e)
+                })
+                .collect();
+
+            let mut results = Vec::with_capacity(deliveries.len());
+            for delivery in deliveries {
+                results.push(match delivery {
+                    Ok(delivery) => match delivery.await {
+                        Ok(Ok(_)) => Ok(()),
+                        Ok(Err((e, _))) => Err(e.into()),
+                        Err(_) => Err("delivery was canceled".into()),
+                    },
+                    Err(e) => Err(e.into()),
+                });
+            }
+            results
+        }
+
+        async fn flush(&self, timeout: Duration) -> Result<(), BoxError> {
+            let producer = self.producer.clone();
+            tokio::task::spawn_blocking(move || producer.flush(timeout)).await??;
+            Ok(())
+        }
+    }
+
+    fn owned_headers(record: &KafkaRecord) -> OwnedHeaders {
+        let mut headers = OwnedHeaders::new_with_capacity(record.headers.len());
+        for (key, value) in &record.headers {
+            headers = headers.insert(Header {
+                key,
+                value: Some(value.as_bytes()),
+            });
+        }
+        headers
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::VecDeque, sync::Mutex};
+
+    use solana_sdk::{
+        hash::Hash,
+        message::{Message, VersionedMessage},
+        signature::Signature,
+    };
+
+    use super::*;
+    use crate::{
+        shred::{TEST_SHRED_VERSION, test_data_shred},
+        utils::parse_shred,
+    };
+
+    const DATA_COMPLETE: u8 = 0b0100_0000;
+
+    // what one send() call answers
+    enum Answer {
+        Ok,
+        // the record at this position fails, the rest are acknowledged
+        FailAt(usize),
+        // only this many results
+        Short(usize),
+    }
+
+    #[derive(Default)]
+    struct MockProducer {
+        answers: Mutex<VecDeque<Answer>>,
+        calls: Mutex<Vec<Vec<KafkaRecord>>>,
+    }
+
+    impl MockProducer {
+        fn answering(answers: Vec<Answer>) -> Arc<Self> {
+            Arc::new(Self {
+                answers: Mutex::new(answers.into()),
+                ..Self::default()
+            })
+        }
+
+        fn calls(&self) -> Vec<Vec<KafkaRecord>> {
+            self.calls.lock().unwrap().clone()
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl KafkaProducer for MockProducer {
+        async fn send(&self, records: &[KafkaRecord]) -> Vec<Result<(), BoxError>> {
+            self.calls.lock().unwrap().push(records.to_vec());
+            let answer = self
+                .answers
+                .lock()
+                .unwrap()
+                .pop_front()
+                .unwrap_or(Answer::Ok);
+            let len = match answer {
+                Answer::Short(len) => len,
+                _ => records.len(),
+            };
+            (0..len)
+                .map(|i| match answer {
+                    Answer::FailAt(at) if at == i => Err("broker unavailable".into()),
+                    _ => Ok(()),
+                })
+                .collect()
+        }
+
+        async fn flush(&self, _timeout: Duration) -> Result<(), BoxError> {
+            Ok(())
+        }
+    }
+
+    fn settings(retries: u32) -> KafkaSettings {
+        KafkaSettings {
+            retries,
+            retry_backoff_ms: 0,
+            ..KafkaSettings::default()
+        }
+    }
+
+    fn shred(slot: Slot, index: u32, flags: u8, data: &[u8]) -> Arc<Shred> {
+        Arc::new(parse_shred(&test_data_shred(slot, index, 0, flags, data)).unwrap())
+    }
+
+    fn shreds(slot: Slot, count: u32) -> Vec<Arc<Shred>> {
+        (0..count)
+            .map(|index| shred(slot, index, 0, b"data"))
+            .collect()
+    }
+
+    fn header<'a>(record: &'a KafkaRecord, key: &str) -> Option<&'a str> {
+        record
+            .headers
+            .iter()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn indices(records: &[KafkaRecord]) -> Vec<&str> {
+        records
+            .iter()
+            .map(|record| header(record, "index").unwrap())
+            .collect()
+    }
+
+    // `entries` serialized and split over data shreds of `chunk` bytes, the last one
+    // DATA_COMPLETE
+    fn entry_shreds(slot: Slot, first: u32, entries: &[Entry], chunk: usize) -> Vec<Arc<Shred>> {
+        let data = bincode::serialize(entries).unwrap();
+        let chunks: Vec<&[u8]> = data.chunks(chunk).collect();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, bytes)| {
+                let flags = if i + 1 == chunks.len() {
+                    DATA_COMPLETE
+                } else {
+                    0
+                };
+                shred(slot, first + i as u32, flags, bytes)
+            })
+            .collect()
+    }
+
+    fn entries(signature: u8, transactions: usize) -> Vec<Entry> {
+        let transaction = |i: usize| VersionedTransaction {
+            signatures: vec![Signature::from([signature + i as u8; 64])],
+            message: VersionedMessage::Legacy(Message::default()),
+        };
+        vec![Entry {
+            num_hashes: 1,
+            hash: Hash::default(),
+            transactions: (0..transactions).map(transaction).collect(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_retry_then_success() {
+        let producer = MockProducer::answering(vec![Answer::FailAt(1)]);
+        let mut plugin = KafkaPlugin::with_producer(settings(3), producer.clone()).unwrap();
+
+        plugin.handle_batch(&shreds(7, 3)).await.unwrap();
+
+        let calls = producer.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(indices(&calls[0]), ["0", "1", "2"]);
+        // from the failed record on, in order
+        assert_eq!(indices(&calls[1]), ["1", "2"]);
+        assert!(matches!(plugin.health(), PluginHealth::Degraded(_)));
+    }
+
+    #[tokio::test]
+    async fn test_retries_running_out_fail_the_call() {
+        let answers = (0..3).map(|_| Answer::FailAt(0)).collect();
+        let producer = MockProducer::answering(answers);
+        let mut plugin = KafkaPlugin::with_producer(settings(2), producer.clone()).unwrap();
+
+        assert!(plugin.handle_batch(&shreds(7, 2)).await.is_err());
+        assert_eq!(producer.calls().len(), 3);
+        assert_eq!(plugin.published(), 0);
+        assert!(matches!(plugin.health(), PluginHealth::Unhealthy(_)));
+    }
+
+    #[tokio::test]
+    async fn test_short_results_count_as_failed() {
+        let producer = MockProducer::answering(vec![Answer::Short(1)]);
+        let mut plugin = KafkaPlugin::with_producer(settings(1), producer.clone()).unwrap();
+
+        plugin.handle_batch(&shreds(7, 3)).await.unwrap();
+
+        let calls = producer.calls();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(indices(&calls[1]), ["1", "2"]);
+        assert_eq!(plugin.published(), 3);
+
+        let producer = MockProducer::answering(vec![Answer::Short(0), Answer::Short(0)]);
+        let mut plugin = KafkaPlugin::with_producer(settings(1), producer).unwrap();
+        assert!(plugin.handle_batch(&shreds(7, 1)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_shred_record_headers_and_key() {
+        let producer = MockProducer::answering(Vec::new());
+        let mut plugin = KafkaPlugin::with_producer(settings(0), producer.clone()).unwrap();
+
+        let shred = shred(42, 3, DATA_COMPLETE, b"data");
+        plugin.handle_batch(&[shred.clone()]).await.unwrap();
+
+        let record = &producer.calls()[0][0];
+        assert_eq!(record.topic, DEFAULT_SHREDS_TOPIC);
+        assert_eq!(record.key, "42");
+        let payload: &[u8] = shred.payload();
+        assert_eq!(record.payload, payload);
+        assert_eq!(header(record, "kind"), Some("shred"));
+        assert_eq!(header(record, "slot"), Some("42"));
+        assert_eq!(header(record, "index"), Some("3"));
+        assert_eq!(header(record, "fec_set_index"), Some("0"));
+        assert_eq!(header(record, "shred_type"), Some("data"));
+        assert_eq!(
+            header(record, "shred_version"),
+            Some(TEST_SHRED_VERSION.to_string().as_str())
+        );
+        assert_eq!(header(record, "flags"), Some("64"));
+        assert!(header(record, "published_at_us").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_transaction_records() {
+        let producer = MockProducer::answering(Vec::new());
+        let settings = KafkaSettings {
+            shreds_topic: None,
+            transactions_topic: Some("txs".to_string()),
+            ..settings(0)
+        };
+        let mut plugin = KafkaPlugin::with_producer(settings, producer.clone()).unwrap();
+
+        plugin
+            .handle_batch(&entry_shreds(9, 0, &entries(1, 2), 64))
+            .await
+            .unwrap();
+
+        let records = &producer.calls()[0];
+        assert_eq!(records.len(), 2);
+        for (i, record) in records.iter().enumerate() {
+            assert_eq!(record.topic, "txs");
+            assert_eq!(record.key, "9");
+            assert_eq!(header(record, "kind"), Some("transaction"));
+            assert_eq!(header(record, "batch_index"), Some("0"));
+            assert_eq!(header(record, "entry_index"), Some("0"));
+            assert_eq!(
+                header(record, "transaction_index"),
+                Some(i.to_string().as_str())
+            );
+            let signature = Signature::from([1 + i as u8; 64]).to_string();
+            assert_eq!(header(record, "signature"), Some(signature.as_str()));
+            let transaction: VersionedTransaction = bincode::deserialize(&record.payload).unwrap();
+            assert_eq!(transaction.signatures[0].to_string(), signature);
+        }
+    }
+
+    #[test]
+    fn test_deshredder_batches() {
+        let mut deshredder = Deshredder::default();
+        let first = entry_shreds(5, 0, &entries(1, 1), 64);
+        let second = entry_shreds(5, first.len() as u32, &entries(9, 1), 64);
+        assert!(first.len() > 1);
+
+        // the second batch can't start before the first one's last shred is here
+        for shred in &second {
+            assert!(deshredder.insert(shred).is_empty());
+        }
+        for shred in &first[..first.len() - 1] {
+            assert!(deshredder.insert(shred).is_empty());
+        }
+        let batches = deshredder.insert(&first[first.len() - 1]);
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].start, 0);
+        assert_eq!(batches[0].end, first.len() as u32 - 1);
+        assert_eq!(batches[1].start, first.len() as u32);
+        assert_eq!(batches[0].entries().unwrap(), entries(1, 1));
+        assert_eq!(batches[1].entries().unwrap(), entries(9, 1));
+    }
+
+    #[test]
+    fn test_deshredder_ignores_shreds_of_taken_batches() {
+        let mut deshredder = Deshredder::default();
+        let batch = entry_shreds(5, 0, &entries(1, 1), 1024);
+        assert_eq!(batch.len(), 1);
+
+        assert_eq!(deshredder.insert(&batch[0]).len(), 1);
+        assert!(deshredder.insert(&batch[0]).is_empty());
+        assert!(deshredder.slots[&5].data.is_empty());
+    }
+}
@@ -0,0 +1,340 @@
+/*
+ ** Shred Latency **
+: Measures, for every turbine shred, how late it arrived relative to two references:
+
+! +-------------+-------------------------------------------------------------------+
+! | Reference   | Delay                                                             |
+! +-------------+-------------------------------------------------------------------+
+! | slot_start  | receive time - estimated wallclock start of the shred's slot      |
+! | first_shred | receive time - receive time of the first shred we got of the slot |
+! +-------------+-------------------------------------------------------------------+
+
+: Repaired shreds are skipped, their delay says more about repair than about turbine.
+: Receive times are taken in the receive thread (`ReceivedShred::received_at`), before the
+: shred is queued for the plugins.
+
+*  ** Slot Start Estimation **
+: With `genesis_timestamp_ms` set, slot N starts at genesis + N * slot_duration. Real slots
+: drift from 400ms over an epoch, so by default the start is learned instead: for the first
+: shred of every slot we compute `offset = first_receive - slot * slot_duration` and keep
+: the smallest offset over the last `learning_window` slots. The first shred always
+: arrives after the slot started, so the minimum is the closest estimate of the real start.
+: Comparing nodes in different data centers is only meaningful if both use the same
+: reference, so set the same genesis timestamp on both when comparing absolute numbers.
+
+*  ** Labels **
+: leader: identity from the configured leader lookup, "unknown" when none is set or it
+:         doesn't know the slot
+: peer:   IP address the shred was received from, ports dropped; only the first
+:         `MAX_PEER_LABELS` addresses get their own label, later ones are "other"
+
+*  ** Leader Schedule **
+: The binary's leader lookup comes from `network.leader_schedule` (`--leader-schedule`), a
+: file written by `solana leader-schedule --output json`. It covers one epoch, slots of
+: other epochs are "unknown" until the file is replaced and the node restarted.
+*/
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    fs,
+    net::IpAddr,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use log::info;
+use serde::Deserialize;
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+
+use crate::{
+    error::{BoxError, Error},
+    metrics::Metrics,
+    types::ReceivedShred,
+};
+
+// slots whose first shred time is kept around for the first_shred delay
+const MAX_TRACKED_SLOTS: usize = 256;
+// source IPs with their own peer label, so spoofed or churning sources can't grow the
+// histogram without bound
+pub const MAX_PEER_LABELS: usize = 64;
+
+pub type LeaderLookup = Arc<dyn Fn(Slot) -> Option<Pubkey> + Send + Sync>;
+
+#[derive(Debug, Clone, Copy)]
+pub struct LatencyConfig {
+    // fixed slot 0 start; None learns the slot start from observations
+    pub genesis_timestamp_ms: Option<u64>,
+    pub slot_duration: Duration,
+    pub learning_window: usize,
+}
+
+impl Default for LatencyConfig {
+    fn default() -> Self {
+        Self {
+            genesis_timestamp_ms: None,
+            slot_duration: Duration::from_millis(400),
+            learning_window: 150,
+        }
+    }
+}
+
+pub struct LatencyTracker {
+    config: LatencyConfig,
+    metrics: Arc<Metrics>,
+    leader_lookup: Option<LeaderLookup>,
+    // slot -> first receive time (µs since epoch)
+    first_shred: BTreeMap<Slot, i128>,
+    // first receive - slot * slot_duration in µs, for the learned slot start
+    offsets: VecDeque<i128>,
+    peer_labels: HashSet<IpAddr>,
+}
+
+impl LatencyTracker {
+    pub fn new(config: LatencyConfig, metrics: Arc<Metrics>) -> Self {
+        Self {
+            config,
+            metrics,
+            leader_lookup: None,
+            first_shred: BTreeMap::new(),
+            offsets: VecDeque::new(),
+            peer_labels: HashSet::new(),
+        }
+    }
+
+    // e.g. backed by a leader schedule fetched over RPC
+    pub fn set_leader_lookup(&mut self, leader_lookup: LeaderLookup) {
+        self.leader_lookup = Some(leader_lookup);
+    }
+
+    pub fn observe(&mut self, received: &ReceivedShred) {
+        if received.is_repaired() {
+            return;
+        }
+
+        let slot = received.shred.slot();
+        let received_at = micros_since_epoch(received.received_at);
+
+        if !self.first_shred.contains_key(&slot) {
+            // older than everything we track, a straggler of a slot we already forgot
+            if self.first_shred.len() >= MAX_TRACKED_SLOTS
+                && self
+                    .first_shred
+                    .first_key_value()
+                    .is_some_and(|(&oldest, _)| slot < oldest)
+            {
+                return;
+            }
+            self.first_shred.insert(slot, received_at);
+            while self.first_shred.len() > MAX_TRACKED_SLOTS {
+                self.first_shred.pop_first();
+            }
+            self.learn(slot, received_at);
+        }
+        let first_shred = self.first_shred[&slot];
+
+        let leader = self
+            .leader_lookup
+            .as_ref()
+            .and_then(|lookup| lookup(slot))
+            .map(|leader| leader.to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+        let peer = self.peer_label(received.from.ip());
+
+        let since_first = seconds(received_at - first_shred);
+        self.record("first_shred", &leader, &peer, since_first);
+
+        if let Some(slot_start) = self.estimate_slot_start(slot) {
+            let since_start = seconds(received_at - slot_start);
+            self.record("slot_start", &leader, &peer, since_start);
+        }
+    }
+
+    fn peer_label(&mut self, ip: IpAddr) -> String {
+        if self.peer_labels.contains(&ip) || self.peer_labels.len() < MAX_PEER_LABELS {
+            self.peer_labels.insert(ip);
+            ip.to_string()
+        } else {
+            "other".to_string()
+        }
+    }
+
+    // estimated start of `slot` in µs since the unix epoch
+    pub fn estimate_slot_start(&self, slot: Slot) -> Option<i128> {
+        let slot_offset = slot as i128 * self.config.slot_duration.as_micros() as i128;
+        match self.config.genesis_timestamp_ms {
+            Some(genesis) => Some(genesis as i128 * 1000 + slot_offset),
+            None => self.offsets.iter().min().map(|offset| offset + slot_offset),
+        }
+    }
+
+    fn learn(&mut self, slot: Slot, first_shred: i128) {
+        if self.config.genesis_timestamp_ms.is_some() {
+            return;
+        }
+        let slot_offset = slot as i128 * self.config.slot_duration.as_micros() as i128;
+        self.offsets.push_back(first_shred - slot_offset);
+        while self.offsets.len() > self.config.learning_window {
+            self.offsets.pop_front();
+        }
+    }
+
+    fn record(&self, reference: &str, leader: &str, peer: &str, delay: f64) {
+        // clock skew or a shred that beat our slot start estimate, nothing to record
+        if delay < 0.0 {
+            return;
+        }
+        self.metrics
+            .shred_latency_seconds
+            .with_label_values(&[reference, leader])
+            .observe(delay);
+        self.metrics
+            .shred_peer_latency_seconds
+            .with_label_values(&[reference, peer])
+            .observe(delay);
+    }
+}
+
+fn micros_since_epoch(time: SystemTime) -> i128 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_micros() as i128
+}
+
+fn seconds(micros: i128) -> f64 {
+    micros as f64 / 1_000_000.0
+}
+
+// `solana leader-schedule --output json`
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LeaderScheduleFile {
+    leader_schedule_entries: Vec<LeaderScheduleEntry>,
+}
+
+#[derive(Deserialize)]
+struct LeaderScheduleEntry {
+    slot: Slot,
+    leader: String,
+}
+
+// slot -> leader from a leader schedule file, see above
+pub fn load_leader_schedule(path: &Path) -> Result<LeaderLookup, Error> {
+    let schedule_error = |source: BoxError| Error::LeaderSchedule {
+        path: path.to_path_buf(),
+        source,
+    };
+    let json = fs::read_to_string(path).map_err(|e| schedule_error(e.into()))?;
+    let file: LeaderScheduleFile =
+        serde_json::from_str(&json).map_err(|e| schedule_error(e.into()))?;
+
+    let mut leaders = HashMap::with_capacity(file.leader_schedule_entries.len());
+    for entry in file.leader_schedule_entries {
+        let leader = Pubkey::from_str(&entry.leader)
+            .map_err(|e| schedule_error(format!("slot {}: {}", entry.slot, e).into()))?;
+        leaders.insert(entry.slot, leader);
+    }
+    info!(
+        "Loaded the leaders of {} slots from {}",
+        leaders.len(),
+        path.display()
+    );
+    Ok(Arc::new(move |slot| leaders.get(&slot).copied()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use super::*;
+    use crate::{shred::test_data_shred, types::ShredSource, utils::parse_shred};
+
+    fn received(slot: Slot, from: Ipv4Addr, at_ms: u64, source: ShredSource) -> ReceivedShred {
+        let payload = test_data_shred(slot, 0, 0, 0, b"entries");
+        ReceivedShred {
+            shred: Arc::new(parse_shred(&payload).unwrap()),
+            source,
+            from: SocketAddr::from((from, 8001)),
+            received_at: UNIX_EPOCH + Duration::from_millis(at_ms),
+        }
+    }
+
+    fn tracker(config: LatencyConfig) -> LatencyTracker {
+        LatencyTracker::new(config, Arc::new(Metrics::new()))
+    }
+
+    const PEER: Ipv4Addr = Ipv4Addr::new(10, 0, 0, 1);
+
+    #[test]
+    fn test_slot_start_from_genesis() {
+        let tracker = tracker(LatencyConfig {
+            genesis_timestamp_ms: Some(1_000),
+            ..LatencyConfig::default()
+        });
+        assert_eq!(tracker.estimate_slot_start(0), Some(1_000_000));
+        assert_eq!(tracker.estimate_slot_start(10), Some(5_000_000));
+    }
+
+    #[test]
+    fn test_slot_start_learned_from_the_earliest_first_shred() {
+        let mut tracker = tracker(LatencyConfig::default());
+        assert_eq!(tracker.estimate_slot_start(10), None);
+
+        // first shreds 50ms and 20ms into their slots, counted from 1s
+        tracker.observe(&received(
+            10,
+            PEER,
+            1_000 + 4_000 + 50,
+            ShredSource::Turbine,
+        ));
+        tracker.observe(&received(
+            11,
+            PEER,
+            1_000 + 4_400 + 20,
+            ShredSource::Turbine,
+        ));
+        // later shreds of a slot don't move the estimate
+        tracker.observe(&received(11, PEER, 1_000 + 4_400 + 1, ShredSource::Turbine));
+        // neither do repaired ones
+        tracker.observe(&received(12, PEER, 1_000, ShredSource::Repair));
+        assert_eq!(tracker.estimate_slot_start(12), Some(1_020_000 + 4_800_000));
+    }
+
+    #[test]
+    fn test_learning_window_forgets_old_offsets() {
+        let mut tracker = tracker(LatencyConfig {
+            learning_window: 1,
+            ..LatencyConfig::default()
+        });
+        tracker.observe(&received(10, PEER, 4_000, ShredSource::Turbine));
+        tracker.observe(&received(11, PEER, 4_400 + 30, ShredSource::Turbine));
+        assert_eq!(tracker.estimate_slot_start(10), Some(30_000 + 4_000_000));
+    }
+
+    #[test]
+    fn test_peer_labels_are_capped() {
+        let mut tracker = tracker(LatencyConfig::default());
+        for n in 0..MAX_PEER_LABELS {
+            let ip = IpAddr::from(Ipv4Addr::new(10, 0, 1, n as u8));
+            assert_eq!(tracker.peer_label(ip), ip.to_string());
+        }
+        let late = IpAddr::from(Ipv4Addr::new(10, 0, 2, 0));
+        assert_eq!(tracker.peer_label(late), "other");
+        // addresses that got a label keep it
+        let early = IpAddr::from(Ipv4Addr::new(10, 0, 1, 0));
+        assert_eq!(tracker.peer_label(early), early.to_string());
+
+        tracker.observe(&received(
+            10,
+            Ipv4Addr::new(10, 0, 2, 1),
+            4_000,
+            ShredSource::Turbine,
+        ));
+        let other = tracker
+            .metrics
+            .shred_peer_latency_seconds
+            .with_label_values(&["first_shred", "other"]);
+        assert_eq!(other.get_sample_count(), 1);
+    }
+}
@@ -1,10 +1,32 @@
+pub mod admin;
+pub mod archive;
+pub mod attribution;
+pub mod config;
+pub mod dynamic;
+pub mod error;
+pub mod filter;
 pub mod gossip;
+pub mod gossip_events;
+pub mod inspect;
+pub mod kafka;
+pub mod latency;
+pub mod logging;
+pub mod metrics;
+pub mod node;
 pub mod output;
+pub mod reject;
+pub mod repair;
 pub mod shred;
-pub mod stats;
+pub mod shutdown;
+pub mod slots;
+pub mod sockets;
+pub mod supervision;
 pub mod types;
 pub mod utils;
+pub mod wasm;
 
 // commonly use types
+pub use error::Error;
+pub use node::{ChainSmoker, ChainSmokerBuilder};
 pub use solana_ledger::shred::Shred;
 pub use solana_sdk::signer::keypair::Keypair;
@@ -0,0 +1,199 @@
+/*
+ ** Logging **
+: Logs go through `tracing`. Records from the `log` crate (ours and the solana crates')
+: are bridged with `tracing-log`, so one filter and one output format covers everything.
+
+*  ** Formats **
+! +------+-------------------------------------------------------------------+
+! | Text | human readable, one line per event (default)                      |
+! | Json | one JSON object per line with timestamp, level, target, fields,   |
+! |      | the current span and the list of parent spans                     |
+! +------+-------------------------------------------------------------------+
+
+*  ** Spans **
+! +------------------+--------------------------------------------------------+
+! | Span             | Where                                                  |
+! +------------------+--------------------------------------------------------+
+! | gossip_discovery | GossipNode::start_discovery                            |
+! | receive_batch    | one batch of shreds taken off the channel (main loop)  |
+! | plugin_dispatch  | one plugin handling one shred/event/report             |
+! +------------------+--------------------------------------------------------+
+
+*  ** Runtime Verbosity **
+: The filter (EnvFilter syntax, RUST_LOG overrides the default) can be swapped while
+: running through `LogHandle::set_filter()`. `watch_log_signals()` maps signals to it:
+:
+: kill -USR1 <pid>   # chainsmoker=debug, then trace
+: kill -USR2 <pid>   # back towards info, then warn
+:
+: Per-shred lines are logged at trace. At info only every `receiver.log_sample`th shred is
+: logged so mainnet rates don't flood stdout (see `ShredReceiver::set_log_sample`).
+*/
+
+use std::{
+    io,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+use crate::error::{BoxError, Error, report};
+use log::info;
+use serde::{Deserialize, Serialize};
+use tokio::signal::unix::{SignalKind, signal};
+use tracing_subscriber::{EnvFilter, Layer, Registry, fmt, layer::SubscriberExt, reload};
+
+pub const DEFAULT_LOG_FILTER: &str = "chainsmoker=info,solana_gossip=warn,solana_metrics=error";
+
+// verbosity steps for the `chainsmoker` target, USR1 moves right and USR2 left
+const LEVELS: [&str; 5] = ["error", "warn", "info", "debug", "trace"];
+const DEFAULT_LEVEL: usize = 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("unknown log format '{}' (text, json)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LoggingConfig {
+    pub format: LogFormat,
+    // EnvFilter directives, RUST_LOG wins if set
+    pub filter: String,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            format: LogFormat::Text,
+            filter: DEFAULT_LOG_FILTER.to_string(),
+        }
+    }
+}
+
+struct FilterState {
+    base: String,
+    level: usize,
+}
+
+// changes the active filter without a restart
+#[derive(Clone)]
+pub struct LogHandle {
+    reload: reload::Handle<EnvFilter, Registry>,
+    state: Arc<Mutex<FilterState>>,
+}
+
+impl LogHandle {
+    pub fn set_filter(&self, filter: &str) -> Result<(), Error> {
+        self.apply(filter)?;
+
+        let mut state = self.state.lock().unwrap();
+        state.base = filter.to_string();
+        state.level = DEFAULT_LEVEL;
+        info!("Log filter set to '{}'", filter);
+        Ok(())
+    }
+
+    pub fn filter(&self) -> String {
+        let state = self.state.lock().unwrap();
+        Self::directives(&state)
+    }
+
+    // moves the chainsmoker target one level up (positive) or down (negative)
+    pub fn step_level(&self, step: isize) -> Result<(), Error> {
+        let mut state = self.state.lock().unwrap();
+        state.level = state
+            .level
+            .saturating_add_signed(step)
+            .min(LEVELS.len() - 1);
+
+        let directives = Self::directives(&state);
+        self.apply(&directives)?;
+        drop(state);
+
+        info!("Log filter set to '{}'", directives);
+        Ok(())
+    }
+
+    fn apply(&self, directives: &str) -> Result<(), Error> {
+        let env_filter = parse_filter(directives)?;
+        self.reload
+            .reload(env_filter)
+            .map_err(|e| log_filter_error(directives, e.into()))
+    }
+
+    fn directives(state: &FilterState) -> String {
+        if state.level == DEFAULT_LEVEL {
+            state.base.clone()
+        } else {
+            // later directives for the same target take precedence
+            format!("{},chainsmoker={}", state.base, LEVELS[state.level])
+        }
+    }
+}
+
+fn parse_filter(directives: &str) -> Result<EnvFilter, Error> {
+    EnvFilter::try_new(directives).map_err(|e| log_filter_error(directives, e.into()))
+}
+
+fn log_filter_error(directives: &str, source: BoxError) -> Error {
+    Error::LogFilter {
+        filter: directives.to_string(),
+        source,
+    }
+}
+
+pub fn init(config: &LoggingConfig) -> Result<LogHandle, Error> {
+    let base = std::env::var("RUST_LOG").unwrap_or_else(|_| config.filter.clone());
+    let (filter, reload) = reload::Layer::new(parse_filter(&base)?);
+
+    let output = match config.format {
+        LogFormat::Text => fmt::layer().with_target(true).boxed(),
+        LogFormat::Json => fmt::layer()
+            .json()
+            .with_current_span(true)
+            .with_span_list(true)
+            .boxed(),
+    };
+
+    // every log record is handed to tracing, the reloadable filter decides what is written
+    tracing_log::LogTracer::init().map_err(|e| Error::LoggingInit { source: e.into() })?;
+    tracing::subscriber::set_global_default(Registry::default().with(filter).with(output))
+        .map_err(|e| Error::LoggingInit { source: e.into() })?;
+
+    Ok(LogHandle {
+        reload,
+        state: Arc::new(Mutex::new(FilterState {
+            base,
+            level: DEFAULT_LEVEL,
+        })),
+    })
+}
+
+pub async fn watch_log_signals(handle: LogHandle) -> io::Result<()> {
+    let mut more = signal(SignalKind::user_defined1())?;
+    let mut less = signal(SignalKind::user_defined2())?;
+
+    loop {
+        let step = tokio::select! {
+            _ = more.recv() => 1,
+            _ = less.recv() => -1,
+        };
+        if let Err(e) = handle.step_level(step) {
+            log::error!("Failed to change log level: {}", report(&e));
+        }
+    }
+}
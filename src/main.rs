@@ -1,28 +1,78 @@
+mod cli;
+
 use std::{
-    net::{IpAddr, UdpSocket},
-    sync::Arc,
-    time::Duration,
+    io::ErrorKind,
+    net::SocketAddr,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, UNIX_EPOCH},
 };
 
+use clap::Parser;
+use log::{debug, error, info};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use solana_sdk::signer::Signer;
+
 use chainsmoker::{
-    Keypair, Shred,
+    ChainSmoker, Shred,
+    archive::{ArchiveReader, ArchiveRecord, ArchiveWriter},
+    config::{Config, LogConfig},
+    error::{BoxError, report},
     gossip::GossipNode,
-    output::{OutputPlugin, PluginRunner},
-    shred::ShredReceiver,
-    types::Network,
+    inspect,
+    kafka::KafkaPlugin,
+    logging::{self, LoggingConfig, watch_log_signals},
+    node::load_identity,
+    output::{OutputPlugin, PluginRegistry, PluginRunner, plugin_settings},
+    shutdown::watch_signals,
+    slots::{SlotTracker, SlotTrackerConfig},
+    sockets::{NodeSockets, SocketConfig},
+    types::{NodeMode, PeerInfo},
+    utils::resolve_entrypoints,
 };
+use cli::{Cli, Command, InspectArgs, NetworkArgs, PeersArgs, ReplayArgs};
 
 // simple console plugin can be grpc/quinn but just console as example
-struct ConsolePlugin;
+#[derive(Default)]
+struct ConsolePlugin {
+    settings: ConsoleSettings,
+    seen: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConsoleSettings {
+    // print every Nth shred
+    every: u64,
+}
+
+impl Default for ConsoleSettings {
+    fn default() -> Self {
+        Self { every: 1 }
+    }
+}
 
 #[async_trait::async_trait]
 impl OutputPlugin for ConsolePlugin {
-    async fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    async fn start(&mut self) -> Result<(), BoxError> {
         println!("Console plugin started");
         Ok(())
     }
 
-    async fn handle_shred(&mut self, shred: Shred) -> Result<(), Box<dyn std::error::Error>> {
+    async fn handle_shred(&mut self, shred: Shred) -> Result<(), BoxError> {
+        self.handle_shared(Arc::new(shred)).await
+    }
+
+    async fn handle_shared(&mut self, shred: Arc<Shred>) -> Result<(), BoxError> {
+        self.seen += 1;
+        if !self.seen.is_multiple_of(self.settings.every.max(1)) {
+            return Ok(());
+        }
         println!(
             "[Plugin] Shred: Slot:{} Index:{} Type:{:?}",
             shred.slot(),
@@ -32,7 +82,7 @@ impl OutputPlugin for ConsolePlugin {
         Ok(())
     }
 
-    async fn stop(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+    async fn stop(&mut self) -> Result<(), BoxError> {
         println!("Console plugin stopped");
         Ok(())
     }
@@ -42,72 +92,361 @@ impl OutputPlugin for ConsolePlugin {
     }
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
-    solana_logger::setup_with_default("chainsmoker=info,solana_gossip=warn,solana_metrics=error");
+fn main() {
+    // Display rather than the Debug output of returning the error from main, with its causes
+    if let Err(e) = run(Cli::parse()) {
+        eprintln!("Error: {}", report(e.as_ref()));
+        std::process::exit(1);
+    }
+}
+
+fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    // --log-format json for one JSON object per line, verbosity via SIGUSR1/SIGUSR2
+    let log_config = LogConfig {
+        format: cli.log_format,
+        filter: cli.log_filter,
+    };
+
+    match cli.command {
+        Command::Listen(args) => {
+            let (config, config_path) = args.config(log_config)?;
+            listen(config, config_path, None)
+        }
+        Command::Record(args) => {
+            let (config, config_path) = args.listen.config(log_config)?;
+            let recording = Recording {
+                archive: ArchiveWriter::create(&args.output)?,
+                duration: args.duration.map(Duration::from_secs),
+            };
+            listen(config, config_path, Some(recording))
+        }
+        command => {
+            logging::init(&LoggingConfig {
+                format: log_config.format,
+                filter: log_config.filter,
+            })?;
+            match command {
+                Command::Peers(args) => peers(args),
+                Command::Replay(args) => replay(args),
+                Command::Inspect(args) => inspect(args),
+                Command::Listen(_) | Command::Record(_) => unreachable!(),
+            }
+        }
+    }
+}
+
+// `record` is `listen` plus an archive every received shred is appended to
+struct Recording {
+    archive: ArchiveWriter,
+    duration: Option<Duration>,
+}
+
+// runs the node described by `config`, which is reloaded on SIGHUP if it came from a file
+fn listen(
+    config: Config,
+    config_path: Option<PathBuf>,
+    recording: Option<Recording>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let log_handle = logging::init(&config.logging_config())?;
+
+    // plugins that can be started by name, from the config or later through the admin endpoint
+    let mut builder = ChainSmoker::builder()
+        .config(config)
+        .plugin_registry(plugin_registry())
+        .log_handle(log_handle.clone());
+    if let Some(config_path) = config_path {
+        builder = builder.reload_from(config_path);
+    }
+    let mut duration = None;
+    if let Some(recording) = recording {
+        builder = builder.archive(recording.archive);
+        duration = recording.duration;
+    }
+    let node = builder.build()?;
 
-    let bind_address: IpAddr = "64.34.80.45".parse().unwrap();
+    // signals are watched from the start so discovery can be interrupted too
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.spawn(watch_signals(node.exit_flag()));
+    rt.spawn(async move {
+        if let Err(e) = watch_log_signals(log_handle).await {
+            error!("Log level signal handler failed: {}", e);
+        }
+    });
+    if let Some(duration) = duration {
+        let exit = node.exit_flag();
+        rt.spawn(async move {
+            tokio::time::sleep(duration).await;
+            info!("Recorded for {:?}, shutting down...", duration);
+            exit.store(true, Ordering::Relaxed);
+        });
+    }
+
+    rt.block_on(node.run())?;
+
+    for peer in node.top_peers(10) {
+        println!(
+            "{:>44} {:>15}: {} packets, {} first, {:.1}% valid, {:.1}% duplicate",
+            peer.label(),
+            peer.addr,
+            peer.packets,
+            peer.first_arrivals,
+            peer.valid_ratio() * 100.0,
+            peer.duplicate_ratio() * 100.0
+        );
+    }
 
-    let identity_keypair = Arc::new(Keypair::new());
+    Ok(())
+}
 
-    let gossip_socket = UdpSocket::bind((bind_address, 8000))?;
-    let tvu_socket = UdpSocket::bind((bind_address, 8001))?;
+// joins gossip as an observer and prints every node it learned about within `--wait`
+fn peers(args: PeersArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let network = args.network.network;
+    let identity_keypair = load_identity(args.network.identity.as_deref())?;
+    info!("Identity {}", identity_keypair.pubkey());
 
-    let gossip_node = GossipNode::new(
+    let entrypoints = resolve_entrypoints(network)?;
+    let socket_config = SocketConfig {
+        mode: NodeMode::Observer,
+        ..socket_config(&args.network)
+    };
+    let sockets = NodeSockets::bind(&socket_config, &entrypoints)?;
+    let gossip_node = GossipNode::new_observer(
         identity_keypair,
-        gossip_socket,
-        &tvu_socket,
-        bind_address,
-        Network::Mainnet,
+        sockets.gossip,
+        sockets.advertise_address,
+        sockets.bind_address,
+        &entrypoints,
+        false,
     )?;
 
-    gossip_node.start_discovery(); // breaks when peers > 100
+    let exit = gossip_node.exit.clone();
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.spawn(watch_signals(exit.clone()));
 
-    println!("finished discovering");
+    // CRDS keeps filling long after the first 100 peers, so wait the full period
+    let deadline = Instant::now() + Duration::from_secs(args.wait);
+    while !exit.load(Ordering::Relaxed) && Instant::now() < deadline {
+        thread::sleep(Duration::from_secs(1));
+        debug!("{} peers known", gossip_node.peer_count());
+    }
 
-    let mut shred_receiver = ShredReceiver::new(Arc::new(tvu_socket));
+    let mut peers = gossip_node.peer_directory();
+    peers.sort_unstable_by_key(|peer| peer.pubkey.to_string());
 
-    // get the receiver BEFORE starting the sender thread to prevent race condition
-    let receiver = shred_receiver.take_receiver();
-    let _shred_handle = shred_receiver.start(); // Start receiving
+    if args.json {
+        let peers: Vec<Value> = peers.iter().map(PeerInfo::to_json).collect();
+        println!("{}", serde_json::to_string_pretty(&peers)?);
+    } else {
+        println!(
+            "{:<44} {:<21} {:<21} {:<21} {:>6} Version",
+            "Pubkey", "Gossip", "TVU", "Serve Repair", "Shred"
+        );
+        for peer in &peers {
+            println!(
+                "{:<44} {:<21} {:<21} {:<21} {:>6} {}",
+                peer.pubkey.to_string(),
+                display_addr(peer.gossip),
+                display_addr(peer.tvu),
+                display_addr(peer.serve_repair),
+                peer.shred_version,
+                peer.version.as_deref().unwrap_or("-")
+            );
+        }
+        println!(
+            "{} peers, {} advertising TVU",
+            peers.len(),
+            peers.iter().filter(|peer| peer.tvu.is_some()).count()
+        );
+    }
 
+    if gossip_node.shutdown().is_err() {
+        error!("Gossip service panicked during shutdown");
+    }
+    Ok(())
+}
+
+// feeds an archive through the slot tracker and plugins as if it was received live
+fn replay(args: ReplayArgs) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = ArchiveReader::open(&args.input)?;
+
+    let plugin_registry = plugin_registry();
     let mut plugin_runner = PluginRunner::new();
-    plugin_runner.add_plugin(Box::new(ConsolePlugin));
+    for name in &args.plugins {
+        plugin_runner.add_plugin(plugin_registry.create(name, &Value::Null)?);
+    }
+    let mut slot_tracker = SlotTracker::new(SlotTrackerConfig::default());
+
+    let exit = Arc::new(AtomicBool::new(false));
+    let rt = tokio::runtime::Runtime::new()?;
+    rt.spawn(watch_signals(exit.clone()));
 
-    let rt = tokio::runtime::Runtime::new().unwrap();
     rt.block_on(async move {
-        plugin_runner.start_all().await.unwrap();
+        plugin_runner.start_all().await?;
 
-        let receiver = std::sync::Arc::new(std::sync::Mutex::new(receiver));
+        let started = Instant::now();
+        let mut first_received_at = None;
+        let (mut replayed, mut skipped) = (0u64, 0u64);
 
-        loop {
-            let receiver_clone = receiver.clone();
+        for record in reader {
+            if exit.load(Ordering::Relaxed) {
+                break;
+            }
+            let record = record?;
 
-            let shred_result = tokio::task::spawn_blocking(move || {
-                let receiver = receiver_clone.lock().unwrap();
-                receiver.recv_timeout(Duration::from_secs(1))
-            })
-            .await;
+            if args.realtime {
+                let first = *first_received_at.get_or_insert(record.received_at);
+                let offset = record.received_at.duration_since(first).unwrap_or_default();
+                tokio::time::sleep((started + offset).saturating_duration_since(Instant::now()))
+                    .await;
+            }
 
-            match shred_result {
-                Ok(Ok(shred)) => {
-                    plugin_runner.handle_shred(shred).await;
-                }
-                Ok(Err(std::sync::mpsc::RecvTimeoutError::Timeout)) => {
-                    continue;
-                }
-                Ok(Err(std::sync::mpsc::RecvTimeoutError::Disconnected)) => {
-                    println!("Shred receiver channel disconnected");
-                    break;
+            match record.to_received() {
+                Ok(received) => {
+                    slot_tracker.observe(&received);
+                    plugin_runner.handle_shred(received).await;
+                    replayed += 1;
                 }
-                Err(_) => {
-                    println!("Error in shred receiver task");
-                    break;
+                Err(e) => {
+                    debug!("Skipping unparseable record from {}: {}", record.from, e);
+                    skipped += 1;
                 }
             }
+
+            for report in slot_tracker.poll() {
+                plugin_runner.handle_slot_report(&report).await;
+            }
+        }
+
+        for report in slot_tracker.flush() {
+            plugin_runner.handle_slot_report(&report).await;
         }
+        plugin_runner.stop_all().await?;
+
+        println!(
+            "Replayed {} shreds from {} ({} skipped) in {:?}",
+            replayed,
+            args.input.display(),
+            skipped,
+            started.elapsed()
+        );
+        Ok(())
+    })
+}
+
+// decodes shreds given as hex, a raw shred file, or the records of an archive
+fn inspect(args: InspectArgs) -> Result<(), Box<dyn std::error::Error>> {
+    if let Some(hex) = &args.hex {
+        let payload = hex::decode(hex.trim().trim_start_matches("0x"))?;
+        return print_inspection(&args, &payload, None);
+    }
 
-        plugin_runner.stop_all().await.unwrap();
+    let Some(path) = &args.file else {
+        return Err("either --hex or --file is required".into());
+    };
+    let reader = match ArchiveReader::open(path) {
+        Ok(reader) => reader,
+        // not an archive, treat the whole file as one shred
+        Err(e) if matches!(e.kind(), ErrorKind::InvalidData | ErrorKind::UnexpectedEof) => {
+            let payload = std::fs::read(path)?;
+            return print_inspection(&args, &payload, None);
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    for record in reader.take(args.limit.unwrap_or(usize::MAX)) {
+        let record = record?;
+        // one bad record shouldn't hide the rest of the archive
+        if let Err(e) = print_inspection(&args, &record.payload, Some(&record)) {
+            println!("{} bytes from {}: {}", record.payload.len(), record.from, e);
+        }
+    }
+    Ok(())
+}
+
+fn print_inspection(
+    args: &InspectArgs,
+    payload: &[u8],
+    record: Option<&ArchiveRecord>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let inspection = inspect::inspect(payload)?;
+    let received_at = record.map(|record| {
+        record
+            .received_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_micros()
     });
 
+    if args.json {
+        let mut value = serde_json::to_value(&inspection)?;
+        match (record, received_at) {
+            // one object per line, so archives can be piped into jq
+            (Some(record), Some(received_at)) => {
+                value["record"] = json!({
+                    "source": format!("{:?}", record.source),
+                    "from": record.from,
+                    "received_at_us": received_at,
+                });
+                println!("{}", value);
+            }
+            _ => println!("{}", serde_json::to_string_pretty(&value)?),
+        }
+        return Ok(());
+    }
+
+    let origin = match (record, received_at) {
+        (Some(record), Some(received_at)) => format!(
+            "{:?} from {} at {}",
+            record.source, record.from, received_at
+        ),
+        _ => String::new(),
+    };
+    if args.brief {
+        println!("{} {}", inspection.summary(), origin);
+        return Ok(());
+    }
+
+    if !origin.is_empty() {
+        println!("Record: {}", origin);
+    }
+    print!("{}", inspection);
+    println!("Payload");
+    print!("{}", inspection.hex_dump());
+    println!();
     Ok(())
 }
+
+fn display_addr(addr: Option<SocketAddr>) -> String {
+    addr.map(|addr| addr.to_string())
+        .unwrap_or_else(|| "-".to_string())
+}
+
+fn socket_config(args: &NetworkArgs) -> SocketConfig {
+    SocketConfig {
+        bind_address: args.bind,
+        advertise_address: args.advertise,
+        port_range: args.port_range,
+        gossip_port: args.gossip_port,
+        tvu_port: args.tvu_port,
+        verify_reachable: !args.no_verify_reachable,
+        ..SocketConfig::default()
+    }
+}
+
+fn plugin_registry() -> PluginRegistry {
+    let mut registry = PluginRegistry::new();
+    registry.register(
+        "Console",
+        Box::new(|settings| {
+            Ok(Box::new(ConsolePlugin {
+                settings: plugin_settings(settings)?,
+                ..ConsolePlugin::default()
+            }))
+        }),
+    );
+    registry.register(
+        "Kafka",
+        Box::new(|settings| Ok(Box::new(KafkaPlugin::from_settings(settings)?))),
+    );
+    registry
+}
@@ -0,0 +1,352 @@
+/*
+ ** Metrics **
+: All counters, gauges and histograms live in one Prometheus registry owned by `Metrics`.
+: Components get an `Arc<Metrics>` and update it in place, `serve()` exposes the registry
+: in the Prometheus text format on `GET /metrics`.
+
+*  ** Exported Metrics **
+! +-------------------------------------------+-----------+----------------------------------+
+! | Name                                      | Type      | Meaning                          |
+! +-------------------------------------------+-----------+----------------------------------+
+! | chainsmoker_packets_received_total        | counter   | UDP packets read from TVU        |
+! | chainsmoker_shreds_parsed_total           | counter   | Packets that parsed as shreds    |
+! | chainsmoker_non_shred_packets_total       | counter   | Packets that failed to parse     |
+! | chainsmoker_shreds_skipped_total{reason}  | counter   | Shreds the receiver dropped      |
+! |                                           |           | unparsed: filter/duplicate       |
+! | chainsmoker_packets_rejected_total        | counter   | Packets that aren't valid shreds |
+! |   {reason, source}                        |           | by reason (see reject.rs)        |
+! | chainsmoker_packets_dropped_total         | counter   | Shreds lost to a closed channel  |
+! |                                           |           | or a socket receive error        |
+! | chainsmoker_plugin_handled_total{plugin}  | counter   | Shreds a plugin handled          |
+! | chainsmoker_plugin_errors_total{plugin}   | counter   | Errors returned by a plugin      |
+! | chainsmoker_plugin_restarts_total{plugin} | counter   | Restarts by its supervision      |
+! | chainsmoker_plugin_health{plugin}         | gauge     | 2 healthy, 1 degraded, 0 down    |
+! |                                           |           | (see supervision.rs)             |
+! | chainsmoker_router_errors_total{router}   | counter   | WASM router traps, fuel running  |
+! |                                           |           | out, bad results (see wasm.rs)   |
+! | chainsmoker_slots_finished_total{status}   | counter   | Slot reports by status:          |
+! |                                           |           | complete/recoverable/incomplete  |
+! | chainsmoker_fec_sets_recoverable_total    | counter   | FEC sets erasure could rebuild   |
+! | chainsmoker_fec_sets_lost_total           | counter   | FEC sets missing too many shreds |
+! | chainsmoker_channel_depth                 | gauge     | Shreds waiting for the plugins   |
+! | chainsmoker_peer_count                    | gauge     | Gossip peers known (all_peers)   |
+! | chainsmoker_top_peer_packets{peer}        | gauge     | Top-N source peers by first      |
+! | chainsmoker_top_peer_first_arrivals{peer} | gauge     | arrivals: packets, first         |
+! | chainsmoker_top_peer_valid_ratio{peer}    | gauge     | arrivals, valid and duplicate    |
+! | chainsmoker_top_peer_duplicate_ratio{peer}| gauge     | ratios (see attribution.rs)      |
+! | chainsmoker_shred_parse_seconds           | histogram | Time to parse one packet         |
+! | chainsmoker_plugin_handle_seconds{plugin} | histogram | Time a plugin spends on a shred  |
+! | chainsmoker_shred_latency_seconds         | histogram | Shred delay after slot start or  |
+! |   {reference, leader}                     |           | first shred (see latency.rs)     |
+! | chainsmoker_shred_peer_latency_seconds    | histogram | Same delays, by source peer IP,  |
+! |   {reference, peer}                       |           | capped (see latency.rs)          |
+! +-------------------------------------------+-----------+----------------------------------+
+
+*  ** Scraping **
+: The endpoint is a bare-bones HTTP/1.1 responder on a tokio TcpListener, one request per
+: connection. Anything other than `GET /metrics` gets a 404. The default port 9464 keeps
+: clear of a Prometheus server on the same host (9090).
+:
+: scrape_configs:
+:   - job_name: chainsmoker
+:     static_configs:
+:       - targets: ["127.0.0.1:9464"]
+*/
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
+
+use log::{debug, error, info};
+use prometheus::{
+    Encoder, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+pub const DEFAULT_METRICS_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9464);
+
+const NAMESPACE: &str = "chainsmoker";
+
+// parsing is in the microseconds, plugins can be much slower (network, disk)
+const PARSE_BUCKETS: &[f64] = &[1e-6, 5e-6, 1e-5, 5e-5, 1e-4, 5e-4, 1e-3, 5e-3];
+const PLUGIN_BUCKETS: &[f64] = &[1e-5, 1e-4, 5e-4, 1e-3, 5e-3, 1e-2, 5e-2, 0.1, 0.5, 1.0];
+// turbine delays, a slot is 400ms and shreds keep coming for a while after it
+const LATENCY_BUCKETS: &[f64] = &[
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.2, 0.3, 0.4, 0.6, 0.8, 1.0, 1.5, 2.0, 5.0,
+];
+
+pub struct Metrics {
+    registry: Registry,
+    pub packets_received: IntCounter,
+    pub shreds_parsed: IntCounter,
+    pub non_shred_packets: IntCounter,
+    pub shreds_skipped: IntCounterVec,
+    pub packets_rejected: IntCounterVec,
+    pub packets_dropped: IntCounter,
+    pub plugin_handled: IntCounterVec,
+    pub plugin_errors: IntCounterVec,
+    pub plugin_restarts: IntCounterVec,
+    pub plugin_health: IntGaugeVec,
+    pub router_errors: IntCounterVec,
+    pub slots_finished: IntCounterVec,
+    pub fec_sets_recoverable: IntCounter,
+    pub fec_sets_lost: IntCounter,
+    pub channel_depth: IntGauge,
+    pub peer_count: IntGauge,
+    pub top_peer_packets: GaugeVec,
+    pub top_peer_first_arrivals: GaugeVec,
+    pub top_peer_valid_ratio: GaugeVec,
+    pub top_peer_duplicate_ratio: GaugeVec,
+    pub shred_parse_seconds: Histogram,
+    pub plugin_handle_seconds: HistogramVec,
+    pub shred_latency_seconds: HistogramVec,
+    pub shred_peer_latency_seconds: HistogramVec,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Metrics {
+    pub fn new() -> Self {
+        let registry = Registry::new();
+
+        let counter = |name: &str, help: &str| {
+            let counter = IntCounter::with_opts(Opts::new(name, help).namespace(NAMESPACE))
+                .expect("valid counter");
+            registry
+                .register(Box::new(counter.clone()))
+                .expect("unique metric");
+            counter
+        };
+        let labeled_counter = |name: &str, help: &str, label: &str| {
+            let counter = IntCounterVec::new(Opts::new(name, help).namespace(NAMESPACE), &[label])
+                .expect("valid counter");
+            registry
+                .register(Box::new(counter.clone()))
+                .expect("unique metric");
+            counter
+        };
+        let gauge = |name: &str, help: &str| {
+            let gauge = IntGauge::with_opts(Opts::new(name, help).namespace(NAMESPACE))
+                .expect("valid gauge");
+            registry
+                .register(Box::new(gauge.clone()))
+                .expect("unique metric");
+            gauge
+        };
+
+        let packets_received = counter("packets_received_total", "UDP packets read from TVU");
+        let shreds_parsed = counter("shreds_parsed_total", "Packets that parsed as shreds");
+        let non_shred_packets = counter("non_shred_packets_total", "Packets that failed to parse");
+        let packets_dropped = counter(
+            "packets_dropped_total",
+            "Shreds lost to a closed channel or a socket receive error",
+        );
+        let plugin_handled =
+            labeled_counter("plugin_handled_total", "Shreds a plugin handled", "plugin");
+        let plugin_errors = labeled_counter(
+            "plugin_errors_total",
+            "Errors returned by a plugin",
+            "plugin",
+        );
+        let plugin_restarts = labeled_counter(
+            "plugin_restarts_total",
+            "Plugin restarts by its supervision policy",
+            "plugin",
+        );
+        let router_errors = labeled_counter(
+            "router_errors_total",
+            "WASM router calls that failed, the shred was routed nowhere",
+            "router",
+        );
+        let plugin_health = IntGaugeVec::new(
+            Opts::new(
+                "plugin_health",
+                "Plugin health: 2 healthy, 1 degraded, 0 unhealthy or not running",
+            )
+            .namespace(NAMESPACE),
+            &["plugin"],
+        )
+        .expect("valid gauge");
+        registry
+            .register(Box::new(plugin_health.clone()))
+            .expect("unique metric");
+        let shreds_skipped = labeled_counter(
+            "shreds_skipped_total",
+            "Shreds the receiver dropped before parsing them",
+            "reason",
+        );
+        let packets_rejected = IntCounterVec::new(
+            Opts::new(
+                "packets_rejected_total",
+                "Packets that aren't valid shreds, by reason and source",
+            )
+            .namespace(NAMESPACE),
+            &["reason", "source"],
+        )
+        .expect("valid counter");
+        registry
+            .register(Box::new(packets_rejected.clone()))
+            .expect("unique metric");
+        let slots_finished =
+            labeled_counter("slots_finished_total", "Slot reports by status", "status");
+        let fec_sets_recoverable = counter(
+            "fec_sets_recoverable_total",
+            "FEC sets erasure recovery could rebuild",
+        );
+        let fec_sets_lost = counter("fec_sets_lost_total", "FEC sets missing too many shreds");
+        let channel_depth = gauge("channel_depth", "Shreds waiting for the plugins");
+        let peer_count = gauge("peer_count", "Gossip peers known");
+
+        let peer_gauge = |name: &str, help: &str| {
+            let gauge = GaugeVec::new(Opts::new(name, help).namespace(NAMESPACE), &["peer"])
+                .expect("valid gauge");
+            registry
+                .register(Box::new(gauge.clone()))
+                .expect("unique metric");
+            gauge
+        };
+        let top_peer_packets = peer_gauge("top_peer_packets", "Packets from a top source peer");
+        let top_peer_first_arrivals = peer_gauge(
+            "top_peer_first_arrivals",
+            "Shreds a top source peer delivered first",
+        );
+        let top_peer_valid_ratio = peer_gauge(
+            "top_peer_valid_ratio",
+            "Share of a top source peer's packets that were shreds",
+        );
+        let top_peer_duplicate_ratio = peer_gauge(
+            "top_peer_duplicate_ratio",
+            "Share of a top source peer's shreds we already had",
+        );
+
+        let shred_parse_seconds = Histogram::with_opts(
+            HistogramOpts::new("shred_parse_seconds", "Time to parse one packet")
+                .namespace(NAMESPACE)
+                .buckets(PARSE_BUCKETS.to_vec()),
+        )
+        .expect("valid histogram");
+        registry
+            .register(Box::new(shred_parse_seconds.clone()))
+            .expect("unique metric");
+
+        let histogram = |name: &str, help: &str, buckets: &[f64], labels: &[&str]| {
+            let histogram = HistogramVec::new(
+                HistogramOpts::new(name, help)
+                    .namespace(NAMESPACE)
+                    .buckets(buckets.to_vec()),
+                labels,
+            )
+            .expect("valid histogram");
+            registry
+                .register(Box::new(histogram.clone()))
+                .expect("unique metric");
+            histogram
+        };
+
+        let plugin_handle_seconds = histogram(
+            "plugin_handle_seconds",
+            "Time a plugin spends on a shred",
+            PLUGIN_BUCKETS,
+            &["plugin"],
+        );
+        let shred_latency_seconds = histogram(
+            "shred_latency_seconds",
+            "Shred receive delay after slot start or the first shred of the slot",
+            LATENCY_BUCKETS,
+            &["reference", "leader"],
+        );
+        let shred_peer_latency_seconds = histogram(
+            "shred_peer_latency_seconds",
+            "Shred receive delay by source peer",
+            LATENCY_BUCKETS,
+            &["reference", "peer"],
+        );
+
+        Self {
+            registry,
+            packets_received,
+            shreds_parsed,
+            non_shred_packets,
+            shreds_skipped,
+            packets_rejected,
+            packets_dropped,
+            plugin_handled,
+            plugin_errors,
+            plugin_restarts,
+            plugin_health,
+            router_errors,
+            slots_finished,
+            fec_sets_recoverable,
+            fec_sets_lost,
+            channel_depth,
+            peer_count,
+            top_peer_packets,
+            top_peer_first_arrivals,
+            top_peer_valid_ratio,
+            top_peer_duplicate_ratio,
+            shred_parse_seconds,
+            plugin_handle_seconds,
+            shred_latency_seconds,
+            shred_peer_latency_seconds,
+        }
+    }
+
+    // Prometheus text exposition format
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        if let Err(e) = TextEncoder::new().encode(&self.registry.gather(), &mut buffer) {
+            error!("Failed to encode metrics: {}", e);
+        }
+        String::from_utf8(buffer).unwrap_or_default()
+    }
+}
+
+pub async fn serve(metrics: Arc<Metrics>, addr: SocketAddr) -> std::io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    info!(
+        "Serving metrics on http://{}/metrics",
+        listener.local_addr()?
+    );
+
+    loop {
+        let (mut stream, peer) = listener.accept().await?;
+        let metrics = metrics.clone();
+
+        tokio::spawn(async move {
+            let mut request = [0u8; 1024];
+            let size = match stream.read(&mut request).await {
+                Ok(size) => size,
+                Err(e) => {
+                    debug!("Metrics request from {} failed: {}", peer, e);
+                    return;
+                }
+            };
+
+            let response = if request[..size].starts_with(b"GET /metrics ") {
+                let body = metrics.encode();
+                format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    prometheus::TEXT_FORMAT,
+                    body.len(),
+                    body
+                )
+            } else {
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    .to_string()
+            };
+
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                debug!("Failed to write metrics response to {}: {}", peer, e);
+            }
+        });
+    }
+}
@@ -0,0 +1,701 @@
+/*
+ ** Embedding **
+: `ChainSmoker` is the whole node behind one handle: identity, sockets, gossip, the shred
+: receiver (and repair client), slot and latency tracking, plugins and the optional
+: metrics/admin endpoints. The `chainsmoker` binary is a thin CLI over it, applications
+: that want shreds in-process build one instead of copying the orchestration.
+
+*  ** Builder **
+! +---------------------------+-----------------------------------------------------+
+! | Setter                    | Default                                             |
+! +---------------------------+-----------------------------------------------------+
+! | config(Config)            | every section at once, e.g. from Config::load       |
+! | network(NetworkConfig)    | mainnet entrypoints, normal mode                    |
+! | sockets(SocketsConfig)    | 0.0.0.0, first free ports in 8000-10000             |
+! | verification(..)          | reachability checked, any shred version             |
+! | receiver(ReceiverConfig)  | batch size, filter, dedup, quarantine, timeouts     |
+! | identity(Arc<Keypair>)    | identity.keypair of the config, else a new keypair  |
+! | plugin(Box<OutputPlugin>) | plugins handed over as instances                    |
+! | plugin_registry(..)       | plugins by name, for config.plugins and admin       |
+! | endpoints(..)             | no metrics/admin server (config() turns them on)    |
+! | log_handle(LogHandle)     | none, logging is left to the application            |
+! | reload_from(path)         | none, else the file is reloaded on SIGHUP           |
+! | archive(ArchiveWriter)    | none, else every received shred is appended to it   |
+! | stream_capacity(usize)    | DEFAULT_STREAM_CAPACITY shreds per subscriber       |
+! | leader_lookup(..)         | network.leader_schedule if set, slot -> leader for  |
+! |                           | latency labels and plugin filters on `leaders`      |
+! +---------------------------+-----------------------------------------------------+
+: `build()` binds the sockets and joins gossip, `run()` then discovers peers, starts the
+: receive threads and the plugins and dispatches until `shutdown()` (or the exit flag is
+: set by a signal), after which it drains, stops the plugins and leaves gossip. A plugin
+: tripping its fatal supervision policy shuts the node down too and is returned from
+: `run()` as `Error::PluginFatal`.
+
+*  ** Example **
+: let node = Arc::new(ChainSmoker::builder().network(network).plugin(plugin).build()?);
+: let mut shreds = node.shred_stream();
+: tokio::spawn({ let node = node.clone(); async move { node.run().await } });
+: while let Ok(received) = shreds.recv().await { ... }
+: node.shutdown();
+
+*  ** Shred Stream **
+: `shred_stream()` is a tokio broadcast receiver fed with every shred the plugins get
+: (after the receiver filter). A subscriber that falls more than `stream_capacity` shreds
+: behind gets `RecvError::Lagged` and skips ahead; it never slows the node down. Nothing
+: is cloned while nobody subscribes.
+*/
+
+use std::{
+    net::UdpSocket,
+    path::{Path, PathBuf},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use crossbeam_channel::RecvTimeoutError;
+use log::{error, info, warn};
+use serde::Serialize;
+use solana_gossip::cluster_info::ClusterInfo;
+use solana_sdk::signer::{Signer, keypair::read_keypair_file};
+use tokio::{sync::broadcast, task::JoinHandle};
+use tracing::Instrument;
+
+use crate::{
+    Keypair,
+    admin::{self, AdminState},
+    archive::ArchiveWriter,
+    attribution::{AttributionConfig, PeerAttribution, PeerTraffic},
+    config::{
+        Config, EndpointsConfig, NetworkConfig, ReceiverConfig, SocketsConfig, VerificationConfig,
+        apply_reload, watch_reload,
+    },
+    error::{BoxError, Error, report},
+    gossip::{GossipNode, peer_directory},
+    gossip_events::GossipEventConfig,
+    latency::{LatencyConfig, LatencyTracker, LeaderLookup, load_leader_schedule},
+    logging::LogHandle,
+    metrics::{self, Metrics},
+    output::{OutputPlugin, PluginRegistry, PluginRunner},
+    reject::Quarantine,
+    repair::{RepairClient, RepairConfig},
+    shred::ShredReceiver,
+    slots::SlotTracker,
+    sockets::NodeSockets,
+    types::{NodeMode, PeerInfo, ReceivedShred, ShredSource},
+};
+
+pub const DEFAULT_STREAM_CAPACITY: usize = 4096;
+
+// peer_count clones every ContactInfo under the CRDS lock, not something to do per batch
+const PEER_COUNT_INTERVAL: Duration = Duration::from_secs(1);
+
+// counters a running node can be asked for at any time, also served on /stats
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct NodeStats {
+    pub packets_received: u64,
+    pub shreds_parsed: u64,
+    pub non_shred_packets: u64,
+    pub packets_dropped: u64,
+    pub channel_depth: i64,
+    pub peer_count: i64,
+}
+
+impl NodeStats {
+    pub fn from_metrics(metrics: &Metrics) -> Self {
+        Self {
+            packets_received: metrics.packets_received.get(),
+            shreds_parsed: metrics.shreds_parsed.get(),
+            non_shred_packets: metrics.non_shred_packets.get(),
+            packets_dropped: metrics.packets_dropped.get(),
+            channel_depth: metrics.channel_depth.get(),
+            peer_count: metrics.peer_count.get(),
+        }
+    }
+}
+
+pub struct ChainSmokerBuilder {
+    config: Config,
+    identity: Option<Arc<Keypair>>,
+    plugins: Vec<Box<dyn OutputPlugin>>,
+    registry: PluginRegistry,
+    endpoints: Option<EndpointsConfig>,
+    log_handle: Option<LogHandle>,
+    reload_path: Option<PathBuf>,
+    archive: Option<ArchiveWriter>,
+    stream_capacity: usize,
+    leader_lookup: Option<LeaderLookup>,
+}
+
+impl Default for ChainSmokerBuilder {
+    fn default() -> Self {
+        Self {
+            config: Config::default(),
+            identity: None,
+            plugins: Vec::new(),
+            registry: PluginRegistry::new(),
+            endpoints: None,
+            log_handle: None,
+            reload_path: None,
+            archive: None,
+            stream_capacity: DEFAULT_STREAM_CAPACITY,
+            leader_lookup: None,
+        }
+    }
+}
+
+impl ChainSmokerBuilder {
+    // all sections of a config file, its endpoints included; logging stays with the caller
+    pub fn config(mut self, config: Config) -> Self {
+        self.endpoints = Some(config.endpoints.clone());
+        self.config = config;
+        self
+    }
+
+    pub fn network(mut self, network: NetworkConfig) -> Self {
+        self.config.network = network;
+        self
+    }
+
+    pub fn sockets(mut self, sockets: SocketsConfig) -> Self {
+        self.config.sockets = sockets;
+        self
+    }
+
+    pub fn verification(mut self, verification: VerificationConfig) -> Self {
+        self.config.verification = verification;
+        self
+    }
+
+    pub fn receiver(mut self, receiver: ReceiverConfig) -> Self {
+        self.config.receiver = receiver;
+        self
+    }
+
+    pub fn identity(mut self, identity: Arc<Keypair>) -> Self {
+        self.identity = Some(identity);
+        self
+    }
+
+    pub fn plugin(mut self, plugin: Box<dyn OutputPlugin>) -> Self {
+        self.plugins.push(plugin);
+        self
+    }
+
+    pub fn plugin_registry(mut self, registry: PluginRegistry) -> Self {
+        self.registry = registry;
+        self
+    }
+
+    pub fn endpoints(mut self, endpoints: EndpointsConfig) -> Self {
+        self.endpoints = Some(endpoints);
+        self
+    }
+
+    pub fn log_handle(mut self, log_handle: LogHandle) -> Self {
+        self.log_handle = Some(log_handle);
+        self
+    }
+
+    pub fn reload_from(mut self, path: impl Into<PathBuf>) -> Self {
+        self.reload_path = Some(path.into());
+        self
+    }
+
+    pub fn archive(mut self, archive: ArchiveWriter) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    pub fn stream_capacity(mut self, capacity: usize) -> Self {
+        self.stream_capacity = capacity.max(1);
+        self
+    }
+
+    // e.g. backed by a leader schedule fetched over RPC
+    pub fn leader_lookup(mut self, leader_lookup: LeaderLookup) -> Self {
+        self.leader_lookup = Some(leader_lookup);
+        self
+    }
+
+    // binds the sockets and joins gossip, nothing is received until run()
+    pub fn build(self) -> Result<ChainSmoker, Error> {
+        let config = self.config;
+        config
+            .validate()
+            .map_err(|problems| Error::InvalidOptions { problems })?;
+
+        let identity = match self.identity {
+            Some(identity) => identity,
+            None => load_identity(config.identity.keypair.as_deref())?,
+        };
+        info!("Identity {}", identity.pubkey());
+
+        let leader_lookup = match (self.leader_lookup, &config.network.leader_schedule) {
+            (Some(leader_lookup), _) => Some(leader_lookup),
+            (None, Some(path)) => Some(load_leader_schedule(path)?),
+            (None, None) => None,
+        };
+
+        let mut plugin_runner = PluginRunner::new();
+        if let Some(leader_lookup) = &leader_lookup {
+            plugin_runner.set_leader_lookup(leader_lookup.clone());
+        }
+        for plugin in self.plugins {
+            plugin_runner.add_plugin(plugin);
+        }
+        for plugin in config.enabled_plugins() {
+            plugin_runner.add_plugin(plugin.create(&self.registry)?);
+            plugin.configure(&mut plugin_runner)?;
+        }
+        plugin_runner.set_routers(config.load_routers()?);
+
+        // public IP is discovered via the entrypoint ip-echo service unless advertise is set
+        let entrypoints = config.entrypoints()?;
+        let sockets = NodeSockets::bind(&config.socket_config(), &entrypoints)?;
+
+        // Observer/Spy only join gossip: no TVU is advertised and no shreds are received
+        let gossip_node = match &sockets.tvu {
+            Some(tvu_socket) => GossipNode::new(
+                identity.clone(),
+                sockets.gossip,
+                tvu_socket,
+                sockets.repair.as_ref(),
+                sockets.advertise_address,
+                sockets.bind_address,
+                &entrypoints,
+            )?,
+            None => GossipNode::new_observer(
+                identity.clone(),
+                sockets.gossip,
+                sockets.advertise_address,
+                sockets.bind_address,
+                &entrypoints,
+                config.network.mode == NodeMode::Spy,
+            )?,
+        };
+
+        let metrics = Arc::new(Metrics::new());
+        plugin_runner.set_metrics(metrics.clone());
+
+        // which retransmitters deliver shreds first, by source IP -> gossip identity
+        let mut attribution = PeerAttribution::new(
+            gossip_node.cluster_info.clone(),
+            AttributionConfig::default(),
+        );
+        attribution.set_metrics(metrics.clone());
+
+        let (shreds, _) = broadcast::channel(self.stream_capacity);
+
+        Ok(ChainSmoker {
+            exit: gossip_node.exit.clone(),
+            cluster_info: gossip_node.cluster_info.clone(),
+            metrics,
+            attribution,
+            shreds,
+            parts: Mutex::new(Some(RunParts {
+                config,
+                identity,
+                gossip_node,
+                tvu: sockets.tvu,
+                repair: sockets.repair,
+                plugin_runner,
+                registry: self.registry,
+                endpoints: self.endpoints,
+                log_handle: self.log_handle,
+                reload_path: self.reload_path,
+                archive: self.archive,
+                leader_lookup,
+            })),
+        })
+    }
+}
+
+// what run() takes over, a node runs once
+struct RunParts {
+    config: Config,
+    identity: Arc<Keypair>,
+    gossip_node: GossipNode,
+    tvu: Option<UdpSocket>,
+    repair: Option<UdpSocket>,
+    plugin_runner: PluginRunner,
+    registry: PluginRegistry,
+    endpoints: Option<EndpointsConfig>,
+    log_handle: Option<LogHandle>,
+    reload_path: Option<PathBuf>,
+    archive: Option<ArchiveWriter>,
+    leader_lookup: Option<LeaderLookup>,
+}
+
+pub struct ChainSmoker {
+    exit: Arc<AtomicBool>,
+    cluster_info: Arc<ClusterInfo>,
+    metrics: Arc<Metrics>,
+    attribution: PeerAttribution,
+    shreds: broadcast::Sender<ReceivedShred>,
+    parts: Mutex<Option<RunParts>>,
+}
+
+impl ChainSmoker {
+    pub fn builder() -> ChainSmokerBuilder {
+        ChainSmokerBuilder::default()
+    }
+
+    // ends run() the same way SIGINT does: drain, stop plugins, leave gossip
+    pub fn shutdown(&self) {
+        self.exit.store(true, Ordering::Relaxed);
+    }
+
+    // the flag shutdown() sets, for shutdown::watch_signals and the like
+    pub fn exit_flag(&self) -> Arc<AtomicBool> {
+        self.exit.clone()
+    }
+
+    pub fn stats(&self) -> NodeStats {
+        NodeStats::from_metrics(&self.metrics)
+    }
+
+    pub fn metrics(&self) -> Arc<Metrics> {
+        self.metrics.clone()
+    }
+
+    pub fn cluster_info(&self) -> Arc<ClusterInfo> {
+        self.cluster_info.clone()
+    }
+
+    pub fn peers(&self) -> Vec<PeerInfo> {
+        peer_directory(&self.cluster_info)
+    }
+
+    // TVU peers with the most first arrivals, see attribution.rs
+    pub fn top_peers(&self, n: usize) -> Vec<PeerTraffic> {
+        self.attribution.top(n)
+    }
+
+    pub fn shred_stream(&self) -> broadcast::Receiver<ReceivedShred> {
+        self.shreds.subscribe()
+    }
+
+    // runs the node until shutdown(), Err(AlreadyRun) if it ran before
+    pub async fn run(&self) -> Result<(), Error> {
+        let parts = self.parts.lock().unwrap().take().ok_or(Error::AlreadyRun)?;
+        let RunParts {
+            mut config,
+            identity,
+            gossip_node,
+            tvu,
+            repair,
+            mut plugin_runner,
+            registry,
+            endpoints,
+            log_handle,
+            reload_path,
+            mut archive,
+            leader_lookup,
+        } = parts;
+        let exit = self.exit.clone();
+        let metrics = self.metrics.clone();
+        let mode = config.network.mode;
+
+        let mut tasks: Vec<JoinHandle<()>> = Vec::new();
+        let (admin_commands, mut admin_receiver) = tokio::sync::mpsc::unbounded_channel();
+        if let Some(endpoints) = endpoints {
+            // Prometheus scrape endpoint, 127.0.0.1:9464/metrics by default
+            let metrics_server = metrics.clone();
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = metrics::serve(metrics_server, endpoints.metrics).await {
+                    error!("Metrics server failed: {}", e);
+                }
+            }));
+
+            // local control plane, 127.0.0.1:9091 by default, see admin.rs for the routes
+            let admin_state = AdminState {
+                cluster_info: self.cluster_info.clone(),
+                metrics: metrics.clone(),
+                log_handle: log_handle.clone(),
+                commands: admin_commands,
+            };
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = admin::serve(admin_state, endpoints.admin).await {
+                    error!("Admin endpoint failed: {}", e);
+                }
+            }));
+        }
+
+        // SIGHUP re-reads the config file, see config.rs for what is applied live
+        let (reload_sender, mut reloads) = tokio::sync::mpsc::unbounded_channel();
+        if let Some(path) = reload_path {
+            tasks.push(tokio::spawn(async move {
+                if let Err(e) = watch_reload(path, reload_sender).await {
+                    error!("Config reload handler failed: {}", e);
+                }
+            }));
+        }
+
+        // breaks when peers > 100 or on shutdown
+        let gossip_node = tokio::task::spawn_blocking(move || {
+            gossip_node.start_discovery();
+            gossip_node
+        })
+        .await
+        .map_err(|e| Error::Io(e.into()))?;
+        info!("Finished discovering");
+
+        if !mode.receives_shreds() {
+            let peers = gossip_node.peer_directory();
+            info!(
+                "{:?} mode: {} peers known, {} advertising TVU",
+                mode,
+                peers.len(),
+                peers.iter().filter(|peer| peer.tvu.is_some()).count()
+            );
+        }
+
+        // votes, EpochSlots, LowestSlot and duplicate shred proofs from gossip
+        let mut event_tap = gossip_node.event_tap(GossipEventConfig::default());
+        let gossip_events = event_tap.take_receiver();
+        let event_handles = event_tap.start();
+
+        let mut receiver = None;
+        let mut shred_handles = Vec::new();
+        if let Some(tvu_socket) = tvu {
+            let mut shred_receiver =
+                ShredReceiver::new(Arc::new(tvu_socket), exit.clone(), metrics.clone());
+            shred_receiver.set_attribution(self.attribution.clone());
+            shred_receiver.set_log_sample(config.receiver.log_sample);
+            shred_receiver.set_shred_version(config.verification.shred_version);
+            shred_receiver.set_filter(config.receiver.filter.clone());
+            shred_receiver.set_drop_duplicates(config.receiver.drop_duplicates);
+            if let Some(dir) = &config.receiver.quarantine_dir {
+                let quarantine = Quarantine::new(dir, config.receiver.quarantine_limit)?;
+                shred_receiver.set_quarantine(quarantine);
+            }
+
+            // get the receiver BEFORE starting the sender thread to prevent race condition
+            receiver = Some(shred_receiver.take_receiver());
+
+            // optional: fill gaps in slots by asking serve-repair peers
+            if let Some(repair_socket) = repair {
+                let repair_client = RepairClient::new(
+                    gossip_node.cluster_info.clone(),
+                    identity,
+                    Arc::new(repair_socket),
+                    shred_receiver.sender(),
+                    shred_receiver.pipeline(ShredSource::Repair),
+                    RepairConfig::default(),
+                    exit.clone(),
+                );
+                shred_receiver.set_repair_observer(repair_client.tracker());
+                shred_handles = repair_client.start();
+            }
+
+            shred_handles.push(shred_receiver.start()); // Start receiving
+            shred_handles.push(self.attribution.start(exit.clone()));
+        }
+
+        // per-slot completeness, reported to plugins once a slot finishes or times out
+        let mut slot_tracker = SlotTracker::new(config.slot_tracker_config());
+        slot_tracker.set_metrics(metrics.clone());
+
+        // shred delay after (estimated) slot start and after the first shred of the slot
+        let mut latency_tracker = LatencyTracker::new(LatencyConfig::default(), metrics.clone());
+        if let Some(leader_lookup) = leader_lookup {
+            latency_tracker.set_leader_lookup(leader_lookup);
+        }
+
+        let shutdown_config = config.shutdown_config();
+        let batch_size = config.receiver.batch_size;
+
+        // a failed start or a plugin that tripped its fatal policy, returned after shutdown
+        let mut outcome = plugin_runner.start_all().await;
+        if outcome.is_err() {
+            exit.store(true, Ordering::Relaxed);
+        }
+
+        let mut peers_counted_at: Option<Instant> = None;
+        while !exit.load(Ordering::Relaxed) {
+            if peers_counted_at.is_none_or(|at| at.elapsed() >= PEER_COUNT_INTERVAL) {
+                metrics.peer_count.set(gossip_node.peer_count() as i64);
+                peers_counted_at = Some(Instant::now());
+            }
+
+            let shred_result = match receiver.clone() {
+                Some(receiver_clone) => {
+                    metrics.channel_depth.set(receiver_clone.len() as i64);
+                    tokio::task::spawn_blocking(move || {
+                        receiver_clone.recv_timeout(Duration::from_secs(1))
+                    })
+                    .await
+                }
+                // gossip-only: nothing to receive, just pace the event loop
+                None => {
+                    tokio::time::sleep(Duration::from_secs(1)).await;
+                    Ok(Err(RecvTimeoutError::Timeout))
+                }
+            };
+
+            match shred_result {
+                Ok(Ok(received)) => {
+                    // take whatever else is already queued along with it
+                    let mut batch = vec![received];
+                    if let Some(receiver) = &receiver {
+                        batch.extend(receiver.try_iter().take(batch_size - 1));
+                    }
+
+                    let span = tracing::debug_span!("receive_batch", shreds = batch.len());
+                    async {
+                        for received in batch {
+                            dispatch(
+                                received,
+                                &mut archive,
+                                &mut slot_tracker,
+                                &mut latency_tracker,
+                                &self.shreds,
+                                &mut plugin_runner,
+                            )
+                            .await;
+                        }
+                    }
+                    .instrument(span)
+                    .await;
+                }
+                Ok(Err(RecvTimeoutError::Timeout)) => {}
+                Ok(Err(RecvTimeoutError::Disconnected)) => {
+                    warn!("Shred receiver channel disconnected");
+                    break;
+                }
+                Err(e) => {
+                    error!("Error in shred receiver task: {}", e);
+                    break;
+                }
+            }
+
+            while let Ok(event) = gossip_events.try_recv() {
+                plugin_runner.handle_gossip_event(&event).await;
+            }
+
+            plugin_runner.poll_batches().await;
+            if let Err(e) = plugin_runner.supervise().await {
+                error!("{}", report(&e));
+                exit.store(true, Ordering::Relaxed);
+                outcome = Err(e);
+            }
+
+            for report in slot_tracker.poll() {
+                plugin_runner.handle_slot_report(&report).await;
+            }
+
+            while let Ok(command) = admin_receiver.try_recv() {
+                admin::handle_command(command, &mut plugin_runner, &registry, &slot_tracker).await;
+            }
+
+            while let Ok(new_config) = reloads.try_recv() {
+                config = apply_reload(
+                    &config,
+                    new_config,
+                    &mut plugin_runner,
+                    &registry,
+                    log_handle.as_ref(),
+                )
+                .await;
+            }
+        }
+
+        // stop receiving first so nothing new lands in the channel while draining
+        exit.store(true, Ordering::Relaxed);
+        for task in tasks {
+            task.abort();
+        }
+        let joined = tokio::task::spawn_blocking(move || {
+            // join every thread, not just the ones before the first that panicked
+            shred_handles
+                .into_iter()
+                .chain(event_handles)
+                .map(|handle| handle.join().is_ok())
+                .fold(true, |all, joined| all && joined)
+        })
+        .await;
+        if !matches!(joined, Ok(true)) {
+            error!("A receiver thread panicked during shutdown");
+        }
+
+        if outcome.is_ok() {
+            if let Some(receiver) = receiver {
+                let deadline = Instant::now() + shutdown_config.drain_timeout;
+                let mut drained = 0;
+                while let Ok(received) = receiver.try_recv() {
+                    dispatch(
+                        received,
+                        &mut archive,
+                        &mut slot_tracker,
+                        &mut latency_tracker,
+                        &self.shreds,
+                        &mut plugin_runner,
+                    )
+                    .await;
+                    drained += 1;
+                    if Instant::now() >= deadline {
+                        warn!("Drain deadline hit after {} shreds", drained);
+                        break;
+                    }
+                }
+                info!("Drained {} shreds", drained);
+            }
+
+            for report in slot_tracker.flush() {
+                plugin_runner.handle_slot_report(&report).await;
+            }
+        }
+
+        let stopped = plugin_runner.stop_all().await;
+
+        if let Some(archive) = archive {
+            match archive.finish() {
+                Ok(records) => info!("Recorded {} shreds", records),
+                Err(e) => error!("Failed to flush archive: {}", e),
+            }
+        }
+
+        if gossip_node.shutdown().is_err() {
+            error!("Gossip service panicked during shutdown");
+        }
+
+        outcome.and(stopped)
+    }
+}
+
+// every received shred goes through here, from the main loop and from the shutdown drain
+async fn dispatch(
+    received: ReceivedShred,
+    archive: &mut Option<ArchiveWriter>,
+    slot_tracker: &mut SlotTracker,
+    latency_tracker: &mut LatencyTracker,
+    stream: &broadcast::Sender<ReceivedShred>,
+    plugin_runner: &mut PluginRunner,
+) {
+    if let Some(archive) = archive
+        && let Err(e) = archive.write(&received)
+    {
+        warn!("Failed to write shred to archive: {}", e);
+    }
+    slot_tracker.observe(&received);
+    latency_tracker.observe(&received);
+    if stream.receiver_count() > 0 {
+        let _ = stream.send(received.clone());
+    }
+    plugin_runner.handle_shred(received).await;
+}
+
+// a fresh identity each run unless a keypair file is given
+pub fn load_identity(path: Option<&Path>) -> Result<Arc<Keypair>, Error> {
+    let keypair = match path {
+        Some(path) => read_keypair_file(path).map_err(|e| Error::Identity {
+            path: path.to_path_buf(),
+            source: BoxError::from(e.to_string()),
+        })?,
+        None => Keypair::new(),
+    };
+    Ok(Arc::new(keypair))
+}
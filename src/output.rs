@@ -1,32 +1,97 @@
 /*
  ** Output Plugin System **
 : The output plugin system provides a simple interface for streaming parsed shreds
-: to downstream consumers. Plugins receive shreds via the `handle_shred` method and
-: can distribute them using any protocol (gRPC, QUIC, WebSocket, file, etc).
+: to downstream consumers. Plugins receive shreds via the `handle_shred` (or
+: `handle_shared`) method and can distribute them using any protocol (gRPC, QUIC,
+: WebSocket, file, etc).
 
 *  ** OutputPlugin Trait **
-: All output plugins must implement four methods:
+: All output plugins must implement start(), stop(), name() and handle_shred(), plugins
+: that read shreds without keeping them also override handle_shared():
 
-! +----------------+----------------------------------------------------+
-! | Method         | Purpose                                            |
-! +----------------+----------------------------------------------------+
-! | start()        | Initialize plugin (setup servers, open files, etc) |
-! | handle_shred() | Process each incoming shred                        |
-! | stop()         | Cleanup plugin resources                           |
-! | name()         | Return plugin identifier for logging               |
-! +----------------+----------------------------------------------------+
+! +-----------------+---------------------------------------------------+
+! | Method          | Purpose                                           |
+! +-----------------+---------------------------------------------------+
+! | start()         | Initialize plugin (setup servers, open files, etc)|
+! | handle_shred()  | Process each incoming shred as an owned `Shred`   |
+! | handle_shared() | Same as an `Arc<Shred>` shared with the other     |
+! |                 | plugins, nothing is copied; defaults to copying   |
+! |                 | the shred into handle_shred()                     |
+! | stop()          | Cleanup plugin resources                          |
+! | name()          | Return plugin identifier for logging              |
+! +-----------------+---------------------------------------------------+
+
+: Optional methods (default to a no-op):
+
+! +----------------------+----------------------------------------------+
+! | Method               | Purpose                                      |
+! +----------------------+----------------------------------------------+
+! | handle_gossip_event()| Votes, EpochSlots, LowestSlot, DuplicateShred|
+! |                      | values seen in gossip (see gossip_events.rs) |
+! | handle_slot_report() | Per-slot completeness once a slot finished   |
+! |                      | or timed out (see slots.rs)                  |
+! | filter()             | The `ShredFilter` the plugin wants, checked  |
+! |                      | by the runner before the shred is cloned     |
+! | handle_batch()       | Several shreds at once, defaults to calling  |
+! |                      | handle_shared() for each, returning the      |
+! |                      | first error once all were handled            |
+! | batch()              | The `BatchConfig` the plugin wants, None for |
+! |                      | one handle_shared() call per shred           |
+! | supervision()        | The `SupervisionConfig` the plugin wants,    |
+! |                      | None for the default (log errors)            |
+! | health()             | Healthy, Degraded or Unhealthy with a reason |
+! |                      | (GET /health, chainsmoker_plugin_health)     |
+! +----------------------+----------------------------------------------+
 
 *  ** Plugin Lifecycle **
 : Plugins follow a simple lifecycle managed by the PluginRunner:
 
 ! 1. start()        -> Plugin initializes (servers start, connections open)
-! 2. handle_shred() -> Called repeatedly for each received shred
+! 2. handle_shared() -> Called repeatedly for each received shred
 ! 3. stop()         -> Plugin cleanup when program exits
 
 *  ** PluginRunner **
 : The PluginRunner manages multiple plugins and distributes shreds to all of them.
-: Each shred is sent to every registered plugin via the `handle_shred` method.
+: Each shred is sent to every registered plugin via the `handle_shared` method. Shreds
+: are `Arc<Shred>` from the receiver on (see `ReceivedShred`), so dispatching to N
+: plugins costs N reference counts, not N payload copies.
 : If a plugin errors, it logs a warning but continues sending to other plugins.
+: With `set_metrics()`, handled shreds, errors and time spent are recorded per plugin
+: (see metrics.rs).
+: Plugins can be paused, resumed, added (`start_plugin()`) and removed while running.
+: Plugins that keep failing are restarted, disabled or stop the node according to their
+: `SupervisionConfig`, and report their own `health()` (see supervision.rs).
+: Plugins added by name are built from a `PluginRegistry` of factories (see admin.rs),
+: which get the plugin's settings from the config file (`[plugins.settings]`, see
+: config.rs) or `Null` when there are none.
+: Each plugin can have a `ShredFilter` (see filter.rs), the one it declares through
+: `filter()` and the one set from the config; shreds that don't match both are skipped
+: for that plugin without being cloned. Filters on `leaders` use the runner's
+: `LeaderLookup` (`set_leader_lookup()`).
+: For plugins with `complete_fec_sets` the runner keeps shreds back per FEC set and hands
+: the set over once all of its data shreds arrived (the set's size comes from a coding
+: shred, LAST_IN_SLOT or the next set starting right after one of its data shreds);
+: shreds of that set arriving later go through directly. Only shreds one of those
+: plugins would get are kept back. Sets that never complete are dropped, at most
+: `MAX_PENDING_FEC_SETS` are kept.
+: WASM routers (`set_routers()`, see wasm.rs) decide per shred which of the plugins
+: they name get it, on top of those plugins' filters.
+
+*  ** Batches **
+: A plugin with a `BatchConfig` (declared through `batch()` or `[plugins.batch]` in the
+: config, which wins) gets its shreds through `handle_batch()` instead: the runner keeps
+: one `Arc<Shred>` per shred, shared by every batching plugin, and hands a plugin its
+: pending shreds once there are `max_shreds` of them or the oldest waited `window_ms`.
+: Windows are checked by `poll_batches()`, which the main loop calls every iteration
+: (at least once a second when no shreds arrive). Pending shreds are delivered before a
+: plugin is stopped or removed.
+
+! +------------+---------+--------------------------------------------+
+! | Field      | Default | Meaning                                    |
+! +------------+---------+--------------------------------------------+
+! | max_shreds | 128     | deliver once this many shreds are pending  |
+! | window_ms  | 100     | or once the oldest pending one is this old |
+! +------------+---------+--------------------------------------------+
 
 *  ** Usage Pattern **
 :
@@ -34,7 +99,8 @@
 : 2. Add to PluginRunner via `add_plugin()`
 : 3. Call `start_all()` to initialize all plugins
 : 4. Feed shreds via `handle_shred()` in a loop
-: 5. Call `stop_all()` for cleanup
+: 5. On shutdown, feed what is left in the channel through the same path (see node.rs)
+: 6. Call `stop_all()` for cleanup
 
 *  ** Example Plugin Implementation **
 :
@@ -42,18 +108,18 @@
 :
 : #[async_trait::async_trait]
 : impl OutputPlugin for MyPlugin {
-:     async fn start(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+:     async fn start(&mut self) -> Result<(), BoxError> {
 :         // Setup your server/connection
 :         Ok(())
 :     }
 :
-:     async fn handle_shred(&mut self, shred: Shred) -> Result<(), Box<dyn std::error::Error>> {
+:     async fn handle_shred(&mut self, shred: Shred) -> Result<(), BoxError> {
 :         // Process/forward the shred
 :         println!("Slot: {}", shred.slot());
 :         Ok(())
 :     }
 :
-:     async fn stop(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+:     async fn stop(&mut self) -> Result<(), BoxError> {
 :         // Cleanup
 :         Ok(())
 :     }
@@ -62,60 +128,1378 @@
 : }
 
 *  ** Thread Safety **
-: Plugins must be Send + Sync as they may be called from async contexts.
+: Plugins must be Send + Sync as they may be called from async contexts. Their errors
+: (`BoxError`) must be Send + Sync as well; the runner wraps them into
+: `Error::Plugin` with the plugin's name (see error.rs).
 */
 
-use log::{info, warn};
+use crate::{
+    error::{BoxError, Error},
+    filter::ShredFilter,
+    gossip_events::GossipEvent,
+    latency::LeaderLookup,
+    metrics::Metrics,
+    slots::{SlotReport, num_data_shreds},
+    supervision::{ErrorPolicy, PluginHealth, PluginState, SupervisionConfig},
+    types::{ReceivedShred, ShredSource},
+    wasm::{RouterStatus, WasmRouter},
+};
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use serde_json::Value;
 use solana_ledger::shred::Shred;
+use solana_sdk::{clock::Slot, pubkey::Pubkey};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tracing::{Instrument, debug_span};
 
 #[async_trait::async_trait]
 pub trait OutputPlugin: Send + Sync {
-    async fn start(&mut self) -> Result<(), Box<dyn std::error::Error>>;
-    async fn handle_shred(&mut self, shred: Shred) -> Result<(), Box<dyn std::error::Error>>;
-    async fn stop(&mut self) -> Result<(), Box<dyn std::error::Error>>;
+    async fn start(&mut self) -> Result<(), BoxError>;
+    async fn stop(&mut self) -> Result<(), BoxError>;
     fn name(&self) -> &str;
+
+    async fn handle_shred(&mut self, shred: Shred) -> Result<(), BoxError>;
+
+    // what the runner calls; it keeps its own Arc, so this default always copies the
+    // shred for handle_shred(), override it to read shreds without copying them
+    async fn handle_shared(&mut self, shred: Arc<Shred>) -> Result<(), BoxError> {
+        self.handle_shred(Arc::unwrap_or_clone(shred)).await
+    }
+
+    async fn handle_gossip_event(&mut self, _event: &GossipEvent) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    async fn handle_slot_report(&mut self, _report: &SlotReport) -> Result<(), BoxError> {
+        Ok(())
+    }
+
+    // read once when the plugin is added, the config filter narrows it further
+    fn filter(&self) -> ShredFilter {
+        ShredFilter::default()
+    }
+
+    // only called for plugins with a BatchConfig. One failed shred doesn't cost the rest
+    // of the batch, the first error is returned after all were handled
+    async fn handle_batch(&mut self, shreds: &[Arc<Shred>]) -> Result<(), BoxError> {
+        let mut first_error = None;
+        for shred in shreds {
+            if let Err(e) = self.handle_shared(shred.clone()).await {
+                first_error.get_or_insert(e);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    // read once when the plugin is added, `[plugins.batch]` in the config overrides it
+    fn batch(&self) -> Option<BatchConfig> {
+        None
+    }
+
+    // read once when the plugin is added, `[plugins.supervision]` overrides it
+    fn supervision(&self) -> Option<SupervisionConfig> {
+        None
+    }
+
+    // asked every main loop iteration, for GET /health and chainsmoker_plugin_health
+    fn health(&self) -> PluginHealth {
+        PluginHealth::Healthy
+    }
+}
+
+pub const DEFAULT_BATCH_MAX_SHREDS: usize = 128;
+pub const DEFAULT_BATCH_WINDOW_MS: u64 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BatchConfig {
+    pub max_shreds: usize,
+    pub window_ms: u64,
+}
+
+impl Default for BatchConfig {
+    fn default() -> Self {
+        Self {
+            max_shreds: DEFAULT_BATCH_MAX_SHREDS,
+            window_ms: DEFAULT_BATCH_WINDOW_MS,
+        }
+    }
+}
+
+impl BatchConfig {
+    pub fn window(&self) -> Duration {
+        Duration::from_millis(self.window_ms)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_shreds == 0 {
+            return Err("max_shreds must be at least 1".to_string());
+        }
+        if self.window_ms == 0 {
+            return Err("window_ms must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+// FEC sets kept back for plugins with `complete_fec_sets`, oldest dropped first
+pub const MAX_PENDING_FEC_SETS: usize = 1024;
+
+// free-form per-plugin settings, each plugin deserializes what it needs
+pub type PluginSettings = Value;
+
+// a plugin the runner can build by name, e.g. when added through the admin endpoint
+pub type PluginFactory =
+    Box<dyn Fn(&PluginSettings) -> Result<Box<dyn OutputPlugin>, BoxError> + Send + Sync>;
+
+// settings as `T`, `T::default()` when the plugin has none
+pub fn plugin_settings<T: DeserializeOwned + Default>(
+    settings: &PluginSettings,
+) -> Result<T, serde_json::Error> {
+    if settings.is_null() {
+        return Ok(T::default());
+    }
+    serde_json::from_value(settings.clone())
+}
+
+#[derive(Default)]
+pub struct PluginRegistry {
+    factories: HashMap<String, PluginFactory>,
+}
+
+impl PluginRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, name: &str, factory: PluginFactory) {
+        self.factories.insert(name.to_string(), factory);
+    }
+
+    pub fn create(
+        &self,
+        name: &str,
+        settings: &PluginSettings,
+    ) -> Result<Box<dyn OutputPlugin>, Error> {
+        let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| Error::UnknownPlugin {
+                name: name.to_string(),
+                available: self.names().join(", "),
+            })?;
+        factory(settings).map_err(|e| Error::plugin(name, "load its settings", e))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.factories.contains_key(name)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.factories.keys().cloned().collect();
+        names.sort();
+        names
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PluginStatus {
+    pub name: String,
+    pub paused: bool,
+    pub handled: u64,
+    pub errors: u64,
+    pub declared_filter: ShredFilter,
+    pub filter: ShredFilter,
+    pub batch: Option<BatchConfig>,
+    pub pending: usize,
+    pub state: PluginState,
+    pub health: PluginHealth,
+    pub supervision: SupervisionConfig,
+    pub consecutive_errors: u32,
+    pub restarts: u64,
+    pub last_error: Option<String>,
+}
+
+struct PluginEntry {
+    plugin: Box<dyn OutputPlugin>,
+    // from OutputPlugin::filter() and from the config, a shred has to match both
+    declared: ShredFilter,
+    filter: ShredFilter,
+    // from OutputPlugin::batch() and from the config, which wins
+    declared_batch: Option<BatchConfig>,
+    batch: Option<BatchConfig>,
+    pending: Vec<Arc<Shred>>,
+    pending_since: Option<Instant>,
+    // from OutputPlugin::supervision() and from the config, which wins
+    declared_supervision: Option<SupervisionConfig>,
+    supervision: Option<SupervisionConfig>,
+    state: PluginState,
+    consecutive_errors: u32,
+    // restarts since the plugin last succeeded at something, for max_restarts
+    failed_restarts: u32,
+    restart_at: Option<Instant>,
+    restarts: u64,
+    last_error: Option<String>,
+    paused: bool,
+    handled: u64,
+    errors: u64,
+}
+
+impl PluginEntry {
+    // paused and tripped plugins are skipped for shreds, gossip events and slot reports
+    fn is_active(&self) -> bool {
+        !self.paused && self.state == PluginState::Running
+    }
+
+    fn matches(&self, shred: &Shred, source: ShredSource, leader: Option<&Pubkey>) -> bool {
+        self.declared.matches(shred, source, leader) && self.filter.matches(shred, source, leader)
+    }
+
+    fn waits_for_fec_sets(&self) -> bool {
+        self.declared.complete_fec_sets || self.filter.complete_fec_sets
+    }
+
+    fn uses_leaders(&self) -> bool {
+        self.declared.leaders.is_some() || self.filter.leaders.is_some()
+    }
+
+    fn batch_config(&self) -> Option<BatchConfig> {
+        self.batch.or(self.declared_batch)
+    }
+
+    fn batch_due(&self, now: Instant) -> bool {
+        match (self.batch_config(), self.pending_since) {
+            (Some(batch), Some(since)) => now.duration_since(since) >= batch.window(),
+            // batching was turned off with shreds still pending
+            (None, Some(_)) => true,
+            (_, None) => false,
+        }
+    }
+
+    fn supervision_config(&self) -> SupervisionConfig {
+        self.supervision
+            .or(self.declared_supervision)
+            .unwrap_or_default()
+    }
+
+    fn record_ok(&mut self) {
+        self.consecutive_errors = 0;
+        self.failed_restarts = 0;
+    }
+
+    fn record_error(&mut self, metrics: Option<&Metrics>, what: &str, error: &BoxError) {
+        warn!("Plugin {} {}error: {}", self.plugin.name(), what, error);
+        self.errors += 1;
+        self.last_error = Some(error.to_string());
+        if let Some(metrics) = metrics {
+            metrics
+                .plugin_errors
+                .with_label_values(&[self.plugin.name()])
+                .inc();
+        }
+
+        self.consecutive_errors += 1;
+        let supervision = self.supervision_config();
+        if self.consecutive_errors < supervision.max_consecutive_errors {
+            return;
+        }
+        match supervision.on_error {
+            ErrorPolicy::Log => {}
+            ErrorPolicy::Restart => self.schedule_restart(&supervision),
+            ErrorPolicy::Disable => self.disable(),
+            ErrorPolicy::Fatal => {
+                error!(
+                    "Plugin {} failed {} times in a row, shutting down",
+                    self.plugin.name(),
+                    self.consecutive_errors
+                );
+                self.state = PluginState::Failed;
+            }
+        }
+    }
+
+    // Err only for plugins whose failure should abort start_all()
+    fn start_failed(&mut self, error: BoxError) -> Result<(), Error> {
+        let supervision = self.supervision_config();
+        if supervision.on_error == ErrorPolicy::Fatal {
+            self.state = PluginState::Failed;
+            return Err(Error::plugin(self.plugin.name(), "start", error));
+        }
+        error!("Plugin {} failed to start: {}", self.plugin.name(), error);
+        self.last_error = Some(error.to_string());
+        match supervision.on_error {
+            ErrorPolicy::Restart => self.schedule_restart(&supervision),
+            _ => self.disable(),
+        }
+        Ok(())
+    }
+
+    fn schedule_restart(&mut self, supervision: &SupervisionConfig) {
+        if self.failed_restarts >= supervision.max_restarts {
+            self.disable();
+            return;
+        }
+        let backoff = supervision.backoff(self.failed_restarts);
+        warn!("Restarting plugin {} in {:?}", self.plugin.name(), backoff);
+        self.state = PluginState::Restarting;
+        self.restart_at = Some(Instant::now() + backoff);
+        self.drop_pending();
+    }
+
+    fn disable(&mut self) {
+        error!("Disabled plugin {}", self.plugin.name());
+        self.state = PluginState::Disabled;
+        self.restart_at = None;
+        self.drop_pending();
+    }
+
+    fn drop_pending(&mut self) {
+        if !self.pending.is_empty() {
+            warn!(
+                "Dropped {} shreds pending for plugin {}",
+                self.pending.len(),
+                self.plugin.name()
+            );
+        }
+        self.pending.clear();
+        self.pending_since = None;
+    }
+
+    // stop() and start() again, backing off further if start() fails
+    async fn restart(&mut self, metrics: Option<&Metrics>) -> Result<(), Error> {
+        let name = self.plugin.name().to_string();
+        self.restarts += 1;
+        self.failed_restarts += 1;
+        if let Some(metrics) = metrics {
+            metrics.plugin_restarts.with_label_values(&[&name]).inc();
+        }
+
+        if let Err(e) = self.plugin.stop().await {
+            warn!("Plugin {} failed to stop for a restart: {}", name, e);
+        }
+        match self.plugin.start().await {
+            Ok(()) => {
+                info!("Restarted {} plugin", name);
+                self.state = PluginState::Running;
+                self.consecutive_errors = 0;
+                self.restart_at = None;
+                Ok(())
+            }
+            Err(e) => {
+                warn!("Plugin {} failed to restart: {}", name, e);
+                self.last_error = Some(e.to_string());
+                self.schedule_restart(&self.supervision_config());
+                Err(Error::plugin(&name, "restart", e))
+            }
+        }
+    }
 }
 
 pub struct PluginRunner {
-    plugins: Vec<Box<dyn OutputPlugin>>,
+    plugins: Vec<PluginEntry>,
+    metrics: Option<Arc<Metrics>>,
+    leader_lookup: Option<LeaderLookup>,
+    fec_sets: FecSetBuffer,
+    routers: Vec<WasmRouter>,
+}
+
+impl Default for PluginRunner {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PluginRunner {
     pub fn new() -> Self {
         Self {
             plugins: Vec::new(),
+            metrics: None,
+            leader_lookup: None,
+            fec_sets: FecSetBuffer::default(),
+            routers: Vec::new(),
         }
     }
 
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    // replaces every router, see wasm.rs
+    pub fn set_routers(&mut self, routers: Vec<WasmRouter>) {
+        for router in &routers {
+            let unknown: Vec<&str> = router
+                .routes()
+                .iter()
+                .map(String::as_str)
+                .filter(|route| {
+                    !self
+                        .plugins
+                        .iter()
+                        .any(|entry| entry.plugin.name() == *route)
+                })
+                .collect();
+            if !unknown.is_empty() {
+                warn!(
+                    "Router {} routes to plugins that don't run: {}",
+                    router.name(),
+                    unknown.join(", ")
+                );
+            }
+        }
+        self.routers = routers;
+    }
+
+    pub fn router_status(&self) -> Vec<RouterStatus> {
+        self.routers.iter().map(WasmRouter::status).collect()
+    }
+
+    // slot -> leader, for plugin filters on `leaders`
+    pub fn set_leader_lookup(&mut self, leader_lookup: LeaderLookup) {
+        self.leader_lookup = Some(leader_lookup);
+    }
+
     pub fn add_plugin(&mut self, plugin: Box<dyn OutputPlugin>) {
-        self.plugins.push(plugin);
+        let declared = plugin.filter();
+        if !declared.is_empty() {
+            info!("{} plugin declares filter {:?}", plugin.name(), declared);
+        }
+        let declared_batch = plugin.batch();
+        if let Some(batch) = declared_batch {
+            info!("{} plugin declares batches of {:?}", plugin.name(), batch);
+        }
+        let declared_supervision = plugin.supervision();
+        self.plugins.push(PluginEntry {
+            plugin,
+            declared,
+            filter: ShredFilter::default(),
+            declared_batch,
+            batch: None,
+            pending: Vec::new(),
+            pending_since: None,
+            declared_supervision,
+            supervision: None,
+            state: PluginState::Running,
+            consecutive_errors: 0,
+            failed_restarts: 0,
+            restart_at: None,
+            restarts: 0,
+            last_error: None,
+            paused: false,
+            handled: 0,
+            errors: 0,
+        });
     }
 
-    pub async fn start_all(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        for plugin in &mut self.plugins {
-            plugin.start().await?;
-            info!("Started {} plugin", plugin.name());
+    // for plugins added while running, `start_all()` has already happened
+    pub async fn start_plugin(&mut self, mut plugin: Box<dyn OutputPlugin>) -> Result<(), Error> {
+        if self.position(plugin.name()).is_some() {
+            return Err(Error::PluginRunning {
+                name: plugin.name().to_string(),
+            });
+        }
+        plugin
+            .start()
+            .await
+            .map_err(|e| Error::plugin(plugin.name(), "start", e))?;
+        info!("Started {} plugin", plugin.name());
+        self.add_plugin(plugin);
+        if let Some(entry) = self.plugins.last() {
+            self.warn_without_leaders(entry);
         }
         Ok(())
     }
 
-    pub async fn handle_shred(&mut self, shred: Shred) {
-        for plugin in &mut self.plugins {
-            if let Err(e) = plugin.handle_shred(shred.clone()).await {
-                warn!("Plugin {} error: {}", plugin.name(), e);
-            }
+    pub async fn remove_plugin(&mut self, name: &str) -> Result<(), Error> {
+        let index = self.index_of(name)?;
+        let mut entry = self.plugins.remove(index);
+        flush_batch(&mut entry, self.metrics.as_deref()).await;
+        entry
+            .plugin
+            .stop()
+            .await
+            .map_err(|e| Error::plugin(name, "stop", e))?;
+        info!("Removed {} plugin", name);
+        Ok(())
+    }
+
+    // paused plugins are skipped for shreds, gossip events and slot reports
+    pub fn set_paused(&mut self, name: &str, paused: bool) -> Result<(), Error> {
+        let index = self.index_of(name)?;
+        self.plugins[index].paused = paused;
+        info!(
+            "{} {} plugin",
+            if paused { "Paused" } else { "Resumed" },
+            name
+        );
+        Ok(())
+    }
+
+    pub fn set_filter(&mut self, name: &str, filter: ShredFilter) -> Result<(), Error> {
+        let index = self.index_of(name)?;
+        if self.plugins[index].filter != filter {
+            info!("Set {} plugin filter to {:?}", name, filter);
+            self.plugins[index].filter = filter;
+            self.warn_without_leaders(&self.plugins[index]);
         }
+        Ok(())
     }
 
-    pub async fn stop_all(&mut self) -> Result<(), Box<dyn std::error::Error>> {
-        for plugin in &mut self.plugins {
-            plugin.stop().await?;
-            info!("Stopped {} plugin", plugin.name());
+    // None goes back to what the plugin declared
+    pub fn set_batch(&mut self, name: &str, batch: Option<BatchConfig>) -> Result<(), Error> {
+        let index = self.index_of(name)?;
+        if self.plugins[index].batch != batch {
+            info!("Set {} plugin batches to {:?}", name, batch);
+            self.plugins[index].batch = batch;
         }
         Ok(())
     }
 
+    // None goes back to what the plugin declared
+    pub fn set_supervision(
+        &mut self,
+        name: &str,
+        supervision: Option<SupervisionConfig>,
+    ) -> Result<(), Error> {
+        let index = self.index_of(name)?;
+        if self.plugins[index].supervision != supervision {
+            info!("Set {} plugin supervision to {:?}", name, supervision);
+            self.plugins[index].supervision = supervision;
+        }
+        Ok(())
+    }
+
+    // restarts a plugin right away, whatever its state, e.g. one that was disabled
+    pub async fn restart_plugin(&mut self, name: &str) -> Result<(), Error> {
+        let index = self.index_of(name)?;
+        let metrics = self.metrics.as_deref();
+        let entry = &mut self.plugins[index];
+        entry.failed_restarts = 0;
+        entry.restart(metrics).await
+    }
+
+    fn warn_without_leaders(&self, entry: &PluginEntry) {
+        if entry.uses_leaders() && self.leader_lookup.is_none() {
+            warn!(
+                "{} plugin filters on leaders but there is no leader lookup, it gets no shreds",
+                entry.plugin.name()
+            );
+        }
+    }
+
+    pub fn status(&self) -> Vec<PluginStatus> {
+        self.plugins
+            .iter()
+            .map(|entry| PluginStatus {
+                name: entry.plugin.name().to_string(),
+                paused: entry.paused,
+                handled: entry.handled,
+                errors: entry.errors,
+                declared_filter: entry.declared.clone(),
+                filter: entry.filter.clone(),
+                batch: entry.batch_config(),
+                pending: entry.pending.len(),
+                state: entry.state,
+                health: entry.plugin.health(),
+                supervision: entry.supervision_config(),
+                consecutive_errors: entry.consecutive_errors,
+                restarts: entry.restarts,
+                last_error: entry.last_error.clone(),
+            })
+            .collect()
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.plugins
+            .iter()
+            .position(|entry| entry.plugin.name() == name)
+    }
+
+    fn index_of(&self, name: &str) -> Result<usize, Error> {
+        self.position(name).ok_or_else(|| Error::NoSuchPlugin {
+            name: name.to_string(),
+        })
+    }
+
+    // a plugin that fails to start is handled by its supervision policy, only a fatal
+    // one makes this fail
+    pub async fn start_all(&mut self) -> Result<(), Error> {
+        for entry in &mut self.plugins {
+            match entry.plugin.start().await {
+                Ok(()) => info!("Started {} plugin", entry.plugin.name()),
+                Err(e) => entry.start_failed(e)?,
+            }
+        }
+        for entry in &self.plugins {
+            self.warn_without_leaders(entry);
+        }
+        Ok(())
+    }
+
+    // `received.source` is checked against plugin filters, see filter.rs
+    pub async fn handle_shred(&mut self, received: ReceivedShred) {
+        let ReceivedShred { shred, source, .. } = received;
+        let metrics = self.metrics.as_deref();
+        let leader = match &self.leader_lookup {
+            Some(lookup) if self.plugins.iter().any(PluginEntry::uses_leaders) => {
+                lookup(shred.slot())
+            }
+            _ => None,
+        };
+
+        let masks = route(&mut self.routers, &shred, metrics);
+        let mut held_back = false;
+        for entry in self.plugins.iter_mut().filter(|entry| entry.is_active()) {
+            if !entry.matches(&shred, source, leader.as_ref())
+                || !routed(&self.routers, &masks, entry.plugin.name())
+            {
+                continue;
+            }
+            if entry.waits_for_fec_sets() {
+                held_back = true;
+            } else {
+                dispatch(entry, &shred, metrics).await;
+            }
+        }
+        if !held_back {
+            return;
+        }
+
+        // the set `shred` completed, or `shred` alone when its set already was; each with
+        // the masks routed on arrival, routers only see a shred once
+        for ready in self.fec_sets.insert(&shred, source, masks) {
+            let routers = &self.routers;
+            let active = self.plugins.iter_mut().filter(|entry| {
+                entry.is_active()
+                    && entry.waits_for_fec_sets()
+                    && entry.matches(&ready.shred, ready.source, leader.as_ref())
+                    && routed(routers, &ready.masks, entry.plugin.name())
+            });
+            for entry in active {
+                dispatch(entry, &ready.shred, metrics).await;
+            }
+        }
+    }
+
+    pub async fn handle_gossip_event(&mut self, event: &GossipEvent) {
+        let metrics = self.metrics.as_deref();
+        for entry in self.plugins.iter_mut().filter(|entry| entry.is_active()) {
+            let span = debug_span!("plugin_dispatch", plugin = entry.plugin.name());
+            match entry
+                .plugin
+                .handle_gossip_event(event)
+                .instrument(span)
+                .await
+            {
+                Ok(()) => entry.record_ok(),
+                Err(e) => entry.record_error(metrics, "gossip event ", &e),
+            }
+        }
+    }
+
+    pub async fn handle_slot_report(&mut self, report: &SlotReport) {
+        let metrics = self.metrics.as_deref();
+        for entry in self.plugins.iter_mut().filter(|entry| entry.is_active()) {
+            let span = debug_span!(
+                "plugin_dispatch",
+                plugin = entry.plugin.name(),
+                slot = report.slot
+            );
+            match entry
+                .plugin
+                .handle_slot_report(report)
+                .instrument(span)
+                .await
+            {
+                Ok(()) => entry.record_ok(),
+                Err(e) => entry.record_error(metrics, "slot report ", &e),
+            }
+        }
+    }
+
+    // hands over batches whose window passed
+    pub async fn poll_batches(&mut self) {
+        let metrics = self.metrics.as_deref();
+        let now = Instant::now();
+        let due = self
+            .plugins
+            .iter_mut()
+            .filter(|entry| entry.is_active() && entry.batch_due(now));
+        for entry in due {
+            flush_batch(entry, metrics).await;
+        }
+    }
+
+    // restarts plugins whose backoff ran out and updates chainsmoker_plugin_health;
+    // Err once a plugin with the fatal policy tripped, the node should shut down
+    pub async fn supervise(&mut self) -> Result<(), Error> {
+        let metrics = self.metrics.as_deref();
+        let now = Instant::now();
+        for entry in &mut self.plugins {
+            if entry.state == PluginState::Restarting
+                && entry.restart_at.is_some_and(|at| at <= now)
+            {
+                // failures are logged and backed off inside
+                let _ = entry.restart(metrics).await;
+            }
+            if let Some(metrics) = metrics {
+                metrics
+                    .plugin_health
+                    .with_label_values(&[entry.plugin.name()])
+                    .set(entry.plugin.health().gauge(entry.state));
+            }
+        }
+
+        match self
+            .plugins
+            .iter()
+            .find(|entry| entry.state == PluginState::Failed)
+        {
+            Some(entry) => Err(Error::PluginFatal {
+                plugin: entry.plugin.name().to_string(),
+                reason: entry.last_error.clone().unwrap_or_default(),
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // stops every running plugin even if one fails, returns the first error and logs the
+    // others. Disabled, failed and restarting plugins aren't running and are skipped
+    pub async fn stop_all(&mut self) -> Result<(), Error> {
+        let metrics = self.metrics.as_deref();
+        let mut first_error = None;
+        for entry in &mut self.plugins {
+            if entry.state != PluginState::Running {
+                continue;
+            }
+            flush_batch(entry, metrics).await;
+            let plugin = &mut entry.plugin;
+            match plugin.stop().await {
+                Ok(()) => info!("Stopped {} plugin", plugin.name()),
+                Err(e) => {
+                    let e = Error::plugin(plugin.name(), "stop", e);
+                    match first_error {
+                        Some(_) => error!("{}", e),
+                        None => first_error = Some(e),
+                    }
+                }
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
     pub fn plugin_count(&self) -> usize {
         self.plugins.len()
     }
 }
+
+// one bitmask per router, see wasm.rs
+fn route(routers: &mut [WasmRouter], shred: &Shred, metrics: Option<&Metrics>) -> Vec<u64> {
+    routers
+        .iter_mut()
+        .map(|router| router.route(shred, metrics))
+        .collect()
+}
+
+// plugins no router names get every shred
+fn routed(routers: &[WasmRouter], masks: &[u64], plugin: &str) -> bool {
+    let mut named = false;
+    for (router, mask) in routers.iter().zip(masks) {
+        if let Some(bit) = router.route_index(plugin) {
+            if mask >> bit & 1 == 1 {
+                return true;
+            }
+            named = true;
+        }
+    }
+    !named
+}
+
+async fn dispatch(entry: &mut PluginEntry, shred: &Arc<Shred>, metrics: Option<&Metrics>) {
+    if let Some(batch) = entry.batch_config() {
+        entry.pending_since.get_or_insert_with(Instant::now);
+        entry.pending.push(shred.clone());
+        if entry.pending.len() >= batch.max_shreds {
+            flush_batch(entry, metrics).await;
+        }
+        return;
+    }
+
+    let started = Instant::now();
+    let span = debug_span!(
+        "plugin_dispatch",
+        plugin = entry.plugin.name(),
+        slot = shred.slot(),
+        index = shred.index()
+    );
+    let result = entry
+        .plugin
+        .handle_shared(shred.clone())
+        .instrument(span)
+        .await;
+    record_handled(entry, 1, started, result, metrics);
+}
+
+async fn flush_batch(entry: &mut PluginEntry, metrics: Option<&Metrics>) {
+    entry.pending_since = None;
+    if entry.pending.is_empty() {
+        return;
+    }
+    let batch = std::mem::take(&mut entry.pending);
+
+    let started = Instant::now();
+    let span = debug_span!(
+        "plugin_dispatch",
+        plugin = entry.plugin.name(),
+        shreds = batch.len()
+    );
+    let result = entry.plugin.handle_batch(&batch).instrument(span).await;
+    record_handled(entry, batch.len(), started, result, metrics);
+}
+
+fn record_handled(
+    entry: &mut PluginEntry,
+    shreds: usize,
+    started: Instant,
+    result: Result<(), BoxError>,
+    metrics: Option<&Metrics>,
+) {
+    if let Some(metrics) = metrics {
+        metrics
+            .plugin_handle_seconds
+            .with_label_values(&[entry.plugin.name()])
+            .observe(started.elapsed().as_secs_f64());
+    }
+
+    match result {
+        Ok(()) => {
+            entry.record_ok();
+            entry.handled += shreds as u64;
+            if let Some(metrics) = metrics {
+                metrics
+                    .plugin_handled
+                    .with_label_values(&[entry.plugin.name()])
+                    .inc_by(shreds as u64);
+            }
+        }
+        Err(e) => entry.record_error(metrics, "", &e),
+    }
+}
+
+// a held back shred with what it is matched on once its set is released
+struct HeldShred {
+    shred: Arc<Shred>,
+    source: ShredSource,
+    // from the routers on arrival, see route()
+    masks: Vec<u64>,
+}
+
+#[derive(Default)]
+struct PendingFecSet {
+    shreds: Vec<HeldShred>,
+    data: BTreeSet<u32>,
+    coding: BTreeSet<u32>,
+    // one past the set's last data shred, once known
+    end: Option<u32>,
+    complete: bool,
+}
+
+impl PendingFecSet {
+    fn take_if_complete(&mut self, start: u32) -> Vec<HeldShred> {
+        let complete = self.end.is_some_and(|end| {
+            self.data.range(start..end).count() as u32 >= end.saturating_sub(start)
+        });
+        if !complete || self.complete {
+            return Vec::new();
+        }
+        self.complete = true;
+        std::mem::take(&mut self.shreds)
+    }
+}
+
+// shreds of FEC sets that aren't complete yet, keyed by (slot, fec_set_index)
+#[derive(Default)]
+struct FecSetBuffer {
+    sets: BTreeMap<(Slot, u32), PendingFecSet>,
+}
+
+impl FecSetBuffer {
+    // shreds that can go to the plugins now, in arrival order within a set
+    fn insert(
+        &mut self,
+        shred: &Arc<Shred>,
+        source: ShredSource,
+        masks: Vec<u64>,
+    ) -> Vec<HeldShred> {
+        let key = (shred.slot(), shred.fec_set_index());
+        let mut ready = Vec::new();
+
+        // a new set starts where the one before it in the slot ends, if that one's last
+        // data shred is right before it; otherwise whole sets in between may be missing
+        if !self.sets.contains_key(&key)
+            && let Some((&(slot, start), previous)) = self.sets.range_mut(..key).next_back()
+            && slot == key.0
+            && previous.end.is_none()
+            && key
+                .1
+                .checked_sub(1)
+                .is_some_and(|last| previous.data.contains(&last))
+        {
+            previous.end = Some(key.1);
+            ready.extend(previous.take_if_complete(start));
+        }
+
+        // the same the other way around, the next set already started right after `shred`
+        let next_starts_after = shred.is_data()
+            && self
+                .sets
+                .range((key.0, key.1.saturating_add(1))..)
+                .next()
+                .is_some_and(|(&(slot, start), _)| {
+                    slot == key.0 && start == shred.index().saturating_add(1)
+                });
+
+        let set = self.sets.entry(key).or_default();
+        let new = if shred.is_data() {
+            set.data.insert(shred.index())
+        } else {
+            set.coding.insert(shred.index())
+        };
+        let held = HeldShred {
+            shred: shred.clone(),
+            source,
+            masks,
+        };
+        if new && set.complete {
+            ready.push(held);
+        } else if new {
+            if shred.is_data() && shred.last_in_slot() {
+                set.end = Some(shred.index() + 1);
+            } else if !shred.is_data() && set.end.is_none() {
+                set.end = num_data_shreds(shred).map(|num_data| key.1 + num_data);
+            } else if next_starts_after && set.end.is_none() {
+                set.end = Some(shred.index() + 1);
+            }
+            set.shreds.push(held);
+            ready.extend(set.take_if_complete(key.1));
+        }
+
+        while self.sets.len() > MAX_PENDING_FEC_SETS {
+            self.sets.pop_first();
+        }
+        ready
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::SocketAddr,
+        sync::Mutex,
+        time::{Duration, SystemTime},
+    };
+
+    use super::*;
+    use crate::{shred::test_data_shred, utils::parse_shred};
+
+    // what a Recorder was called with, and whether its next calls fail
+    #[derive(Default)]
+    struct Calls {
+        starts: u32,
+        stops: u32,
+        shreds: Vec<(Slot, u32)>,
+        batches: Vec<usize>,
+        fail: bool,
+        fail_start: bool,
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        name: &'static str,
+        calls: Arc<Mutex<Calls>>,
+        filter: ShredFilter,
+        batch: Option<BatchConfig>,
+        supervision: Option<SupervisionConfig>,
+    }
+
+    impl Recorder {
+        fn new(name: &'static str) -> Self {
+            Self {
+                name,
+                ..Self::default()
+            }
+        }
+
+        fn handled(&self, shred: &Shred) -> Result<(), BoxError> {
+            let mut calls = self.calls.lock().unwrap();
+            calls.shreds.push((shred.slot(), shred.index()));
+            if calls.fail {
+                return Err("failing on demand".into());
+            }
+            Ok(())
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl OutputPlugin for Recorder {
+        async fn start(&mut self) -> Result<(), BoxError> {
+            let mut calls = self.calls.lock().unwrap();
+            calls.starts += 1;
+            if calls.fail_start {
+                return Err("failing to start on demand".into());
+            }
+            Ok(())
+        }
+
+        async fn stop(&mut self) -> Result<(), BoxError> {
+            self.calls.lock().unwrap().stops += 1;
+            Ok(())
+        }
+
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        async fn handle_shred(&mut self, shred: Shred) -> Result<(), BoxError> {
+            self.handled(&shred)
+        }
+
+        async fn handle_batch(&mut self, shreds: &[Arc<Shred>]) -> Result<(), BoxError> {
+            self.calls.lock().unwrap().batches.push(shreds.len());
+            let mut first_error = None;
+            for shred in shreds {
+                if let Err(e) = self.handled(shred) {
+                    first_error.get_or_insert(e);
+                }
+            }
+            first_error.map_or(Ok(()), Err)
+        }
+
+        fn filter(&self) -> ShredFilter {
+            self.filter.clone()
+        }
+
+        fn batch(&self) -> Option<BatchConfig> {
+            self.batch
+        }
+
+        fn supervision(&self) -> Option<SupervisionConfig> {
+            self.supervision
+        }
+    }
+
+    fn received(slot: Slot, index: u32, fec_set_index: u32, flags: u8) -> ReceivedShred {
+        let payload = test_data_shred(slot, index, fec_set_index, flags, b"entries");
+        ReceivedShred {
+            shred: Arc::new(parse_shred(&payload).unwrap()),
+            source: ShredSource::Turbine,
+            from: SocketAddr::from(([127, 0, 0, 1], 8001)),
+            received_at: SystemTime::now(),
+        }
+    }
+
+    // `plugins` added and started, with a handle on each one's calls
+    async fn runner(plugins: Vec<Recorder>) -> (PluginRunner, Vec<Arc<Mutex<Calls>>>) {
+        let mut runner = PluginRunner::new();
+        let mut calls = Vec::new();
+        for plugin in plugins {
+            calls.push(plugin.calls.clone());
+            runner.add_plugin(Box::new(plugin));
+        }
+        runner.start_all().await.unwrap();
+        (runner, calls)
+    }
+
+    fn batched(max_shreds: usize, window_ms: u64) -> Recorder {
+        Recorder {
+            batch: Some(BatchConfig {
+                max_shreds,
+                window_ms,
+            }),
+            ..Recorder::new("Batched")
+        }
+    }
+
+    #[tokio::test]
+    async fn batches_are_handed_over_at_max_shreds() {
+        let (mut runner, calls) = runner(vec![batched(3, 60_000)]).await;
+        for index in 0..7 {
+            runner.handle_shred(received(100, index, 0, 0)).await;
+        }
+        assert_eq!(calls[0].lock().unwrap().batches, [3, 3]);
+        assert_eq!(runner.status()[0].pending, 1);
+        assert_eq!(runner.status()[0].handled, 6);
+
+        // what is still pending is delivered before the plugin stops
+        runner.stop_all().await.unwrap();
+        let calls = calls[0].lock().unwrap();
+        assert_eq!(calls.batches, [3, 3, 1]);
+        assert_eq!(calls.shreds.len(), 7);
+        assert_eq!(calls.stops, 1);
+    }
+
+    #[tokio::test]
+    async fn batches_are_handed_over_once_the_window_passed() {
+        let (mut runner, calls) = runner(vec![batched(128, 50)]).await;
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        runner.handle_shred(received(100, 1, 0, 0)).await;
+        runner.poll_batches().await;
+        assert!(calls[0].lock().unwrap().batches.is_empty());
+
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        runner.poll_batches().await;
+        assert_eq!(calls[0].lock().unwrap().batches, [2]);
+        assert_eq!(runner.status()[0].pending, 0);
+    }
+
+    #[tokio::test]
+    async fn config_batch_wins_and_pending_shreds_survive_removal() {
+        let (mut runner, calls) = runner(vec![batched(128, 60_000)]).await;
+        runner
+            .set_batch(
+                "Batched",
+                Some(BatchConfig {
+                    max_shreds: 2,
+                    window_ms: 60_000,
+                }),
+            )
+            .unwrap();
+        for index in 0..3 {
+            runner.handle_shred(received(100, index, 0, 0)).await;
+        }
+        assert_eq!(calls[0].lock().unwrap().batches, [2]);
+
+        runner.remove_plugin("Batched").await.unwrap();
+        assert_eq!(calls[0].lock().unwrap().batches, [2, 1]);
+        assert_eq!(runner.plugin_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn unbatched_plugins_get_each_shred_as_it_arrives() {
+        let (mut runner, calls) = runner(vec![Recorder::new("Direct"), batched(2, 60_000)]).await;
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        assert_eq!(calls[0].lock().unwrap().shreds, [(100, 0)]);
+        assert!(calls[1].lock().unwrap().shreds.is_empty());
+        assert!(calls[0].lock().unwrap().batches.is_empty());
+    }
+
+    fn supervised(name: &'static str, on_error: ErrorPolicy, max_errors: u32) -> Recorder {
+        Recorder {
+            supervision: Some(SupervisionConfig {
+                on_error,
+                max_consecutive_errors: max_errors,
+                restart_backoff_ms: 20,
+                max_backoff_ms: 1_000,
+                max_restarts: 2,
+            }),
+            ..Recorder::new(name)
+        }
+    }
+
+    #[tokio::test]
+    async fn a_success_resets_the_error_count() {
+        let (mut runner, calls) = runner(vec![supervised("Flaky", ErrorPolicy::Disable, 2)]).await;
+        calls[0].lock().unwrap().fail = true;
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        calls[0].lock().unwrap().fail = false;
+        runner.handle_shred(received(100, 1, 0, 0)).await;
+        calls[0].lock().unwrap().fail = true;
+        runner.handle_shred(received(100, 2, 0, 0)).await;
+
+        let status = &runner.status()[0];
+        assert_eq!(status.state, PluginState::Running);
+        assert_eq!(status.consecutive_errors, 1);
+        assert_eq!(status.errors, 2);
+        assert_eq!(status.last_error.as_deref(), Some("failing on demand"));
+    }
+
+    #[tokio::test]
+    async fn restart_after_the_backoff() {
+        let (mut runner, calls) = runner(vec![supervised("Flaky", ErrorPolicy::Restart, 2)]).await;
+        calls[0].lock().unwrap().fail = true;
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        runner.handle_shred(received(100, 1, 0, 0)).await;
+        assert_eq!(runner.status()[0].state, PluginState::Restarting);
+
+        // not dispatched to while it waits, nor restarted before the backoff ran out
+        runner.handle_shred(received(100, 2, 0, 0)).await;
+        runner.supervise().await.unwrap();
+        assert_eq!(calls[0].lock().unwrap().shreds.len(), 2);
+        assert_eq!(runner.status()[0].state, PluginState::Restarting);
+
+        calls[0].lock().unwrap().fail = false;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+        runner.supervise().await.unwrap();
+        let status = &runner.status()[0];
+        assert_eq!(status.state, PluginState::Running);
+        assert_eq!(status.restarts, 1);
+        assert_eq!(status.consecutive_errors, 0);
+        {
+            let calls = calls[0].lock().unwrap();
+            assert_eq!((calls.starts, calls.stops), (2, 1));
+        }
+
+        runner.handle_shred(received(100, 3, 0, 0)).await;
+        assert_eq!(calls[0].lock().unwrap().shreds.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn failed_restarts_back_off_further_then_disable() {
+        let (mut runner, calls) = runner(vec![supervised("Broken", ErrorPolicy::Restart, 1)]).await;
+        {
+            let mut calls = calls[0].lock().unwrap();
+            calls.fail = true;
+            calls.fail_start = true;
+        }
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        assert_eq!(runner.status()[0].state, PluginState::Restarting);
+
+        // first restart after 20ms fails, the next one waits 40ms
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        runner.supervise().await.unwrap();
+        assert_eq!(runner.status()[0].state, PluginState::Restarting);
+        tokio::time::sleep(Duration::from_millis(25)).await;
+        runner.supervise().await.unwrap();
+        assert_eq!(runner.status()[0].restarts, 1);
+
+        // the second failed restart reaches max_restarts
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        runner.supervise().await.unwrap();
+        let status = &runner.status()[0];
+        assert_eq!(status.state, PluginState::Disabled);
+        assert_eq!(status.restarts, 2);
+        assert_eq!(calls[0].lock().unwrap().starts, 3);
+
+        // until it's restarted through the admin API
+        calls[0].lock().unwrap().fail_start = false;
+        runner.restart_plugin("Broken").await.unwrap();
+        assert_eq!(runner.status()[0].state, PluginState::Running);
+    }
+
+    #[tokio::test]
+    async fn disabled_plugins_are_skipped_and_fatal_ones_stop_the_node() {
+        let (mut runner, calls) = runner(vec![
+            supervised("Disabled", ErrorPolicy::Disable, 1),
+            supervised("Fatal", ErrorPolicy::Fatal, 2),
+        ])
+        .await;
+        calls[0].lock().unwrap().fail = true;
+        calls[1].lock().unwrap().fail = true;
+
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        assert_eq!(runner.status()[0].state, PluginState::Disabled);
+        assert!(runner.supervise().await.is_ok());
+
+        runner.handle_shred(received(100, 1, 0, 0)).await;
+        assert_eq!(calls[0].lock().unwrap().shreds.len(), 1);
+        assert_eq!(runner.status()[1].state, PluginState::Failed);
+        assert!(matches!(
+            runner.supervise().await,
+            Err(Error::PluginFatal { plugin, .. }) if plugin == "Fatal"
+        ));
+
+        // neither is running, so neither is stopped
+        runner.stop_all().await.unwrap();
+        assert_eq!(calls[0].lock().unwrap().stops, 0);
+        assert_eq!(calls[1].lock().unwrap().stops, 0);
+    }
+
+    #[tokio::test]
+    async fn start_all_carries_on_past_a_plugin_that_fails_to_start() {
+        let broken = Recorder::new("Broken");
+        broken.calls.lock().unwrap().fail_start = true;
+        let restarting = supervised("Restarting", ErrorPolicy::Restart, 5);
+        restarting.calls.lock().unwrap().fail_start = true;
+        let (mut runner, calls) = runner(vec![broken, restarting, Recorder::new("Working")]).await;
+
+        let states: Vec<PluginState> = runner.status().iter().map(|status| status.state).collect();
+        assert_eq!(
+            states,
+            [
+                PluginState::Disabled,
+                PluginState::Restarting,
+                PluginState::Running
+            ]
+        );
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        assert!(calls[0].lock().unwrap().shreds.is_empty());
+        assert_eq!(calls[2].lock().unwrap().shreds, [(100, 0)]);
+
+        // unless the plugin is fatal
+        let fatal = supervised("Fatal", ErrorPolicy::Fatal, 1);
+        fatal.calls.lock().unwrap().fail_start = true;
+        let mut runner = PluginRunner::new();
+        runner.add_plugin(Box::new(fatal));
+        assert!(runner.start_all().await.is_err());
+    }
+
+    const LAST_IN_SLOT: u8 = 0b1100_0000;
+
+    fn fec_sets(filter: ShredFilter) -> Recorder {
+        Recorder {
+            filter: ShredFilter {
+                complete_fec_sets: true,
+                ..filter
+            },
+            ..Recorder::new("FecSets")
+        }
+    }
+
+    #[tokio::test]
+    async fn fec_sets_are_held_until_the_next_set_or_last_in_slot() {
+        let (mut runner, calls) = runner(vec![fec_sets(ShredFilter::default())]).await;
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        runner.handle_shred(received(100, 1, 0, 0)).await;
+        assert!(calls[0].lock().unwrap().shreds.is_empty());
+
+        // set 2 starts right after shred 1, so set 0 is 0..2 and complete
+        runner.handle_shred(received(100, 2, 2, 0)).await;
+        assert_eq!(calls[0].lock().unwrap().shreds, [(100, 0), (100, 1)]);
+
+        runner.handle_shred(received(100, 3, 2, LAST_IN_SLOT)).await;
+        assert_eq!(calls[0].lock().unwrap().shreds.len(), 4);
+        // a late copy of a released set's shred isn't held again
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        assert_eq!(calls[0].lock().unwrap().shreds.len(), 4);
+    }
+
+    #[tokio::test]
+    async fn a_later_set_doesnt_close_over_missing_sets() {
+        let (mut runner, calls) = runner(vec![fec_sets(ShredFilter::default())]).await;
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        runner.handle_shred(received(100, 1, 0, 0)).await;
+        // set 2 (shreds 2 and 3) hasn't arrived, set 0 can't end at 4
+        runner.handle_shred(received(100, 4, 4, 0)).await;
+        assert!(calls[0].lock().unwrap().shreds.is_empty());
+
+        // set 2 closes set 0 when it starts and is closed by set 4 when its last shred lands
+        runner.handle_shred(received(100, 2, 2, 0)).await;
+        assert_eq!(calls[0].lock().unwrap().shreds, [(100, 0), (100, 1)]);
+        runner.handle_shred(received(100, 3, 2, 0)).await;
+        assert_eq!(
+            calls[0].lock().unwrap().shreds,
+            [(100, 0), (100, 1), (100, 2), (100, 3)]
+        );
+    }
+
+    #[tokio::test]
+    async fn only_shreds_a_fec_set_plugin_wants_are_held() {
+        let later_slots = ShredFilter {
+            min_slot: Some(200),
+            ..ShredFilter::default()
+        };
+        let (mut runner, calls) =
+            runner(vec![fec_sets(later_slots), Recorder::new("Direct")]).await;
+        runner.handle_shred(received(100, 0, 0, 0)).await;
+        runner.handle_shred(received(100, 1, 0, 0)).await;
+        assert!(runner.fec_sets.sets.is_empty());
+        assert_eq!(calls[1].lock().unwrap().shreds.len(), 2);
+
+        runner.handle_shred(received(200, 0, 0, 0)).await;
+        assert_eq!(runner.fec_sets.sets.len(), 1);
+        assert!(calls[0].lock().unwrap().shreds.is_empty());
+    }
+}
@@ -0,0 +1,329 @@
+/*
+ ** Rejected Packets **
+: Packets on the TVU and repair sockets that don't make it to a `Shred` are classified by
+: why they were rejected instead of all being counted as "non-shred". The reason comes
+: from the header checks in `ShredHeaderView::new` (see shred.rs), the shred version check,
+: or the error `parse_shred()` returned.
+
+*  ** Reasons **
+! +---------------------+----------------------------------------------------------+
+! | Reason              | Packet                                                   |
+! +---------------------+----------------------------------------------------------+
+! | too_short           | shorter than the common header (0x53 bytes)              |
+! | unknown_variant     | variant byte isn't a (still supported) shred variant     |
+! | wrong_shred_version | shred_version isn't verification.shred_version           |
+! | bad_payload_size    | payload length doesn't match the variant                 |
+! | bad_size_field      | data header `size` is outside the payload                |
+! | proof_mismatch      | Merkle proof size or proof doesn't fit the payload       |
+! | bad_index           | shred, erasure or coding counts/indexes out of range     |
+! | bad_parent          | parent_offset points before slot 0 or at the slot itself |
+! | bad_flags           | data flags that can't be set together                    |
+! | malformed           | anything else the deserializer rejected                  |
+! +---------------------+----------------------------------------------------------+
+: Counted as `chainsmoker_packets_rejected_total{reason, source}` (see metrics.rs),
+: source being turbine or repair.
+
+*  ** Quarantine **
+: With a quarantine directory (`receiver.quarantine_dir`, `--quarantine-dir`) the raw
+: bytes of rejected packets are kept for later study, one archive per reason:
+: `<dir>/<reason>.bin`, in the archive format of archive.rs. Read them back with
+: `chainsmoker inspect --file <dir>/bad_size_field.bin`, which decodes whatever it can and
+: lists what is wrong. At most `limit` packets are written in total so a flood of garbage
+: can't fill the disk.
+*/
+
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    fmt, fs, io,
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use log::{error, info, trace};
+use serde::Serialize;
+use solana_ledger::shred::Error as ShredError;
+
+use crate::{
+    archive::ArchiveWriter, error::Error, metrics::Metrics, shred::ShredHeaderView,
+    types::ShredSource,
+};
+
+pub const DEFAULT_QUARANTINE_LIMIT: u64 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RejectReason {
+    TooShort,
+    UnknownVariant,
+    WrongShredVersion,
+    BadPayloadSize,
+    BadSizeField,
+    ProofMismatch,
+    BadIndex,
+    BadParent,
+    BadFlags,
+    Malformed,
+}
+
+impl RejectReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::TooShort => "too_short",
+            Self::UnknownVariant => "unknown_variant",
+            Self::WrongShredVersion => "wrong_shred_version",
+            Self::BadPayloadSize => "bad_payload_size",
+            Self::BadSizeField => "bad_size_field",
+            Self::ProofMismatch => "proof_mismatch",
+            Self::BadIndex => "bad_index",
+            Self::BadParent => "bad_parent",
+            Self::BadFlags => "bad_flags",
+            Self::Malformed => "malformed",
+        }
+    }
+
+    // why `payload` failed to parse with `error`, header problems first
+    pub fn classify(payload: &[u8], error: &Error) -> Self {
+        if let Err(reason) = ShredHeaderView::new(payload) {
+            return reason;
+        }
+        match error {
+            Error::ShredParse(error) => Self::from_shred_error(error),
+            _ => Self::Malformed,
+        }
+    }
+
+    fn from_shred_error(error: &ShredError) -> Self {
+        match error {
+            ShredError::InvalidPacketSize => Self::TooShort,
+            ShredError::InvalidShredVariant | ShredError::InvalidShredType => Self::UnknownVariant,
+            ShredError::InvalidPayloadSize(_) | ShredError::InvalidShardSize(_) => {
+                Self::BadPayloadSize
+            }
+            ShredError::InvalidDataSize { .. } => Self::BadSizeField,
+            ShredError::InvalidProofSize(_)
+            | ShredError::UnknownProofSize
+            | ShredError::InvalidMerkleProof
+            | ShredError::InvalidMerkleRoot => Self::ProofMismatch,
+            ShredError::InvalidShredIndex(..)
+            | ShredError::InvalidErasureShardIndex(_)
+            | ShredError::InvalidNumCodingShreds(_) => Self::BadIndex,
+            ShredError::InvalidParentOffset { .. } | ShredError::InvalidParentSlot { .. } => {
+                Self::BadParent
+            }
+            ShredError::InvalidShredFlags(_) => Self::BadFlags,
+            _ => Self::Malformed,
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+struct QuarantineState {
+    archives: HashMap<RejectReason, ArchiveWriter>,
+    written: u64,
+}
+
+// raw rejected packets on disk, shared by the shred receiver and the repair client
+#[derive(Clone)]
+pub struct Quarantine {
+    dir: PathBuf,
+    limit: u64,
+    state: Arc<Mutex<QuarantineState>>,
+}
+
+impl Quarantine {
+    pub fn new(dir: impl AsRef<Path>, limit: u64) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        info!(
+            "Quarantining up to {} rejected packets in {}",
+            limit,
+            dir.display()
+        );
+        Ok(Self {
+            dir,
+            limit,
+            state: Arc::new(Mutex::new(QuarantineState {
+                archives: HashMap::new(),
+                written: 0,
+            })),
+        })
+    }
+
+    pub fn write(
+        &self,
+        reason: RejectReason,
+        source: ShredSource,
+        from: SocketAddr,
+        received_at: SystemTime,
+        payload: &[u8],
+    ) {
+        let mut state = self.state.lock().unwrap();
+        if state.written >= self.limit {
+            return;
+        }
+        state.written += 1;
+        if state.written == self.limit {
+            info!("Quarantine limit of {} packets reached", self.limit);
+        }
+
+        let archive = match state.archives.entry(reason) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let path = self.dir.join(format!("{}.bin", reason));
+                match ArchiveWriter::create(&path) {
+                    Ok(archive) => entry.insert(archive),
+                    Err(e) => {
+                        error!("Failed to create quarantine {}: {}", path.display(), e);
+                        return;
+                    }
+                }
+            }
+        };
+        // flushed right away, rejects are rare and the files are read while we run
+        let written = archive
+            .write_packet(received_at, source, from, payload)
+            .and_then(|()| archive.flush());
+        if let Err(e) = written {
+            error!("Failed to quarantine a {} packet: {}", reason, e);
+        }
+    }
+
+    pub fn written(&self) -> u64 {
+        self.state.lock().unwrap().written
+    }
+}
+
+// counts, logs and quarantines the packets one receive path rejects
+#[derive(Clone)]
+pub struct RejectSink {
+    source: ShredSource,
+    metrics: Option<Arc<Metrics>>,
+    quarantine: Option<Quarantine>,
+}
+
+impl RejectSink {
+    pub fn new(source: ShredSource) -> Self {
+        Self {
+            source,
+            metrics: None,
+            quarantine: None,
+        }
+    }
+
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    pub fn set_quarantine(&mut self, quarantine: Quarantine) {
+        self.quarantine = Some(quarantine);
+    }
+
+    // same metrics and quarantine, counted under another source
+    pub fn with_source(&self, source: ShredSource) -> Self {
+        Self {
+            source,
+            ..self.clone()
+        }
+    }
+
+    pub fn reject(
+        &self,
+        reason: RejectReason,
+        from: SocketAddr,
+        received_at: SystemTime,
+        payload: &[u8],
+    ) {
+        trace!(
+            "REJECTED {:?} packet: {}, {} bytes from {}",
+            self.source,
+            reason,
+            payload.len(),
+            from
+        );
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .packets_rejected
+                .with_label_values(&[reason.as_str(), self.source.as_str()])
+                .inc();
+        }
+        if let Some(quarantine) = &self.quarantine {
+            quarantine.write(reason, self.source, from, received_at, payload);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{shred::test_data_shred, utils::parse_shred};
+
+    const LAST_IN_SLOT: u8 = 0b1100_0000;
+
+    // what a payload that doesn't parse is rejected as
+    fn classify(payload: &[u8]) -> RejectReason {
+        let error = parse_shred(payload).err().expect("payload parsed");
+        RejectReason::classify(payload, &error)
+    }
+
+    fn shred(index: u32, fec_set_index: u32, flags: u8) -> Vec<u8> {
+        test_data_shred(100, index, fec_set_index, flags, b"entries")
+    }
+
+    #[test]
+    fn test_header_problems() {
+        let payload = shred(0, 0, 0);
+        assert_eq!(classify(&payload[..40]), RejectReason::TooShort);
+
+        let mut unknown = payload.clone();
+        unknown[0x40] = 0x00;
+        assert_eq!(classify(&unknown), RejectReason::UnknownVariant);
+        // a legacy variant has a header, but isn't parsed any more
+        let mut legacy = payload;
+        legacy[0x40] = 0xa5;
+        assert_eq!(classify(&legacy), RejectReason::UnknownVariant);
+    }
+
+    #[test]
+    fn test_payload_and_size_field() {
+        let payload = shred(0, 0, 0);
+        assert_eq!(classify(&payload[..1100]), RejectReason::BadPayloadSize);
+
+        let mut size = payload;
+        size[0x56..0x58].copy_from_slice(&u16::MAX.to_le_bytes());
+        assert_eq!(classify(&size), RejectReason::BadSizeField);
+    }
+
+    #[test]
+    fn test_index_parent_and_flags() {
+        // past MAX_DATA_SHREDS_PER_SLOT, and before the start of its FEC set
+        assert_eq!(classify(&shred(40_000, 40_000, 0)), RejectReason::BadIndex);
+        assert_eq!(classify(&shred(5, 10, 0)), RejectReason::BadIndex);
+
+        // LAST_IN_SLOT without DATA_COMPLETE
+        assert_eq!(classify(&shred(0, 0, 0b1000_0000)), RejectReason::BadFlags);
+        assert!(parse_shred(&shred(0, 0, LAST_IN_SLOT)).is_ok());
+
+        // a parent offset of 0 is only valid in slot 0
+        let mut parent = shred(0, 0, 0);
+        parent[0x53..0x55].copy_from_slice(&0u16.to_le_bytes());
+        assert_eq!(classify(&parent), RejectReason::BadParent);
+    }
+
+    #[test]
+    fn test_other_errors_are_malformed() {
+        let payload = shred(0, 0, 0);
+        let error = Error::Io(std::io::Error::other("not a shred error"));
+        assert_eq!(
+            RejectReason::classify(&payload, &error),
+            RejectReason::Malformed
+        );
+        assert_eq!(RejectReason::Malformed.to_string(), "malformed");
+    }
+}
@@ -0,0 +1,624 @@
+/*
+ ** Repair Client **
+: Turbine is lossy, so some shreds of a slot never reach our TVU socket. The repair client
+: watches the shred stream for gaps and asks peers that run serve-repair to resend the
+: missing shreds. Responses go through the receiver's `PacketPipeline` (shred version,
+: `[receiver.filter]`, attribution and duplicate dropping, see shred.rs) into the same
+: channel as turbine shreds, tagged with `ShredSource::Repair`. Plugins that only want one
+: or the other set `source` in their filter (see filter.rs).
+
+*  ** Peer Selection **
+: Peers come from `ClusterInfo::repair_peers`, which only returns nodes advertising a
+: serve-repair socket with the same shred version as ours (and whose LowestSlot is not
+: past the slot we ask for). Requests are spread round-robin over those peers.
+
+*  ** Repair Request Wire Format **
+: Requests are the bincode encoding of the `RepairProtocol` enum. Only the signed
+: variants are used, the legacy ones exist to keep the discriminants in place.
+
+! +--------+-----+-----------+-------------+-----------------------------------------+
+! | Offset | Size| Type      | Name        | Purpose                                 |
+! +--------+-----+-----------+-------------+-----------------------------------------+
+! | 0x00   | 4B  | u32       | discriminant| 8 = WindowIndex, 9 = HighestWindowIndex |
+! | 0x04   | 64B | Signature | signature   | Sender signature (see below)            |
+! | 0x44   | 32B | Pubkey    | sender      | Our identity                            |
+! | 0x64   | 32B | Pubkey    | recipient   | Identity of the repair peer             |
+! | 0x84   | 8B  | u64       | timestamp   | Wallclock in ms                         |
+! | 0x8c   | 4B  | u32       | nonce       | Echoed back in the response             |
+! | 0x90   | 8B  | u64       | slot        | Slot to repair                          |
+! | 0x98   | 8B  | u64       | shred_index | Data shred index                        |
+! +--------+-----+-----------+-------------+-----------------------------------------+
+
+: The signature covers the whole payload except the signature bytes themselves
+: (bytes 0x00..0x04 followed by 0x44..end).
+
+*  ** Responses **
+: A response is the shred payload followed by the 4-byte nonce of the request.
+: Before serving us, a peer sends a 132-byte `Ping` which has to be answered with a
+: signed `Pong` to its serve-repair socket, otherwise requests are silently dropped.
+
+*  ** Request Types **
+! +--------------------+--------------------------------------------------------+
+! | Request            | When                                                   |
+! +--------------------+--------------------------------------------------------+
+! | WindowIndex        | Index below the highest one seen (or LAST_IN_SLOT)     |
+! |                    | is missing                                             |
+! | HighestWindowIndex | LAST_IN_SLOT was never seen, ask for anything past the |
+! |                    | highest index we have                                  |
+! +--------------------+--------------------------------------------------------+
+*/
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+use crossbeam_channel::Sender;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use solana_gossip::{
+    cluster_info::ClusterInfo,
+    contact_info::Protocol,
+    ping_pong::{Ping, Pong},
+};
+use solana_ledger::shred::Shred;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::Signature,
+    signer::{Signer, keypair::Keypair},
+};
+
+use crate::{shred::PacketPipeline, types::ReceivedShred, utils::get_timestamp_ms};
+
+const SIGNATURE_BYTES: usize = 64;
+const NONCE_BYTES: usize = 4;
+const REPAIR_PING_TOKEN_SIZE: usize = 32;
+const REPAIR_RESPONSE_SERIALIZED_PING_BYTES: usize = 4 + 32 + REPAIR_PING_TOKEN_SIZE + 64;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RepairConfig {
+    // how long a slot has to be quiet before its gaps are requested
+    pub repair_delay: Duration,
+    // minimum time between two requests for the same shred
+    pub retry_interval: Duration,
+    pub max_requests_per_slot: usize,
+    pub max_tracked_slots: usize,
+    pub tick: Duration,
+}
+
+impl Default for RepairConfig {
+    fn default() -> Self {
+        Self {
+            repair_delay: Duration::from_millis(200),
+            retry_interval: Duration::from_millis(500),
+            max_requests_per_slot: 64,
+            max_tracked_slots: 32,
+            tick: Duration::from_millis(100),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepairRequest {
+    WindowIndex { slot: u64, shred_index: u64 },
+    HighestWindowIndex { slot: u64, shred_index: u64 },
+}
+
+impl RepairRequest {
+    pub fn slot(&self) -> u64 {
+        match self {
+            Self::WindowIndex { slot, .. } | Self::HighestWindowIndex { slot, .. } => *slot,
+        }
+    }
+
+    pub fn shred_index(&self) -> u64 {
+        match self {
+            Self::WindowIndex { shred_index, .. }
+            | Self::HighestWindowIndex { shred_index, .. } => *shred_index,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct RepairRequestHeader {
+    signature: Signature,
+    sender: Pubkey,
+    recipient: Pubkey,
+    timestamp: u64,
+    nonce: u32,
+}
+
+// mirrors the serve-repair protocol enum, unit variants only hold discriminants in place
+#[allow(dead_code)]
+#[derive(Serialize)]
+enum RepairProtocol {
+    LegacyWindowIndex,
+    LegacyHighestWindowIndex,
+    LegacyOrphan,
+    LegacyWindowIndexWithNonce,
+    LegacyHighestWindowIndexWithNonce,
+    LegacyOrphanWithNonce,
+    LegacyAncestorHashes,
+    Pong(Pong),
+    WindowIndex {
+        header: RepairRequestHeader,
+        slot: u64,
+        shred_index: u64,
+    },
+    HighestWindowIndex {
+        header: RepairRequestHeader,
+        slot: u64,
+        shred_index: u64,
+    },
+}
+
+#[derive(Deserialize)]
+enum RepairResponse {
+    Ping(Ping<REPAIR_PING_TOKEN_SIZE>),
+}
+
+#[derive(Default)]
+struct SlotState {
+    received: BTreeSet<u32>,
+    highest_index: u32,
+    last_index: Option<u32>,
+    last_update: Option<Instant>,
+    requested: HashMap<u32, Instant>,
+    requests_sent: usize,
+}
+
+impl SlotState {
+    fn is_complete(&self) -> bool {
+        match self.last_index {
+            Some(last) => self.received.len() as u32 == last + 1,
+            None => false,
+        }
+    }
+}
+
+// per-slot view of which data shreds arrived, used to decide what to ask for
+#[derive(Default)]
+pub struct RepairTracker {
+    slots: BTreeMap<u64, SlotState>,
+}
+
+impl RepairTracker {
+    pub fn observe(&mut self, shred: &Shred, max_tracked_slots: usize) {
+        if !shred.is_data() {
+            return;
+        }
+
+        let state = self.slots.entry(shred.slot()).or_default();
+        state.received.insert(shred.index());
+        state.requested.remove(&shred.index());
+        state.highest_index = state.highest_index.max(shred.index());
+        state.last_update = Some(Instant::now());
+        if shred.last_in_slot() {
+            state.last_index = Some(shred.index());
+        }
+
+        while self.slots.len() > max_tracked_slots {
+            self.slots.pop_first();
+        }
+    }
+
+    // what to ask for now, nothing is recorded until `requested()` is called for it
+    pub fn missing(&self, config: &RepairConfig) -> Vec<RepairRequest> {
+        let now = Instant::now();
+        let mut requests = Vec::new();
+
+        for (&slot, state) in &self.slots {
+            let quiet = state
+                .last_update
+                .is_some_and(|t| now.duration_since(t) >= config.repair_delay);
+            if !quiet || state.is_complete() || state.requests_sent >= config.max_requests_per_slot
+            {
+                continue;
+            }
+
+            let upper = state.last_index.unwrap_or(state.highest_index);
+            let budget = config.max_requests_per_slot - state.requests_sent;
+            let mut slot_requests = Vec::new();
+
+            for index in 0..=upper {
+                if slot_requests.len() >= budget {
+                    break;
+                }
+                if state.received.contains(&index) {
+                    continue;
+                }
+                let recently_requested = state
+                    .requested
+                    .get(&index)
+                    .is_some_and(|t| now.duration_since(*t) < config.retry_interval);
+                if recently_requested {
+                    continue;
+                }
+                slot_requests.push(RepairRequest::WindowIndex {
+                    slot,
+                    shred_index: index as u64,
+                });
+            }
+
+            // LAST_IN_SLOT never arrived, ask for anything past what we have
+            if state.last_index.is_none() && slot_requests.len() < budget {
+                let next = state.highest_index + 1;
+                let recently_requested = state
+                    .requested
+                    .get(&next)
+                    .is_some_and(|t| now.duration_since(*t) < config.retry_interval);
+                if !recently_requested {
+                    slot_requests.push(RepairRequest::HighestWindowIndex {
+                        slot,
+                        shred_index: next as u64,
+                    });
+                }
+            }
+
+            requests.extend(slot_requests);
+        }
+
+        requests
+    }
+
+    // a request from `missing()` was sent to a peer, counts against the slot's budget
+    pub fn requested(&mut self, request: RepairRequest) {
+        let Some(state) = self.slots.get_mut(&request.slot()) else {
+            return;
+        };
+        state
+            .requested
+            .insert(request.shred_index() as u32, Instant::now());
+        state.requests_sent += 1;
+    }
+}
+
+#[derive(Default)]
+struct RepairStats {
+    requests: u64,
+    no_peers: u64,
+    responses: u64,
+    pings: u64,
+    invalid: u64,
+    skipped: u64,
+}
+
+pub struct RepairClient {
+    cluster_info: Arc<ClusterInfo>,
+    keypair: Arc<Keypair>,
+    socket: Arc<UdpSocket>,
+    sender: Sender<ReceivedShred>,
+    pipeline: PacketPipeline,
+    tracker: Arc<Mutex<RepairTracker>>,
+    outstanding: Arc<Mutex<HashMap<u32, Instant>>>,
+    config: RepairConfig,
+    exit: Arc<AtomicBool>,
+}
+
+impl RepairClient {
+    pub fn new(
+        cluster_info: Arc<ClusterInfo>,
+        keypair: Arc<Keypair>,
+        socket: Arc<UdpSocket>,
+        sender: Sender<ReceivedShred>,
+        // from ShredReceiver::pipeline(ShredSource::Repair)
+        pipeline: PacketPipeline,
+        config: RepairConfig,
+        exit: Arc<AtomicBool>,
+    ) -> Self {
+        if let Err(e) = socket.set_read_timeout(Some(config.tick)) {
+            error!("Failed to set repair socket read timeout: {}", e);
+        }
+
+        Self {
+            cluster_info,
+            keypair,
+            socket,
+            sender,
+            pipeline,
+            tracker: Arc::new(Mutex::new(RepairTracker::default())),
+            outstanding: Arc::new(Mutex::new(HashMap::new())),
+            config,
+            exit,
+        }
+    }
+
+    // handle for the shred receiver to report turbine shreds
+    pub fn tracker(&self) -> RepairObserver {
+        RepairObserver {
+            tracker: self.tracker.clone(),
+            max_tracked_slots: self.config.max_tracked_slots,
+        }
+    }
+
+    pub fn start(self) -> Vec<thread::JoinHandle<()>> {
+        let client = Arc::new(self);
+
+        let requester = {
+            let client = client.clone();
+            thread::spawn(move || client.request_loop())
+        };
+        let responder = thread::spawn(move || client.response_loop());
+
+        vec![requester, responder]
+    }
+
+    fn request_loop(&self) {
+        info!("Starting repair client...");
+
+        let mut stats = RepairStats::default();
+        let mut next_peer = 0usize;
+        let mut nonce = get_timestamp_ms() as u32;
+        let mut last_log = Instant::now();
+
+        while !self.exit.load(Ordering::Relaxed) {
+            thread::sleep(self.config.tick);
+
+            let requests = self.tracker.lock().unwrap().missing(&self.config);
+            // repair_peers scans and clones the peer table, once per slot and tick is enough
+            let mut slot_peers = HashMap::new();
+            // only requests that went out are recorded, the rest are retried next tick
+            let mut sent = Vec::new();
+            for request in requests {
+                let slot = request.slot();
+                let peers = slot_peers
+                    .entry(slot)
+                    .or_insert_with(|| self.cluster_info.repair_peers(slot));
+                if peers.is_empty() {
+                    stats.no_peers += 1;
+                    continue;
+                }
+                let peer = &peers[next_peer % peers.len()];
+                next_peer = next_peer.wrapping_add(1);
+
+                let Some(addr) = peer.serve_repair(Protocol::UDP) else {
+                    continue;
+                };
+
+                nonce = nonce.wrapping_add(1);
+                match self.sign_request(request, *peer.pubkey(), nonce) {
+                    Ok(payload) => {
+                        if let Err(e) = self.socket.send_to(&payload, addr) {
+                            debug!("Failed to send repair request to {}: {}", addr, e);
+                            continue;
+                        }
+                        self.outstanding
+                            .lock()
+                            .unwrap()
+                            .insert(nonce, Instant::now());
+                        stats.requests += 1;
+                        sent.push(request);
+                    }
+                    Err(e) => error!("Failed to serialize repair request: {}", e),
+                }
+            }
+            if !sent.is_empty() {
+                let mut tracker = self.tracker.lock().unwrap();
+                for request in sent {
+                    tracker.requested(request);
+                }
+            }
+
+            // forget nonces nobody answered
+            let expiry = self.config.retry_interval * 4;
+            self.outstanding
+                .lock()
+                .unwrap()
+                .retain(|_, sent| sent.elapsed() < expiry);
+
+            if last_log.elapsed() >= Duration::from_secs(10) {
+                info!(
+                    "Repair Stats: {} requests, {} without peers",
+                    stats.requests, stats.no_peers
+                );
+                last_log = Instant::now();
+            }
+        }
+    }
+
+    fn response_loop(&self) {
+        let mut buffer = [0u8; 1232];
+        let mut stats = RepairStats::default();
+        let mut last_log = Instant::now();
+
+        while !self.exit.load(Ordering::Relaxed) {
+            match self.socket.recv_from(&mut buffer) {
+                Ok((size, from)) => {
+                    self.process_response(&buffer[..size], from, &mut stats);
+                }
+                Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+                Err(e) => {
+                    error!("Repair receive error: {}", e);
+                    thread::sleep(Duration::from_millis(100));
+                }
+            }
+
+            if last_log.elapsed() >= Duration::from_secs(10) {
+                info!(
+                    "Repair Responses: {} shreds, {} pings, {} invalid, {} skipped",
+                    stats.responses, stats.pings, stats.invalid, stats.skipped
+                );
+                last_log = Instant::now();
+            }
+        }
+
+        info!("Repair client stopped");
+    }
+
+    fn process_response(&self, data: &[u8], from: SocketAddr, stats: &mut RepairStats) {
+        if data.len() == REPAIR_RESPONSE_SERIALIZED_PING_BYTES {
+            if let Ok(RepairResponse::Ping(ping)) = bincode::deserialize(data) {
+                stats.pings += 1;
+                self.send_pong(&ping, from);
+            }
+            return;
+        }
+
+        let received_at = SystemTime::now();
+        if data.len() <= NONCE_BYTES {
+            // rejected as too short
+            stats.invalid += 1;
+            self.pipeline.process(data, from, received_at);
+            return;
+        }
+
+        let (payload, nonce) = data.split_at(data.len() - NONCE_BYTES);
+        let nonce = u32::from_le_bytes(nonce.try_into().unwrap());
+        if self.outstanding.lock().unwrap().remove(&nonce).is_none() {
+            stats.invalid += 1;
+            return;
+        }
+
+        // rejected, filtered out or a duplicate of a shred we already have
+        let Some(received) = self.pipeline.process(payload, from, received_at) else {
+            stats.skipped += 1;
+            return;
+        };
+        stats.responses += 1;
+        debug!(
+            "REPAIRED: Slot:{} Index:{} from {}",
+            received.shred.slot(),
+            received.shred.index(),
+            from
+        );
+        self.tracker
+            .lock()
+            .unwrap()
+            .observe(&received.shred, self.config.max_tracked_slots);
+        if self.sender.send(received).is_err() {
+            warn!("Output channel closed, dropping repaired shred");
+        }
+    }
+
+    fn send_pong(&self, ping: &Ping<REPAIR_PING_TOKEN_SIZE>, to: SocketAddr) {
+        let pong = RepairProtocol::Pong(Pong::new(ping, &self.keypair));
+        match bincode::serialize(&pong) {
+            Ok(payload) => {
+                if let Err(e) = self.socket.send_to(&payload, to) {
+                    debug!("Failed to send pong to {}: {}", to, e);
+                }
+            }
+            Err(e) => error!("Failed to serialize pong: {}", e),
+        }
+    }
+
+    fn sign_request(
+        &self,
+        request: RepairRequest,
+        recipient: Pubkey,
+        nonce: u32,
+    ) -> Result<Vec<u8>, bincode::Error> {
+        let header = RepairRequestHeader {
+            signature: Signature::default(),
+            sender: self.keypair.pubkey(),
+            recipient,
+            timestamp: get_timestamp_ms(),
+            nonce,
+        };
+        let request = match request {
+            RepairRequest::WindowIndex { slot, shred_index } => RepairProtocol::WindowIndex {
+                header,
+                slot,
+                shred_index,
+            },
+            RepairRequest::HighestWindowIndex { slot, shred_index } => {
+                RepairProtocol::HighestWindowIndex {
+                    header,
+                    slot,
+                    shred_index,
+                }
+            }
+        };
+
+        let mut payload = bincode::serialize(&request)?;
+        let signable = [&payload[..4], &payload[4 + SIGNATURE_BYTES..]].concat();
+        let signature = self.keypair.sign_message(&signable);
+        payload[4..4 + SIGNATURE_BYTES].copy_from_slice(signature.as_ref());
+        Ok(payload)
+    }
+}
+
+#[derive(Clone)]
+pub struct RepairObserver {
+    tracker: Arc<Mutex<RepairTracker>>,
+    max_tracked_slots: usize,
+}
+
+impl RepairObserver {
+    pub fn observe(&self, shred: &Shred) {
+        self.tracker
+            .lock()
+            .unwrap()
+            .observe(shred, self.max_tracked_slots);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{shred::test_data_shred, utils::parse_shred};
+
+    const LAST_IN_SLOT: u8 = 0b1100_0000;
+
+    fn observe(tracker: &mut RepairTracker, slot: u64, index: u32, flags: u8) {
+        let shred = parse_shred(&test_data_shred(slot, index, 0, flags, b"entries")).unwrap();
+        tracker.observe(&shred, 32);
+    }
+
+    fn config() -> RepairConfig {
+        RepairConfig {
+            repair_delay: Duration::ZERO,
+            retry_interval: Duration::from_secs(60),
+            ..RepairConfig::default()
+        }
+    }
+
+    #[test]
+    fn unsent_requests_are_asked_for_again() {
+        let mut tracker = RepairTracker::default();
+        observe(&mut tracker, 10, 0, 0);
+        observe(&mut tracker, 10, 3, LAST_IN_SLOT);
+
+        let gaps = vec![
+            RepairRequest::WindowIndex {
+                slot: 10,
+                shred_index: 1,
+            },
+            RepairRequest::WindowIndex {
+                slot: 10,
+                shred_index: 2,
+            },
+        ];
+        assert_eq!(tracker.missing(&config()), gaps);
+        // no peer to send them to
+        assert_eq!(tracker.missing(&config()), gaps);
+
+        tracker.requested(gaps[0]);
+        assert_eq!(tracker.missing(&config()), gaps[1..]);
+    }
+
+    #[test]
+    fn budget_counts_sent_requests_only() {
+        let config = RepairConfig {
+            max_requests_per_slot: 2,
+            ..config()
+        };
+        let mut tracker = RepairTracker::default();
+        observe(&mut tracker, 10, 5, 0);
+
+        // 0..=4 missing plus the highest index, capped by the budget
+        let requests = tracker.missing(&config);
+        assert_eq!(requests.len(), 2);
+        for request in requests {
+            tracker.requested(request);
+        }
+        assert!(tracker.missing(&config).is_empty());
+    }
+}
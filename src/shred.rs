@@ -51,98 +51,506 @@
 : The maximum shred packet size is determined based on the IPv6 minimum link MTU.
 
 ! Max size for shred packet is 1228 bytes (Legacy) or 1203 bytes (Merkle).
+
+*  ** Receive Path **
+: Most packets never need a full `Shred`. `ShredHeaderView` borrows the packet and reads
+: the common header fields at the offsets above, so the receiver decides on the header
+: alone and only copies and deserializes what it keeps:
+
+! +---+--------------------------+----------------------------------------------+
+! | # | Step                     | Dropped packets count as                     |
+! +---+--------------------------+----------------------------------------------+
+! | 1 | ShredHeaderView::new     | rejected: too_short, unknown_variant         |
+! | 2 | shred_version            | rejected: wrong_shred_version                |
+! | 3 | attribution (dedup)      | shreds_skipped{reason="duplicate"}           |
+! | 4 | receiver filter          | shreds_skipped{reason="filter"}              |
+! | 5 | Shred construction       | rejected: classified from the parse error    |
+! | 6 | attribution (record)     | shreds_skipped{reason="duplicate"}           |
+! +---+--------------------------+----------------------------------------------+
+: Duplicates are only dropped with `set_drop_duplicates(true)`, otherwise they are counted
+: by attribution and passed on as before. Step 3 only looks the shred up, it is recorded
+: as seen in step 6 once it parsed, so a corrupt packet with a valid-looking header can't
+: make the real shred look like a duplicate. Step 6 catches a copy that arrived in between. Rejected packets also count as
+: non_shred_packets and can be quarantined to disk (see reject.rs).
+: Repair responses take the same steps: the repair client gets a `PacketPipeline` from
+: `ShredReceiver::pipeline(ShredSource::Repair)` and feeds it the response minus the nonce.
 */
 
-use crate::{stats::ReceiveStats, utils::parse_shred};
-use log::{debug, error, info};
+use crate::{
+    attribution::PeerAttribution,
+    error::Error,
+    filter::{ShredFilter, ShredKind},
+    inspect::ShredVariant,
+    metrics::Metrics,
+    reject::{Quarantine, RejectReason, RejectSink},
+    repair::RepairObserver,
+    types::{ReceivedShred, ShredSource},
+    utils::parse_shred,
+};
+use crossbeam_channel::{Receiver, Sender};
+use log::{Level, error, info, log};
 use solana_ledger::shred::Shred;
-use std::{net::UdpSocket, sync::Arc, thread, time::Duration};
-use std::sync::mpsc;
+use solana_sdk::clock::Slot;
+use std::{
+    io::ErrorKind,
+    net::{SocketAddr, UdpSocket},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
+
+// how often the receive loop wakes up to check the exit flag when no packets arrive
+const RECV_TIMEOUT: Duration = Duration::from_millis(500);
+// every Nth shred is logged at info, the rest only at trace
+pub const DEFAULT_LOG_SAMPLE: u64 = 1000;
+
+pub const SIZE_OF_COMMON_HEADER: usize = 0x53;
+const OFFSET_VARIANT: usize = 0x40;
+const OFFSET_SLOT: usize = 0x41;
+const OFFSET_INDEX: usize = 0x49;
+const OFFSET_VERSION: usize = 0x4d;
+const OFFSET_FEC_SET_INDEX: usize = 0x4f;
+
+// the common header of a packet, read in place without copying the payload
+#[derive(Debug, Clone, Copy)]
+pub struct ShredHeaderView<'a> {
+    payload: &'a [u8],
+    variant: ShredVariant,
+    shred_type: ShredKind,
+}
+
+impl<'a> ShredHeaderView<'a> {
+    // fails if the packet is shorter than the common header or the variant is unknown
+    pub fn new(payload: &'a [u8]) -> Result<Self, RejectReason> {
+        if payload.len() < SIZE_OF_COMMON_HEADER {
+            return Err(RejectReason::TooShort);
+        }
+        let variant = ShredVariant::decode(payload[OFFSET_VARIANT]);
+        Ok(Self {
+            payload,
+            variant,
+            shred_type: variant.shred_type.ok_or(RejectReason::UnknownVariant)?,
+        })
+    }
+
+    pub fn payload(&self) -> &'a [u8] {
+        self.payload
+    }
+
+    pub fn variant(&self) -> ShredVariant {
+        self.variant
+    }
+
+    pub fn shred_type(&self) -> ShredKind {
+        self.shred_type
+    }
+
+    pub fn is_data(&self) -> bool {
+        self.shred_type == ShredKind::Data
+    }
+
+    pub fn slot(&self) -> Slot {
+        u64::from_le_bytes(self.field(OFFSET_SLOT))
+    }
+
+    pub fn index(&self) -> u32 {
+        u32::from_le_bytes(self.field(OFFSET_INDEX))
+    }
+
+    pub fn version(&self) -> u16 {
+        u16::from_le_bytes(self.field(OFFSET_VERSION))
+    }
+
+    pub fn fec_set_index(&self) -> u32 {
+        u32::from_le_bytes(self.field(OFFSET_FEC_SET_INDEX))
+    }
+
+    // copies the payload and deserializes the full shred
+    pub fn to_shred(&self) -> Result<Shred, Error> {
+        parse_shred(self.payload)
+    }
+
+    // all offsets are inside the common header, which new() checked is there
+    fn field<const N: usize>(&self, offset: usize) -> [u8; N] {
+        self.payload[offset..offset + N]
+            .try_into()
+            .expect("common header field")
+    }
+}
 
 pub struct ShredReceiver {
     socket: Arc<UdpSocket>,
-    sender: mpsc::Sender<Shred>,
-    receiver: Option<mpsc::Receiver<Shred>>,
+    sender: Sender<ReceivedShred>,
+    receiver: Option<Receiver<ReceivedShred>>,
+    exit: Arc<AtomicBool>,
+    repair: Option<RepairObserver>,
+    attribution: Option<PeerAttribution>,
+    metrics: Arc<Metrics>,
+    log_sample: u64,
+    shred_version: Option<u16>,
+    filter: ShredFilter,
+    drop_duplicates: bool,
+    rejects: RejectSink,
 }
 
 impl ShredReceiver {
-    pub fn new(socket: Arc<UdpSocket>) -> Self {
-        let (sender, receiver) = mpsc::channel::<Shred>();
+    pub fn new(socket: Arc<UdpSocket>, exit: Arc<AtomicBool>, metrics: Arc<Metrics>) -> Self {
+        let (sender, receiver) = crossbeam_channel::unbounded::<ReceivedShred>();
 
         if let Err(e) = socket.set_nonblocking(false) {
             error!("Failed to set socket blocking: {}", e);
         }
+        if let Err(e) = socket.set_read_timeout(Some(RECV_TIMEOUT)) {
+            error!("Failed to set socket read timeout: {}", e);
+        }
+
+        let mut rejects = RejectSink::new(ShredSource::Turbine);
+        rejects.set_metrics(metrics.clone());
 
         Self {
             socket,
             sender,
-            receiver: Some(receiver), 
+            receiver: Some(receiver),
+            exit,
+            repair: None,
+            attribution: None,
+            metrics,
+            log_sample: DEFAULT_LOG_SAMPLE,
+            shred_version: None,
+            filter: ShredFilter::default(),
+            drop_duplicates: false,
+            rejects,
         }
     }
 
-    fn process_packet(data: &[u8], sender_addr: std::net::SocketAddr, count: u64) -> Option<Shred> {
-        match parse_shred(data) {
-            Ok(shred) => {
-                info!(
-                    "SHRED #{}: Slot:{} Index:{} Type:{:?} from {}",
-                    count,
-                    shred.slot(),
-                    shred.index(),
-                    shred.shred_type(),
-                    sender_addr
-                );
+    // report every turbine shred to the repair client so it can find gaps
+    pub fn set_repair_observer(&mut self, observer: RepairObserver) {
+        self.repair = Some(observer);
+    }
 
-                Some(shred)
-            }
-            Err(_) => {
-                debug!(
-                    "NON-SHRED #{}: {} bytes from {}",
-                    count,
-                    data.len(),
-                    sender_addr
-                );
-                None
-            }
+    // per source peer packet, duplicate and first-arrival counts
+    pub fn set_attribution(&mut self, attribution: PeerAttribution) {
+        self.attribution = Some(attribution);
+    }
+
+    pub fn set_log_sample(&mut self, log_sample: u64) {
+        self.log_sample = log_sample.max(1);
+    }
+
+    // drop shreds of other clusters (or forks of the cluster) instead of passing them on
+    pub fn set_shred_version(&mut self, shred_version: Option<u16>) {
+        self.shred_version = shred_version;
+    }
+
+    // shreds outside the filter are dropped before they are parsed, so neither the slot
+    // tracker nor the plugins nor an archive see them
+    pub fn set_filter(&mut self, filter: ShredFilter) {
+        self.filter = filter;
+    }
+
+    // drop shreds another peer already delivered, needs set_attribution (it keeps the seen set)
+    pub fn set_drop_duplicates(&mut self, drop_duplicates: bool) {
+        self.drop_duplicates = drop_duplicates;
+    }
+
+    // raw bytes of rejected packets go to disk as well
+    pub fn set_quarantine(&mut self, quarantine: Quarantine) {
+        self.rejects.set_quarantine(quarantine);
+    }
+
+    // the checks configured so far, for packets of `source`. Call after the setters above
+    pub fn pipeline(&self, source: ShredSource) -> PacketPipeline {
+        PacketPipeline {
+            source,
+            attribution: self.attribution.clone(),
+            metrics: self.metrics.clone(),
+            log_sample: self.log_sample,
+            shred_version: self.shred_version,
+            filter: self.filter.clone(),
+            drop_duplicates: self.drop_duplicates,
+            rejects: self.rejects.with_source(source),
         }
     }
 
     pub fn start(&mut self) -> thread::JoinHandle<()> {
         let socket = self.socket.clone();
         let sender = self.sender.clone();
+        let exit = self.exit.clone();
+        let repair = self.repair.clone();
+        let metrics = self.metrics.clone();
+        let pipeline = self.pipeline(ShredSource::Turbine);
 
         thread::spawn(move || {
             info!("Starting shred receiver...");
 
             let mut buffer = [0u8; 1232];
-            let mut stats = ReceiveStats::new();
 
-            loop {
+            while !exit.load(Ordering::Relaxed) {
                 match socket.recv_from(&mut buffer) {
                     Ok((size, sender_addr)) => {
-                        stats.increment();
+                        let received_at = SystemTime::now();
+                        metrics.packets_received.inc();
 
-                        if let Some(shred_data) =
-                            Self::process_packet(&buffer[..size], sender_addr, stats.count)
-                        {
-                            // CHANGED: Use std::sync::mpsc send() instead of tokio
-                            if sender.send(shred_data).is_err() {
+                        let received = pipeline.process(&buffer[..size], sender_addr, received_at);
+                        if let Some(received) = received {
+                            if let Some(repair) = &repair {
+                                repair.observe(&received.shred);
+                            }
+
+                            if sender.send(received).is_err() {
+                                metrics.packets_dropped.inc();
                                 error!("Output channel closed, stopping receiver");
                                 break;
                             }
                         }
-
-                        stats.maybe_log();
                     }
+                    Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
                     Err(e) => {
+                        metrics.packets_dropped.inc();
                         error!("Receive error: {}", e);
                         thread::sleep(Duration::from_millis(100));
                     }
                 }
             }
+
+            info!(
+                "Shred receiver stopped after {} packets",
+                metrics.packets_received.get()
+            );
         })
     }
 
-    pub fn take_receiver(&mut self) -> mpsc::Receiver<Shred> {
+    // lets other producers (e.g. the repair client) feed the same pipeline
+    pub fn sender(&self) -> Sender<ReceivedShred> {
+        self.sender.clone()
+    }
+
+    pub fn take_receiver(&mut self) -> Receiver<ReceivedShred> {
         self.receiver.take().expect("Receiver already taken")
     }
 }
+
+// what a receive path needs to decide on a packet, see ShredReceiver::pipeline()
+#[derive(Clone)]
+pub struct PacketPipeline {
+    source: ShredSource,
+    attribution: Option<PeerAttribution>,
+    metrics: Arc<Metrics>,
+    log_sample: u64,
+    shred_version: Option<u16>,
+    filter: ShredFilter,
+    drop_duplicates: bool,
+    rejects: RejectSink,
+}
+
+impl PacketPipeline {
+    // None if the packet was rejected, skipped or is a dropped duplicate
+    pub fn process(
+        &self,
+        data: &[u8],
+        sender_addr: SocketAddr,
+        received_at: SystemTime,
+    ) -> Option<ReceivedShred> {
+        let metrics = &self.metrics;
+        let count = metrics.packets_received.get();
+
+        let header = ShredHeaderView::new(data).and_then(|header| match self.shred_version {
+            Some(version) if version != header.version() => Err(RejectReason::WrongShredVersion),
+            _ => Ok(header),
+        });
+        let header = match header {
+            Ok(header) => header,
+            Err(reason) => {
+                if let Some(attribution) = &self.attribution {
+                    attribution.record(sender_addr, None);
+                }
+                return self.reject(reason, data, sender_addr, received_at);
+            }
+        };
+
+        let duplicate = self.drop_duplicates
+            && self
+                .attribution
+                .as_ref()
+                .is_some_and(|attribution| attribution.seen(&header) == Some(true));
+        if duplicate || !self.filter.matches_header(&header) {
+            // still counted for the sender, filtered shreds are recorded without a parse
+            if let Some(attribution) = &self.attribution {
+                attribution.record(sender_addr, Some(&header));
+            }
+            return self.skip(if duplicate { "duplicate" } else { "filter" });
+        }
+
+        let started = Instant::now();
+        let parsed = header.to_shred();
+        metrics
+            .shred_parse_seconds
+            .observe(started.elapsed().as_secs_f64());
+        let shred = match parsed {
+            Ok(shred) => shred,
+            Err(e) => {
+                if let Some(attribution) = &self.attribution {
+                    attribution.record(sender_addr, None);
+                }
+                let reason = RejectReason::classify(data, &e);
+                return self.reject(reason, data, sender_addr, received_at);
+            }
+        };
+
+        let arrival = self
+            .attribution
+            .as_ref()
+            .and_then(|attribution| attribution.record(sender_addr, Some(&header)));
+        if self.drop_duplicates && arrival == Some(false) {
+            return self.skip("duplicate");
+        }
+
+        metrics.shreds_parsed.inc();
+        let level = if metrics.shreds_parsed.get().is_multiple_of(self.log_sample) {
+            Level::Info
+        } else {
+            Level::Trace
+        };
+        log!(
+            level,
+            "SHRED #{}: Slot:{} Index:{} Type:{:?} {:?} from {}",
+            count,
+            shred.slot(),
+            shred.index(),
+            shred.shred_type(),
+            self.source,
+            sender_addr
+        );
+
+        Some(ReceivedShred {
+            shred: Arc::new(shred),
+            source: self.source,
+            from: sender_addr,
+            received_at,
+        })
+    }
+
+    fn skip(&self, reason: &str) -> Option<ReceivedShred> {
+        self.metrics
+            .shreds_skipped
+            .with_label_values(&[reason])
+            .inc();
+        None
+    }
+
+    fn reject(
+        &self,
+        reason: RejectReason,
+        data: &[u8],
+        sender_addr: SocketAddr,
+        received_at: SystemTime,
+    ) -> Option<ReceivedShred> {
+        self.metrics.non_shred_packets.inc();
+        self.rejects.reject(reason, sender_addr, received_at, data);
+        None
+    }
+}
+
+// a chained Merkle data shred carrying `data`, signature and proof zeroed, for tests
+#[cfg(test)]
+pub(crate) fn test_data_shred(
+    slot: Slot,
+    index: u32,
+    fec_set_index: u32,
+    flags: u8,
+    data: &[u8],
+) -> Vec<u8> {
+    const PROOF_SIZE: u8 = 6;
+    let mut payload = vec![0u8; 1203];
+    payload[OFFSET_VARIANT] = 0x90 | PROOF_SIZE;
+    payload[OFFSET_SLOT..OFFSET_SLOT + 8].copy_from_slice(&slot.to_le_bytes());
+    payload[OFFSET_INDEX..OFFSET_INDEX + 4].copy_from_slice(&index.to_le_bytes());
+    payload[OFFSET_VERSION..OFFSET_VERSION + 2].copy_from_slice(&TEST_SHRED_VERSION.to_le_bytes());
+    payload[OFFSET_FEC_SET_INDEX..OFFSET_FEC_SET_INDEX + 4]
+        .copy_from_slice(&fec_set_index.to_le_bytes());
+    // parent_offset 1, the data flags and size
+    payload[0x53..0x55].copy_from_slice(&1u16.to_le_bytes());
+    payload[0x55] = flags;
+    let size = (0x58 + data.len()) as u16;
+    payload[0x56..0x58].copy_from_slice(&size.to_le_bytes());
+    payload[0x58..0x58 + data.len()].copy_from_slice(data);
+    payload
+}
+
+#[cfg(test)]
+pub(crate) const TEST_SHRED_VERSION: u16 = 50093;
+
+#[cfg(test)]
+mod tests {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::*;
+    use crate::attribution::{AttributionConfig, test_attribution};
+
+    fn pipeline(attribution: PeerAttribution) -> PacketPipeline {
+        PacketPipeline {
+            source: ShredSource::Turbine,
+            attribution: Some(attribution),
+            metrics: Arc::new(Metrics::new()),
+            log_sample: DEFAULT_LOG_SAMPLE,
+            shred_version: Some(TEST_SHRED_VERSION),
+            filter: ShredFilter::default(),
+            drop_duplicates: true,
+            rejects: RejectSink::new(ShredSource::Turbine),
+        }
+    }
+
+    fn peer(last_octet: u8) -> SocketAddr {
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, last_octet)), 8001)
+    }
+
+    #[test]
+    fn corrupt_packet_doesnt_shadow_the_valid_shred() {
+        let attribution = test_attribution(AttributionConfig::default());
+        let pipeline = pipeline(attribution.clone());
+        let valid = test_data_shred(100, 7, 0, 0, b"entries");
+        // same header, size field past the payload
+        let mut corrupt = valid.clone();
+        corrupt[0x56..0x58].copy_from_slice(&u16::MAX.to_le_bytes());
+
+        let now = SystemTime::now();
+        assert!(pipeline.process(&corrupt, peer(1), now).is_none());
+        assert_eq!(pipeline.metrics.non_shred_packets.get(), 1);
+
+        let received = pipeline.process(&valid, peer(2), now).unwrap();
+        assert_eq!((received.shred.slot(), received.shred.index()), (100, 7));
+        // the real copy got the first arrival, not the corrupt one
+        let top = attribution.top(2);
+        assert_eq!(top[0].addr, peer(2).ip());
+        assert_eq!(top[0].first_arrivals, 1);
+        assert_eq!(top[1].first_arrivals, 0);
+        assert_eq!(top[1].valid, 0);
+
+        // a second valid copy is a duplicate
+        assert!(pipeline.process(&valid, peer(3), now).is_none());
+        assert_eq!(
+            pipeline
+                .metrics
+                .shreds_skipped
+                .with_label_values(&["duplicate"])
+                .get(),
+            1
+        );
+    }
+
+    #[test]
+    fn wrong_shred_version_is_rejected() {
+        let pipeline = pipeline(test_attribution(AttributionConfig::default()));
+        let mut payload = test_data_shred(100, 7, 0, 0, b"entries");
+        payload[OFFSET_VERSION..OFFSET_VERSION + 2].copy_from_slice(&1u16.to_le_bytes());
+        assert!(
+            pipeline
+                .process(&payload, peer(1), SystemTime::now())
+                .is_none()
+        );
+        assert_eq!(pipeline.metrics.non_shred_packets.get(), 1);
+    }
+}
@@ -0,0 +1,75 @@
+/*
+ ** Graceful Shutdown **
+: All long running parts of ChainSmoker share the `exit: Arc<AtomicBool>` owned by
+: `GossipNode`. SIGINT/SIGTERM set the flag and every loop polls it, so a single signal
+: winds the whole node down in a fixed order without dropping shreds already received.
+
+*  ** Shutdown Sequence **
+! +------+----------------------------------------------------------------+
+! | Step | Action                                                         |
+! +------+----------------------------------------------------------------+
+! | 1    | Signal received, `exit` set to true                            |
+! | 2    | ShredReceiver thread notices `exit` on its next socket timeout |
+! | 3    | Shreds left in the channel are drained into the archive, slot  |
+! |      | and latency trackers, shred stream and plugins like any other  |
+! |      | (bounded by `drain_timeout`)                                   |
+! | 4    | PluginRunner::stop_all() stops every running plugin            |
+! | 5    | GossipNode::shutdown() joins the GossipService threads         |
+! +------+----------------------------------------------------------------+
+
+: A second signal while shutting down exits the process immediately.
+*/
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use log::{error, info, warn};
+use tokio::signal::unix::{SignalKind, signal};
+
+pub const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Clone, Copy)]
+pub struct ShutdownConfig {
+    pub drain_timeout: Duration,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+        }
+    }
+}
+
+pub async fn wait_for_signal() -> std::io::Result<&'static str> {
+    let mut sigint = signal(SignalKind::interrupt())?;
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    let name = tokio::select! {
+        _ = sigint.recv() => "SIGINT",
+        _ = sigterm.recv() => "SIGTERM",
+    };
+    Ok(name)
+}
+
+// sets `exit` on the first SIGINT/SIGTERM, hard exits on the second one
+pub async fn watch_signals(exit: Arc<AtomicBool>) {
+    match wait_for_signal().await {
+        Ok(name) => info!("Received {}, shutting down...", name),
+        Err(e) => {
+            error!("Failed to install signal handlers: {}", e);
+            return;
+        }
+    }
+    exit.store(true, Ordering::Relaxed);
+
+    if let Ok(name) = wait_for_signal().await {
+        warn!("Received {} during shutdown, exiting immediately", name);
+        std::process::exit(1);
+    }
+}
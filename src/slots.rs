@@ -0,0 +1,427 @@
+/*
+ ** Slot Tracker **
+: Keeps a per-slot record of which shreds arrived (turbine and repair) and turns it into a
+: `SlotReport` once the slot is finished. A slot is finished when LAST_IN_SLOT arrived and
+: every data shred up to it is present, or when no shred for it arrived for
+: `slot_timeout`. Reports go to plugins via `OutputPlugin::handle_slot_report()`.
+
+*  ** FEC Sets **
+: Data shreds are grouped in FEC sets (keyed by `fec_set_index`, the index of the first
+: data shred in the set). Coding shreds of a set carry the number of data shreds in it.
+: When no coding shred was seen, the size is taken from the next set's start index or
+: from LAST_IN_SLOT. Sets no shred arrived for leave a gap between the end of one set and
+: the start of the next (or LAST_IN_SLOT); each gap is reported as one lost set starting
+: at the gap, since how many sets it spans isn't known.
+
+! +-------------+------------------------------------------------------------+
+! | State       | Condition                                                  |
+! +-------------+------------------------------------------------------------+
+! | complete    | every data shred of the set arrived                        |
+! | recoverable | data shreds missing, but data + coding >= num_data_shreds  |
+! |             | so erasure recovery could rebuild them                     |
+! | lost        | not enough shreds to rebuild the missing data, or no shred |
+! |             | of the set arrived at all                                  |
+! +-------------+------------------------------------------------------------+
+
+*  ** Coding Shred Header (raw) **
+: `num_data_shreds` is crate-private in solana-ledger, so it is read from the payload:
+: u16 at 0x53 (see shred.rs for the full layout).
+*/
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info};
+use serde::Serialize;
+use solana_ledger::shred::Shred;
+use solana_sdk::clock::Slot;
+
+use crate::{metrics::Metrics, types::ReceivedShred};
+
+const NUM_DATA_SHREDS_OFFSET: usize = 0x53;
+
+#[derive(Debug, Clone, Copy)]
+pub struct SlotTrackerConfig {
+    // a slot without new shreds for this long is reported as timed out
+    pub slot_timeout: Duration,
+    pub max_tracked_slots: usize,
+}
+
+impl Default for SlotTrackerConfig {
+    fn default() -> Self {
+        Self {
+            slot_timeout: Duration::from_secs(5),
+            max_tracked_slots: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SlotReport {
+    pub slot: Slot,
+    pub data_shreds: usize,
+    pub coding_shreds: usize,
+    pub repaired_shreds: usize,
+    pub highest_index: u32,
+    pub last_in_slot: bool,
+    pub missing: Vec<u32>,
+    pub complete_fec_sets: usize,
+    pub recoverable_fec_sets: Vec<u32>,
+    pub lost_fec_sets: Vec<u32>,
+    pub timed_out: bool,
+    // first to last shred seen
+    pub duration: Duration,
+}
+
+impl SlotReport {
+    pub fn is_complete(&self) -> bool {
+        self.last_in_slot && self.missing.is_empty()
+    }
+
+    // true when erasure recovery could rebuild everything that is missing
+    pub fn is_recoverable(&self) -> bool {
+        self.last_in_slot && self.lost_fec_sets.is_empty()
+    }
+}
+
+// a slot that is still being received
+#[derive(Debug, Clone, Serialize)]
+pub struct SlotProgress {
+    pub slot: Slot,
+    pub data_shreds: usize,
+    pub coding_shreds: usize,
+    pub highest_index: u32,
+    pub last_index: Option<u32>,
+    pub repaired_shreds: usize,
+    pub age_ms: u64,
+}
+
+#[derive(Default)]
+struct FecSetState {
+    coding: BTreeSet<u32>,
+    num_data: Option<u32>,
+}
+
+struct SlotState {
+    data: BTreeSet<u32>,
+    fec_sets: BTreeMap<u32, FecSetState>,
+    highest_index: u32,
+    last_index: Option<u32>,
+    repaired: usize,
+    first_seen: Instant,
+    last_update: Instant,
+}
+
+impl SlotState {
+    fn new(now: Instant) -> Self {
+        Self {
+            data: BTreeSet::new(),
+            fec_sets: BTreeMap::new(),
+            highest_index: 0,
+            last_index: None,
+            repaired: 0,
+            first_seen: now,
+            last_update: now,
+        }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.last_index
+            .is_some_and(|last| self.data.len() as u32 == last + 1)
+    }
+
+    fn report(&self, slot: Slot, timed_out: bool) -> SlotReport {
+        let upper = self.last_index.unwrap_or(self.highest_index);
+        let missing: Vec<u32> = (0..=upper)
+            .filter(|index| !self.data.contains(index))
+            .collect();
+
+        let mut complete_fec_sets = 0;
+        let mut recoverable_fec_sets = Vec::new();
+        let mut lost_fec_sets = Vec::new();
+
+        // where the next set should start if none is missing in between
+        let mut expected = 0;
+        let starts: Vec<u32> = self.fec_sets.keys().copied().collect();
+        for (i, (&start, fec_set)) in self.fec_sets.iter().enumerate() {
+            if start > expected {
+                lost_fec_sets.push(expected);
+            }
+            let end = match (fec_set.num_data, starts.get(i + 1), self.last_index) {
+                (Some(num_data), _, _) => start + num_data,
+                (None, Some(&next), _) => next,
+                (None, None, Some(last)) => last + 1,
+                (None, None, None) => self.highest_index + 1,
+            };
+            let num_data = end.saturating_sub(start);
+            let received = self.data.range(start..end).count() as u32;
+
+            if received >= num_data {
+                complete_fec_sets += 1;
+            } else if received + fec_set.coding.len() as u32 >= num_data {
+                recoverable_fec_sets.push(start);
+            } else {
+                lost_fec_sets.push(start);
+            }
+            expected = expected.max(end);
+        }
+        if let Some(last) = self.last_index
+            && expected <= last
+        {
+            lost_fec_sets.push(expected);
+        }
+
+        SlotReport {
+            slot,
+            data_shreds: self.data.len(),
+            coding_shreds: self.fec_sets.values().map(|set| set.coding.len()).sum(),
+            repaired_shreds: self.repaired,
+            highest_index: self.highest_index,
+            last_in_slot: self.last_index.is_some(),
+            missing,
+            complete_fec_sets,
+            recoverable_fec_sets,
+            lost_fec_sets,
+            timed_out,
+            duration: self.last_update.duration_since(self.first_seen),
+        }
+    }
+}
+
+#[derive(Default)]
+struct SlotSummary {
+    complete: u64,
+    incomplete: u64,
+    recoverable: u64,
+    lost_fec_sets: u64,
+}
+
+pub struct SlotTracker {
+    config: SlotTrackerConfig,
+    slots: BTreeMap<Slot, SlotState>,
+    // reported slots, so late shreds don't open the slot again
+    finished: BTreeSet<Slot>,
+    ready: Vec<SlotReport>,
+    metrics: Option<Arc<Metrics>>,
+    summary: SlotSummary,
+    last_log: Instant,
+}
+
+impl SlotTracker {
+    pub fn new(config: SlotTrackerConfig) -> Self {
+        Self {
+            config,
+            slots: BTreeMap::new(),
+            finished: BTreeSet::new(),
+            ready: Vec::new(),
+            metrics: None,
+            summary: SlotSummary::default(),
+            last_log: Instant::now(),
+        }
+    }
+
+    pub fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    pub fn observe(&mut self, received: &ReceivedShred) {
+        let shred = &received.shred;
+        let slot = shred.slot();
+        let stale = self.finished.first().is_some_and(|&oldest| slot < oldest);
+        if stale || self.finished.contains(&slot) {
+            return;
+        }
+
+        let now = Instant::now();
+        let state = self
+            .slots
+            .entry(slot)
+            .or_insert_with(|| SlotState::new(now));
+        state.last_update = now;
+
+        let fec_set = state.fec_sets.entry(shred.fec_set_index()).or_default();
+        let new = if shred.is_data() {
+            let new = state.data.insert(shred.index());
+            state.highest_index = state.highest_index.max(shred.index());
+            if shred.last_in_slot() {
+                state.last_index = Some(shred.index());
+            }
+            new
+        } else {
+            if fec_set.num_data.is_none() {
+                fec_set.num_data = num_data_shreds(shred);
+            }
+            fec_set.coding.insert(shred.index())
+        };
+        if new && received.is_repaired() {
+            state.repaired += 1;
+        }
+
+        if state.is_complete() {
+            self.finish(slot, false);
+        }
+
+        while self.slots.len() > self.config.max_tracked_slots {
+            if let Some(&oldest) = self.slots.keys().next() {
+                self.finish(oldest, true);
+            }
+        }
+    }
+
+    // finished and timed out slots since the last call
+    pub fn poll(&mut self) -> Vec<SlotReport> {
+        let now = Instant::now();
+        let timed_out: Vec<Slot> = self
+            .slots
+            .iter()
+            .filter(|(_, state)| now.duration_since(state.last_update) >= self.config.slot_timeout)
+            .map(|(&slot, _)| slot)
+            .collect();
+        for slot in timed_out {
+            self.finish(slot, true);
+        }
+
+        if self.last_log.elapsed() >= Duration::from_secs(10) {
+            info!(
+                "Slot Stats: {} complete, {} incomplete ({} recoverable), {} lost FEC sets",
+                self.summary.complete,
+                self.summary.incomplete,
+                self.summary.recoverable,
+                self.summary.lost_fec_sets
+            );
+            self.last_log = Instant::now();
+        }
+
+        std::mem::take(&mut self.ready)
+    }
+
+    pub fn progress(&self) -> Vec<SlotProgress> {
+        self.slots
+            .iter()
+            .map(|(&slot, state)| SlotProgress {
+                slot,
+                data_shreds: state.data.len(),
+                coding_shreds: state.fec_sets.values().map(|set| set.coding.len()).sum(),
+                highest_index: state.highest_index,
+                last_index: state.last_index,
+                repaired_shreds: state.repaired,
+                age_ms: state.first_seen.elapsed().as_millis() as u64,
+            })
+            .collect()
+    }
+
+    // reports every slot still open, used on shutdown
+    pub fn flush(&mut self) -> Vec<SlotReport> {
+        let slots: Vec<Slot> = self.slots.keys().copied().collect();
+        for slot in slots {
+            self.finish(slot, true);
+        }
+        std::mem::take(&mut self.ready)
+    }
+
+    fn finish(&mut self, slot: Slot, timed_out: bool) {
+        let Some(state) = self.slots.remove(&slot) else {
+            return;
+        };
+        let report = state.report(slot, timed_out);
+
+        debug!(
+            "SLOT {}: {} data, {} coding, {} missing, {} lost FEC sets{}",
+            slot,
+            report.data_shreds,
+            report.coding_shreds,
+            report.missing.len(),
+            report.lost_fec_sets.len(),
+            if timed_out { " (timed out)" } else { "" }
+        );
+
+        if report.is_complete() {
+            self.summary.complete += 1;
+        } else {
+            self.summary.incomplete += 1;
+            if report.is_recoverable() {
+                self.summary.recoverable += 1;
+            }
+        }
+        self.summary.lost_fec_sets += report.lost_fec_sets.len() as u64;
+
+        if let Some(metrics) = &self.metrics {
+            let status = if report.is_complete() {
+                "complete"
+            } else if report.is_recoverable() {
+                "recoverable"
+            } else {
+                "incomplete"
+            };
+            metrics.slots_finished.with_label_values(&[status]).inc();
+            metrics
+                .fec_sets_recoverable
+                .inc_by(report.recoverable_fec_sets.len() as u64);
+            metrics
+                .fec_sets_lost
+                .inc_by(report.lost_fec_sets.len() as u64);
+        }
+
+        self.finished.insert(slot);
+        while self.finished.len() > self.config.max_tracked_slots * 4 {
+            self.finished.pop_first();
+        }
+        self.ready.push(report);
+    }
+}
+
+pub fn num_data_shreds(shred: &Shred) -> Option<u32> {
+    let payload: &[u8] = shred.payload();
+    let bytes = payload.get(NUM_DATA_SHREDS_OFFSET..NUM_DATA_SHREDS_OFFSET + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fec_set(num_data: u32) -> FecSetState {
+        FecSetState {
+            coding: BTreeSet::new(),
+            num_data: Some(num_data),
+        }
+    }
+
+    #[test]
+    fn fec_sets_without_shreds_are_lost() {
+        let now = Instant::now();
+        let mut state = SlotState::new(now);
+        // sets at 0, 32, 64 and 96, nothing of 32 and 96 arrived
+        state.data.extend(0..32);
+        state.data.extend(64..96);
+        state.fec_sets.insert(0, fec_set(32));
+        state.fec_sets.insert(64, fec_set(32));
+        state.highest_index = 127;
+        state.last_index = Some(127);
+
+        let report = state.report(1, true);
+        assert_eq!(report.complete_fec_sets, 2);
+        assert!(report.recoverable_fec_sets.is_empty());
+        assert_eq!(report.lost_fec_sets, vec![32, 96]);
+        assert!(!report.is_recoverable());
+    }
+
+    #[test]
+    fn complete_slot_has_no_lost_fec_sets() {
+        let now = Instant::now();
+        let mut state = SlotState::new(now);
+        state.data.extend(0..64);
+        state.fec_sets.insert(0, fec_set(32));
+        state.fec_sets.insert(32, fec_set(32));
+        state.highest_index = 63;
+        state.last_index = Some(63);
+
+        let report = state.report(1, false);
+        assert_eq!(report.complete_fec_sets, 2);
+        assert!(report.lost_fec_sets.is_empty());
+        assert!(report.is_complete());
+    }
+}
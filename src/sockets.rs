@@ -0,0 +1,205 @@
+/*
+ ** Node Sockets **
+: ChainSmoker needs two UDP sockets: one for gossip and one for TVU (shred reception),
+: plus an optional third one for the repair client. Observer and spy modes only join
+: gossip, so only the gossip socket is bound.
+: The addresses we bind locally and the addresses we advertise to the cluster in our
+: `ContactInfo` are not always the same, e.g. when running behind NAT or on a host with
+: several interfaces.
+
+*  ** Addresses **
+! +-------------------+----------------------------------------------------------+
+! | Field             | Purpose                                                  |
+! +-------------------+----------------------------------------------------------+
+! | mode              | Full binds TVU (+ repair), Observer/Spy only gossip      |
+! | bind_address      | Local interface the sockets are bound to (0.0.0.0 = all) |
+! | advertise_address | Public IP put in ContactInfo, discovered if not given    |
+! | port_range        | Range to pick free gossip/TVU ports from                 |
+! | gossip_port       | Fixed gossip port, overrides the range                   |
+! | tvu_port          | Fixed TVU port, overrides the range                      |
+! | repair            | Bind a repair socket (advertised as serve-repair)        |
+! | repair_port       | Fixed repair port, overrides the range                   |
+! +-------------------+----------------------------------------------------------+
+
+*  ** Public IP Discovery **
+: If no advertise address is configured, the public IP is discovered by asking the
+: ip-echo service that runs on every entrypoint (same port as gossip). The first
+: entrypoint that answers wins.
+
+*  ** Reachability **
+: Before the ContactInfo is advertised, the entrypoint ip-echo service is asked to send
+: a UDP datagram to each of our ports. If any port is unreachable, validators would never
+: be able to send us shreds, so binding fails early instead of silently receiving nothing.
+: Spy mode advertises an unroutable gossip address, so there is nothing to verify.
+*/
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, UdpSocket};
+
+use log::{info, warn};
+use solana_net_utils::PortRange;
+
+use crate::{error::Error, types::NodeMode, utils::discover_public_ip};
+
+pub const DEFAULT_PORT_RANGE: PortRange = (8000, 10_000);
+
+#[derive(Debug, Clone)]
+pub struct SocketConfig {
+    pub mode: NodeMode,
+    pub bind_address: IpAddr,
+    pub advertise_address: Option<IpAddr>,
+    pub port_range: PortRange,
+    pub gossip_port: Option<u16>,
+    pub tvu_port: Option<u16>,
+    pub repair: bool,
+    pub repair_port: Option<u16>,
+    pub verify_reachable: bool,
+}
+
+impl Default for SocketConfig {
+    fn default() -> Self {
+        Self {
+            mode: NodeMode::Full,
+            bind_address: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            advertise_address: None,
+            port_range: DEFAULT_PORT_RANGE,
+            gossip_port: None,
+            tvu_port: None,
+            repair: false,
+            repair_port: None,
+            verify_reachable: true,
+        }
+    }
+}
+
+pub struct NodeSockets {
+    pub gossip: UdpSocket,
+    pub tvu: Option<UdpSocket>,
+    pub repair: Option<UdpSocket>,
+    pub bind_address: IpAddr,
+    pub advertise_address: IpAddr,
+}
+
+impl NodeSockets {
+    pub fn bind(config: &SocketConfig, entrypoints: &[SocketAddr]) -> Result<Self, Error> {
+        let advertise_address = match config.advertise_address {
+            Some(addr) => addr,
+            None => discover_public_ip(entrypoints, config.bind_address)?,
+        };
+
+        let gossip = bind_port(
+            "gossip",
+            config.bind_address,
+            config.gossip_port,
+            config.port_range,
+        )?;
+        let receives_shreds = config.mode.receives_shreds();
+        let tvu = if receives_shreds {
+            Some(bind_port(
+                "tvu",
+                config.bind_address,
+                config.tvu_port,
+                config.port_range,
+            )?)
+        } else {
+            None
+        };
+        let repair = if receives_shreds && config.repair {
+            Some(bind_port(
+                "repair",
+                config.bind_address,
+                config.repair_port,
+                config.port_range,
+            )?)
+        } else {
+            None
+        };
+
+        let sockets = Self {
+            gossip,
+            tvu,
+            repair,
+            bind_address: config.bind_address,
+            advertise_address,
+        };
+
+        match &sockets.tvu {
+            Some(tvu) => info!(
+                "Bound gossip {} and TVU {} (advertising {})",
+                sockets.gossip.local_addr()?,
+                tvu.local_addr()?,
+                advertise_address
+            ),
+            None => info!(
+                "Bound gossip {} in {:?} mode (advertising {})",
+                sockets.gossip.local_addr()?,
+                config.mode,
+                advertise_address
+            ),
+        }
+
+        if config.verify_reachable
+            && config.mode != NodeMode::Spy
+            && !sockets.verify_reachable(entrypoints)
+        {
+            return Err(Error::Unreachable { advertise_address });
+        }
+
+        Ok(sockets)
+    }
+
+    pub fn gossip_addr(&self) -> SocketAddr {
+        advertised_addr(self.advertise_address, &self.gossip)
+    }
+
+    pub fn tvu_addr(&self) -> Option<SocketAddr> {
+        self.tvu
+            .as_ref()
+            .map(|socket| advertised_addr(self.advertise_address, socket))
+    }
+
+    pub fn repair_addr(&self) -> Option<SocketAddr> {
+        self.repair
+            .as_ref()
+            .map(|socket| advertised_addr(self.advertise_address, socket))
+    }
+
+    pub fn verify_reachable(&self, entrypoints: &[SocketAddr]) -> bool {
+        let mut sockets = vec![&self.gossip];
+        sockets.extend(self.tvu.as_ref());
+        sockets.extend(self.repair.as_ref());
+
+        for entrypoint in entrypoints {
+            info!("Verifying gossip/TVU reachability via {}", entrypoint);
+            if solana_net_utils::verify_all_reachable_udp(entrypoint, &sockets) {
+                return true;
+            }
+            warn!("Reachability check via {} failed", entrypoint);
+        }
+        false
+    }
+}
+
+pub fn advertised_addr(advertise_address: IpAddr, socket: &UdpSocket) -> SocketAddr {
+    let port = socket
+        .local_addr()
+        .map(|addr| addr.port())
+        .unwrap_or_default();
+    SocketAddr::new(advertise_address, port)
+}
+
+fn bind_port(
+    socket: &'static str,
+    bind_address: IpAddr,
+    port: Option<u16>,
+    port_range: PortRange,
+) -> Result<UdpSocket, Error> {
+    let bound = match port {
+        Some(port) => UdpSocket::bind((bind_address, port)),
+        None => solana_net_utils::bind_in_range(bind_address, port_range).map(|(_, socket)| socket),
+    };
+    bound.map_err(|source| Error::SocketBind {
+        socket,
+        bind_address,
+        source,
+    })
+}
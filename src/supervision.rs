@@ -0,0 +1,129 @@
+/*
+ ** Plugin Supervision **
+: Every plugin runs under a `SupervisionConfig` that decides what happens when it keeps
+: failing, declared by the plugin (`OutputPlugin::supervision()`) or set per plugin in the
+: config file (`[plugins.supervision]`), which wins. Errors from handle_shared/batch,
+: gossip events and slot reports count; any success resets the count.
+
+*  ** Policies **
+! +---------+------------------------------------------------------------------------+
+! | Policy  | After `max_consecutive_errors` errors in a row, or a failed start()    |
+! +---------+------------------------------------------------------------------------+
+! | log     | keep dispatching and log each error; a failed start() disables it      |
+! |         | (default, one broken plugin doesn't take the node down)                |
+! | restart | stop() then start() it again after `restart_backoff_ms`, doubled per   |
+! |         | restart up to `max_backoff_ms`; disabled once `max_restarts` restarts  |
+! |         | in a row failed to make it healthy                                     |
+! | disable | stop dispatching to it until it is restarted through the admin API     |
+! | fatal   | shut the whole node down (a failed start() aborts start_all)           |
+! +---------+------------------------------------------------------------------------+
+: Shreds pending in a batch of a plugin that trips are dropped.
+
+*  ** State and Health **
+: `PluginState` is what the runner does with a plugin: running, restarting (waiting out
+: the backoff), disabled or failed (a fatal plugin tripped). `OutputPlugin::health()` is
+: what the plugin says about itself, e.g. degraded when its downstream is slow. Both are
+: in `PluginStatus` (GET /plugins), summed up on GET /health (503 unless every plugin is
+: running and not unhealthy) and exported as `chainsmoker_plugin_health{plugin}`:
+! +-------+-----------------------------------------------+
+! | Value | Meaning                                       |
+! +-------+-----------------------------------------------+
+! | 2     | running and healthy                           |
+! | 1     | running and degraded                          |
+! | 0     | unhealthy, restarting, disabled or failed     |
+! +-------+-----------------------------------------------+
+*/
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_MAX_CONSECUTIVE_ERRORS: u32 = 5;
+pub const DEFAULT_RESTART_BACKOFF_MS: u64 = 1_000;
+pub const DEFAULT_MAX_BACKOFF_MS: u64 = 60_000;
+pub const DEFAULT_MAX_RESTARTS: u32 = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ErrorPolicy {
+    #[default]
+    Log,
+    Restart,
+    Disable,
+    Fatal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisionConfig {
+    pub on_error: ErrorPolicy,
+    pub max_consecutive_errors: u32,
+    pub restart_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    pub max_restarts: u32,
+}
+
+impl Default for SupervisionConfig {
+    fn default() -> Self {
+        Self {
+            on_error: ErrorPolicy::default(),
+            max_consecutive_errors: DEFAULT_MAX_CONSECUTIVE_ERRORS,
+            restart_backoff_ms: DEFAULT_RESTART_BACKOFF_MS,
+            max_backoff_ms: DEFAULT_MAX_BACKOFF_MS,
+            max_restarts: DEFAULT_MAX_RESTARTS,
+        }
+    }
+}
+
+impl SupervisionConfig {
+    // wait before restart number `restarts` (0 for the first)
+    pub fn backoff(&self, restarts: u32) -> Duration {
+        let backoff = self
+            .restart_backoff_ms
+            .saturating_mul(1u64 << restarts.min(32))
+            .min(self.max_backoff_ms);
+        Duration::from_millis(backoff)
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_consecutive_errors == 0 {
+            return Err("max_consecutive_errors must be at least 1".to_string());
+        }
+        if self.restart_backoff_ms > self.max_backoff_ms {
+            return Err(format!(
+                "restart_backoff_ms {} is above max_backoff_ms {}",
+                self.restart_backoff_ms, self.max_backoff_ms
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PluginState {
+    Running,
+    Restarting,
+    Disabled,
+    Failed,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Serialize)]
+#[serde(tag = "status", content = "reason", rename_all = "lowercase")]
+pub enum PluginHealth {
+    #[default]
+    Healthy,
+    Degraded(String),
+    Unhealthy(String),
+}
+
+impl PluginHealth {
+    // chainsmoker_plugin_health value, see the table above
+    pub fn gauge(&self, state: PluginState) -> i64 {
+        match (state, self) {
+            (PluginState::Running, PluginHealth::Healthy) => 2,
+            (PluginState::Running, PluginHealth::Degraded(_)) => 1,
+            _ => 0,
+        }
+    }
+}
@@ -1,4 +1,11 @@
-#[derive(Debug, Clone, Copy)]
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use solana_ledger::shred::Shred;
+use solana_sdk::pubkey::Pubkey;
+use std::{net::SocketAddr, str::FromStr, sync::Arc, time::SystemTime};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Network {
     Mainnet,
     Testnet,
@@ -20,3 +27,104 @@ impl Network {
         }
     }
 }
+
+impl FromStr for Network {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "mainnet" | "mainnet-beta" => Ok(Network::Mainnet),
+            "testnet" => Ok(Network::Testnet),
+            _ => Err(format!("unknown network '{}' (mainnet, testnet)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeMode {
+    // gossip + TVU, shreds are received and passed to plugins
+    #[default]
+    Full,
+    // gossip only, no TVU is advertised so nobody sends us shreds
+    Observer,
+    // gossip only with an unroutable gossip address: we pull, peers can't push to us
+    Spy,
+}
+
+impl NodeMode {
+    pub fn receives_shreds(&self) -> bool {
+        matches!(self, NodeMode::Full)
+    }
+}
+
+impl FromStr for NodeMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "full" => Ok(NodeMode::Full),
+            "observer" => Ok(NodeMode::Observer),
+            "spy" => Ok(NodeMode::Spy),
+            _ => Err(format!("unknown mode '{}' (full, observer, spy)", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    pub pubkey: Pubkey,
+    pub gossip: Option<SocketAddr>,
+    pub tvu: Option<SocketAddr>,
+    pub serve_repair: Option<SocketAddr>,
+    pub shred_version: u16,
+    pub version: Option<String>,
+    pub last_updated: u64,
+}
+
+impl PeerInfo {
+    // pubkey as base58 rather than the byte array serde would give us
+    pub fn to_json(&self) -> Value {
+        json!({
+            "pubkey": self.pubkey.to_string(),
+            "gossip": self.gossip,
+            "tvu": self.tvu,
+            "serve_repair": self.serve_repair,
+            "shred_version": self.shred_version,
+            "version": self.version,
+            "last_updated": self.last_updated,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ShredSource {
+    Turbine,
+    Repair,
+}
+
+impl ShredSource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Turbine => "turbine",
+            Self::Repair => "repair",
+        }
+    }
+}
+
+// a shred as it leaves the receive side of the pipeline, tagged with where it came from;
+// the shred is shared from here on, plugins, archive and trackers never copy the payload
+#[derive(Debug, Clone)]
+pub struct ReceivedShred {
+    pub shred: Arc<Shred>,
+    pub source: ShredSource,
+    pub from: SocketAddr,
+    pub received_at: SystemTime,
+}
+
+impl ReceivedShred {
+    pub fn is_repaired(&self) -> bool {
+        self.source == ShredSource::Repair
+    }
+}
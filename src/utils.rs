@@ -1,31 +1,41 @@
-use crate::types::Network;
+use crate::{error::Error, types::Network};
 use log::{debug, error, info};
 use solana_gossip::contact_info::{ContactInfo, Protocol};
 use solana_ledger::shred::Shred;
 use std::{
+    io,
     net::{IpAddr, SocketAddr, ToSocketAddrs},
     time::{SystemTime, UNIX_EPOCH},
 };
 
-pub fn resolve_entrypoints(
-    network: Network,
-) -> Result<Vec<SocketAddr>, Box<dyn std::error::Error>> {
-    let entrypoint_strings = network.entrypoints();
+pub fn resolve_entrypoints(network: Network) -> Result<Vec<SocketAddr>, Error> {
+    resolve_addresses(&network.entrypoints())
+}
 
-    let mut resolved = Vec::with_capacity(3);
+// host:port strings, e.g. entrypoints from a config file
+pub fn resolve_addresses(entrypoint_strings: &[impl AsRef<str>]) -> Result<Vec<SocketAddr>, Error> {
+    let mut resolved = Vec::with_capacity(entrypoint_strings.len());
     for (i, entrypoint_str) in entrypoint_strings.iter().enumerate() {
+        let entrypoint_str = entrypoint_str.as_ref();
         debug!("Resolving entrypoint {}: '{}'", i + 1, entrypoint_str);
 
+        let resolution_error = |source| Error::EntrypointResolution {
+            entrypoint: entrypoint_str.to_string(),
+            source,
+        };
         let addr: SocketAddr = entrypoint_str
             .to_socket_addrs()
             .map_err(|e| {
                 error!("FAILED resolving '{}': {:?}", entrypoint_str, e);
-                e
+                resolution_error(e)
             })?
             .next()
             .ok_or_else(|| {
                 error!("No addresses found for '{}'", entrypoint_str);
-                "No addresses resolved"
+                resolution_error(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "no addresses resolved",
+                ))
             })?;
 
         resolved.push(addr);
@@ -37,7 +47,8 @@ pub fn resolve_entrypoints(
 pub fn get_cluster_shred_version(
     entrypoints: &[SocketAddr],
     bind_address: IpAddr,
-) -> Result<u16, Box<dyn std::error::Error>> {
+) -> Result<u16, Error> {
+    let mut last_error = None;
     for entrypoint in entrypoints {
         match solana_net_utils::get_cluster_shred_version_with_binding(entrypoint, bind_address) {
             Ok(0) => continue, // Invalid
@@ -45,12 +56,41 @@ pub fn get_cluster_shred_version(
                 info!("Got shred version {} from {}", shred_version, entrypoint);
                 return Ok(shred_version);
             }
-            Err(e) => error!("Failed to get shred version from {}: {}", entrypoint, e),
+            Err(e) => {
+                error!("Failed to get shred version from {}: {}", entrypoint, e);
+                last_error = Some(e.into());
+            }
         }
     }
 
-    info!("Using default shred version: 9065");
-    Ok(9065)
+    Err(Error::ShredVersion {
+        entrypoints: entrypoints.len(),
+        source: last_error,
+    })
+}
+
+pub fn discover_public_ip(
+    entrypoints: &[SocketAddr],
+    bind_address: IpAddr,
+) -> Result<IpAddr, Error> {
+    let mut last_error = None;
+    for entrypoint in entrypoints {
+        match solana_net_utils::get_public_ip_addr_with_binding(entrypoint, bind_address) {
+            Ok(public_ip) => {
+                info!("Discovered public IP {} via {}", public_ip, entrypoint);
+                return Ok(public_ip);
+            }
+            Err(e) => {
+                error!("Failed to get public IP from {}: {}", entrypoint, e);
+                last_error = Some(e.into());
+            }
+        }
+    }
+
+    Err(Error::PublicIp {
+        entrypoints: entrypoints.len(),
+        source: last_error,
+    })
 }
 
 pub fn log_peer_details(peers: &[(ContactInfo, u64)], tpu_peers: &[ContactInfo], iteration: usize) {
@@ -98,7 +138,7 @@ pub fn log_peer_details(peers: &[(ContactInfo, u64)], tpu_peers: &[ContactInfo],
     debug!("=== END PEER DETAILS ===");
 }
 
-pub fn parse_shred(data: &[u8]) -> Result<Shred, Box<dyn std::error::Error>> {
+pub fn parse_shred(data: &[u8]) -> Result<Shred, Error> {
     let shred = Shred::new_from_serialized_shred(data.to_vec())?;
     Ok(shred)
 }
@@ -109,3 +149,10 @@ pub fn get_timestamp() -> u64 {
         .unwrap_or_default()
         .as_secs()
 }
+
+pub fn get_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
@@ -0,0 +1,414 @@
+/*
+ ** WASM Routers **
+: Shred filters written by teams we don't want to run native code from. A router is a
+: WebAssembly module run in a sandbox (wasmi, an interpreter): it gets no imports, so it
+: can't touch files, sockets or the clock, every call is limited in fuel (instructions)
+: and its memory can't grow past `max_memory_bytes`.
+: For every shred the runner asks each router where it goes; the answer is a bitmask over
+: the router's `routes`, names of other plugins. A plugin named in some router's routes
+: only gets the shreds a router sent to it (on top of its own filters), plugins no router
+: names get every shred as before. A router that traps, runs out of fuel or returns bits
+: past its routes sends the shred nowhere and counts an error.
+
+*  ** Module ABI (version 1) **
+! +-------------------------+---------------------------------------------------------+
+! | Export                  | Signature                                               |
+! +-------------------------+---------------------------------------------------------+
+! | chainsmoker_abi_version | () -> i32, must return WASM_ABI_VERSION                 |
+! | route                   | (slot: i64, index: i32, fec_set_index: i32, kind: i32,  |
+! |                         | version: i32, flags: i32, payload_len: i32) -> i64      |
+! +-------------------------+---------------------------------------------------------+
+: kind is 0 for data and 1 for coding shreds; flags has bit 0 set for LAST_IN_SLOT and bit
+: 1 for DATA_COMPLETE (data shreds only). Bit i of the result routes the shred to
+: `routes[i]`, 0 drops it for every route. Routers see shred headers only, payloads and
+: transactions are not handed over.
+
+*  ** Config **
+! +------------------+----------+------------------------------------------------+
+! | Field            | Default  | Meaning                                        |
+! +------------------+----------+------------------------------------------------+
+! | name             |          | for logs, metrics and GET /plugins             |
+! | module           |          | .wasm file                                     |
+! | routes           |          | plugin names, at most 64                       |
+! | fuel             | 100000   | fuel per route() call                          |
+! | max_memory_bytes | 1048576  | linear memory limit, also for the initial size |
+! +------------------+----------+------------------------------------------------+
+
+*  ** Example **
+: [[routers]]
+: name = "votes-team"
+: module = "/etc/chainsmoker/votes_filter.wasm"
+: routes = ["Console", "Kafka"]
+*/
+
+use std::{fs, path::PathBuf};
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use solana_ledger::shred::Shred;
+use wasmi::{Engine, Linker, Module, Store, StoreLimits, StoreLimitsBuilder, TypedFunc};
+
+use crate::{
+    error::{BoxError, Error},
+    metrics::Metrics,
+};
+
+pub const WASM_ABI_VERSION: i32 = 1;
+pub const DEFAULT_FUEL: u64 = 100_000;
+pub const DEFAULT_MAX_MEMORY_BYTES: usize = 1 << 20;
+pub const MAX_ROUTES: usize = u64::BITS as usize;
+
+const FLAG_LAST_IN_SLOT: i32 = 1;
+const FLAG_DATA_COMPLETE: i32 = 1 << 1;
+
+// slot, index, fec_set_index, kind, version, flags, payload_len
+type RouteFn = TypedFunc<(i64, i32, i32, i32, i32, i32, i32), i64>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouterConfig {
+    pub name: String,
+    pub module: PathBuf,
+    pub routes: Vec<String>,
+    #[serde(default = "default_fuel")]
+    pub fuel: u64,
+    #[serde(default = "default_max_memory_bytes")]
+    pub max_memory_bytes: usize,
+}
+
+fn default_fuel() -> u64 {
+    DEFAULT_FUEL
+}
+
+fn default_max_memory_bytes() -> usize {
+    DEFAULT_MAX_MEMORY_BYTES
+}
+
+impl RouterConfig {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.routes.is_empty() {
+            return Err("routes is empty, nothing would be routed".to_string());
+        }
+        if self.routes.len() > MAX_ROUTES {
+            return Err(format!(
+                "{} routes, at most {} fit the result",
+                self.routes.len(),
+                MAX_ROUTES
+            ));
+        }
+        if self.fuel == 0 {
+            return Err("fuel must be at least 1".to_string());
+        }
+        Ok(())
+    }
+
+    pub fn load(&self) -> Result<WasmRouter, Error> {
+        let wasm = fs::read(&self.module).map_err(|e| self.module_error(e.into()))?;
+        WasmRouter::new(self, &wasm).map_err(|e| self.module_error(e))
+    }
+
+    fn module_error(&self, source: BoxError) -> Error {
+        Error::WasmModule {
+            path: self.module.clone(),
+            source,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouterStatus {
+    pub name: String,
+    pub module: PathBuf,
+    pub routes: Vec<String>,
+    pub routed: u64,
+    pub dropped: u64,
+    pub errors: u64,
+}
+
+pub struct WasmRouter {
+    name: String,
+    module: PathBuf,
+    routes: Vec<String>,
+    fuel: u64,
+    store: Store<StoreLimits>,
+    route: RouteFn,
+    routed: u64,
+    dropped: u64,
+    errors: u64,
+}
+
+impl WasmRouter {
+    // `wasm` is the module's bytes, config.module is only kept for status and errors
+    pub fn new(config: &RouterConfig, wasm: &[u8]) -> Result<Self, BoxError> {
+        let mut engine_config = wasmi::Config::default();
+        engine_config.consume_fuel(true);
+        let engine = Engine::new(&engine_config);
+        let module = Module::new(&engine, wasm)?;
+
+        let limits = StoreLimitsBuilder::new()
+            .memory_size(config.max_memory_bytes)
+            .memories(1)
+            .tables(1)
+            .instances(1)
+            .build();
+        let mut store = Store::new(&engine, limits);
+        store.limiter(|limits| limits);
+        store.set_fuel(config.fuel).map_err(|e| e.to_string())?;
+
+        // no imports, a module asking for any fails here
+        let instance = Linker::<StoreLimits>::new(&engine)
+            .instantiate(&mut store, &module)?
+            .start(&mut store)?;
+        let abi_version = instance
+            .get_typed_func::<(), i32>(&store, "chainsmoker_abi_version")?
+            .call(&mut store, ())?;
+        if abi_version != WASM_ABI_VERSION {
+            return Err(format!(
+                "module has ABI version {}, expected {}",
+                abi_version, WASM_ABI_VERSION
+            )
+            .into());
+        }
+        let route = instance.get_typed_func(&store, "route")?;
+
+        info!(
+            "Loaded router {} from {}, routing to {}",
+            config.name,
+            config.module.display(),
+            config.routes.join(", ")
+        );
+        Ok(Self {
+            name: config.name.clone(),
+            module: config.module.clone(),
+            routes: config.routes.clone(),
+            fuel: config.fuel,
+            store,
+            route,
+            routed: 0,
+            dropped: 0,
+            errors: 0,
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    // index into the result bitmask, None if this router doesn't route to `plugin`
+    pub fn route_index(&self, plugin: &str) -> Option<usize> {
+        self.routes.iter().position(|route| route == plugin)
+    }
+
+    pub fn routes(&self) -> &[String] {
+        &self.routes
+    }
+
+    // bitmask over routes, 0 when the module failed
+    pub fn route(&mut self, shred: &Shred, metrics: Option<&Metrics>) -> u64 {
+        match self.call(shred) {
+            Ok(0) => {
+                self.dropped += 1;
+                0
+            }
+            Ok(mask) => {
+                self.routed += 1;
+                mask
+            }
+            Err(e) => {
+                self.errors += 1;
+                if let Some(metrics) = metrics {
+                    metrics.router_errors.with_label_values(&[&self.name]).inc();
+                }
+                // a broken module fails on every shred, don't log each one
+                if self.errors.is_power_of_two() {
+                    warn!("Router {} error #{}: {}", self.name, self.errors, e);
+                }
+                0
+            }
+        }
+    }
+
+    fn call(&mut self, shred: &Shred) -> Result<u64, BoxError> {
+        let mut flags = 0;
+        if shred.is_data() && shred.last_in_slot() {
+            flags |= FLAG_LAST_IN_SLOT;
+        }
+        if shred.is_data() && shred.data_complete() {
+            flags |= FLAG_DATA_COMPLETE;
+        }
+        let params = (
+            shred.slot() as i64,
+            shred.index() as i32,
+            shred.fec_set_index() as i32,
+            i32::from(!shred.is_data()),
+            i32::from(shred.version()),
+            flags,
+            shred.payload().len() as i32,
+        );
+
+        self.store.set_fuel(self.fuel).map_err(|e| e.to_string())?;
+        let mask = self.route.call(&mut self.store, params)? as u64;
+        check_mask(mask, self.routes.len())
+    }
+
+    pub fn status(&self) -> RouterStatus {
+        RouterStatus {
+            name: self.name.clone(),
+            module: self.module.clone(),
+            routes: self.routes.clone(),
+            routed: self.routed,
+            dropped: self.dropped,
+            errors: self.errors,
+        }
+    }
+}
+
+// a mask with bits past `routes` is an error, with MAX_ROUTES routes every bit is one
+fn check_mask(mask: u64, routes: usize) -> Result<u64, BoxError> {
+    if mask.checked_shr(routes as u32).unwrap_or(0) != 0 {
+        return Err(format!("returned {:#x}, only {} routes", mask, routes).into());
+    }
+    Ok(mask)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{shred::test_data_shred, utils::parse_shred};
+
+    const LAST_IN_SLOT: u8 = 0b1100_0000;
+
+    // a module with `route` running `body`, `extra` goes before the functions
+    fn module(extra: &str, body: &str) -> Vec<u8> {
+        wat::parse_str(format!(
+            r#"(module
+                {extra}
+                (func (export "chainsmoker_abi_version") (result i32) i32.const 1)
+                (func (export "route")
+                    (param $slot i64) (param $index i32) (param $fec_set_index i32)
+                    (param $kind i32) (param $version i32) (param $flags i32)
+                    (param $payload_len i32) (result i64)
+                    {body}))"#
+        ))
+        .unwrap()
+    }
+
+    fn config(routes: &[&str]) -> RouterConfig {
+        RouterConfig {
+            name: "test".to_string(),
+            module: PathBuf::from("test.wasm"),
+            routes: routes.iter().map(|route| route.to_string()).collect(),
+            fuel: 1_000,
+            max_memory_bytes: DEFAULT_MAX_MEMORY_BYTES,
+        }
+    }
+
+    fn shred(slot: u64, flags: u8) -> Shred {
+        parse_shred(&test_data_shred(slot, 0, 0, flags, b"entries")).unwrap()
+    }
+
+    #[test]
+    fn test_routes_by_header() {
+        // even slots to Console, odd ones to Kafka
+        let wasm = module(
+            "",
+            "(i64.shl (i64.const 1) (i64.and (local.get $slot) (i64.const 1)))",
+        );
+        let mut router = WasmRouter::new(&config(&["Console", "Kafka"]), &wasm).unwrap();
+        assert_eq!(router.route(&shred(100, 0), None), 0b01);
+        assert_eq!(router.route(&shred(101, 0), None), 0b10);
+        assert_eq!(router.route_index("Kafka"), Some(1));
+        assert_eq!(router.route_index("Archive"), None);
+
+        // flags and kind as documented
+        let wasm = module(
+            "",
+            "(i64.extend_i32_u (i32.or (local.get $flags) (i32.shl (local.get $kind) (i32.const 2))))",
+        );
+        let mut router = WasmRouter::new(&config(&["A", "B", "C"]), &wasm).unwrap();
+        assert_eq!(router.route(&shred(100, 0), None), 0);
+        assert_eq!(
+            router.route(&shred(100, LAST_IN_SLOT), None),
+            (FLAG_LAST_IN_SLOT | FLAG_DATA_COMPLETE) as u64
+        );
+
+        let status = router.status();
+        assert_eq!((status.routed, status.dropped, status.errors), (1, 1, 0));
+    }
+
+    #[test]
+    fn test_running_out_of_fuel_drops_the_shred() {
+        let wasm = module("", "(loop $forever (br $forever)) (i64.const 1)");
+        let mut router = WasmRouter::new(&config(&["Console"]), &wasm).unwrap();
+        assert_eq!(router.route(&shred(100, 0), None), 0);
+        // fuel is topped up per call, the next shred fails the same way and not earlier
+        assert_eq!(router.route(&shred(101, 0), None), 0);
+        assert_eq!(router.status().errors, 2);
+    }
+
+    #[test]
+    fn test_bits_past_routes_drop_the_shred() {
+        let wasm = module("", "(i64.const 0b100)");
+        let mut router = WasmRouter::new(&config(&["A", "B"]), &wasm).unwrap();
+        assert_eq!(router.route(&shred(100, 0), None), 0);
+        assert_eq!(router.status().errors, 1);
+    }
+
+    #[test]
+    fn test_memory_limit() {
+        // 32 pages of 64KiB are 2MiB, over the 1MiB default
+        let wasm = module("(memory 32)", "(i64.const 1)");
+        assert!(WasmRouter::new(&config(&["Console"]), &wasm).is_err());
+
+        // growing past it fails inside the module, which sees -1
+        let wasm = module(
+            "(memory 1)",
+            "(if (result i64) (i32.eq (memory.grow (i32.const 16)) (i32.const -1))
+                (then (i64.const 0))
+                (else (i64.const 1)))",
+        );
+        let mut router = WasmRouter::new(&config(&["Console"]), &wasm).unwrap();
+        assert_eq!(router.route(&shred(100, 0), None), 0);
+        assert_eq!(router.status().dropped, 1);
+    }
+
+    #[test]
+    fn test_modules_with_imports_are_rejected() {
+        let wasm = module(
+            r#"(import "env" "now" (func $now (result i64)))"#,
+            "(call $now)",
+        );
+        assert!(WasmRouter::new(&config(&["Console"]), &wasm).is_err());
+    }
+
+    #[test]
+    fn test_abi_version_mismatch() {
+        let wasm = wat::parse_str(
+            r#"(module
+                (func (export "chainsmoker_abi_version") (result i32) i32.const 2)
+                (func (export "route")
+                    (param i64 i32 i32 i32 i32 i32 i32) (result i64) i64.const 1))"#,
+        )
+        .unwrap();
+        let error = WasmRouter::new(&config(&["Console"]), &wasm).err().unwrap();
+        assert!(error.to_string().contains("ABI version 2"), "{}", error);
+    }
+
+    #[test]
+    fn test_check_mask_within_routes() {
+        assert_eq!(check_mask(0b101, 3).unwrap(), 0b101);
+        assert_eq!(check_mask(0, 1).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_check_mask_past_routes() {
+        assert!(check_mask(0b1000, 3).is_err());
+        assert!(check_mask(1 << 63, 63).is_err());
+    }
+
+    #[test]
+    fn test_check_mask_max_routes() {
+        assert_eq!(check_mask(u64::MAX, MAX_ROUTES).unwrap(), u64::MAX);
+        assert_eq!(check_mask(1 << 63, MAX_ROUTES).unwrap(), 1 << 63);
+    }
+}
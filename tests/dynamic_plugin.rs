@@ -0,0 +1,108 @@
+// Builds examples/dynamic_plugin as a cdylib and loads it through DynamicPlugin::load
+
+use std::{path::PathBuf, process::Command, sync::Arc};
+
+use chainsmoker::{Error, Shred, dynamic::DynamicPlugin, output::OutputPlugin};
+use serde_json::{Value, json};
+
+const NAME: &str = "ShredCounter";
+
+// the library cargo built, from its json messages
+fn build_library() -> PathBuf {
+    let cargo = std::env::var("CARGO").unwrap_or_else(|_| "cargo".to_string());
+    let output = Command::new(cargo)
+        .args([
+            "build",
+            "--example",
+            "dynamic_plugin",
+            "--message-format=json",
+        ])
+        .current_dir(env!("CARGO_MANIFEST_DIR"))
+        .output()
+        .expect("run cargo build");
+    assert!(
+        output.status.success(),
+        "cargo build --example dynamic_plugin failed"
+    );
+
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+        .filter(|message| message["target"]["name"] == "dynamic_plugin")
+        .filter_map(|message| message["filenames"].as_array().cloned())
+        .flatten()
+        .filter_map(|filename| filename.as_str().map(PathBuf::from))
+        .find(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "so" || extension == "dylib")
+        })
+        .expect("cdylib in the build output")
+}
+
+// a chained Merkle data shred, signature and proof zeroed
+fn data_shred(slot: u64, index: u32) -> Shred {
+    let data = b"dynamic plugin";
+    let mut payload = vec![0u8; 1203];
+    payload[0x40] = 0x96;
+    payload[0x41..0x49].copy_from_slice(&slot.to_le_bytes());
+    payload[0x49..0x4d].copy_from_slice(&index.to_le_bytes());
+    payload[0x4d..0x4f].copy_from_slice(&1u16.to_le_bytes());
+    payload[0x53..0x55].copy_from_slice(&1u16.to_le_bytes());
+    payload[0x56..0x58].copy_from_slice(&((0x58 + data.len()) as u16).to_le_bytes());
+    payload[0x58..0x58 + data.len()].copy_from_slice(data);
+    Shred::new_from_serialized_shred(payload).expect("valid data shred")
+}
+
+#[tokio::test]
+async fn test_shred_round_trip() {
+    let library = build_library();
+    let settings = json!({ "every": 1, "expect_slot": 42 });
+    let mut plugin = DynamicPlugin::load(&library, NAME, &settings).unwrap();
+    assert_eq!(plugin.name(), NAME);
+    assert_eq!(plugin.path(), library);
+
+    plugin.start().await.unwrap();
+    plugin
+        .handle_shared(Arc::new(data_shred(42, 0)))
+        .await
+        .unwrap();
+
+    // the library's error comes back through last_error
+    let error = plugin
+        .handle_shared(Arc::new(data_shred(43, 1)))
+        .await
+        .unwrap_err();
+    assert_eq!(error.to_string(), "slot 43, expected 42");
+    plugin.stop().await.unwrap();
+}
+
+#[test]
+fn test_wrong_abi_version() {
+    let library = build_library();
+    let settings = json!({ "abi_version": 99 });
+    match DynamicPlugin::load(&library, NAME, &settings) {
+        Err(Error::PluginAbi {
+            found, expected, ..
+        }) => {
+            assert_eq!(found, 99);
+            assert_eq!(expected, chainsmoker::dynamic::PLUGIN_ABI_VERSION);
+        }
+        Err(e) => panic!("expected PluginAbi, got {}", e),
+        Ok(_) => panic!("loaded a library with ABI version 99"),
+    }
+}
+
+#[test]
+fn test_name_mismatch() {
+    let library = build_library();
+    match DynamicPlugin::load(&library, "Other", &Value::Null) {
+        Err(Error::PluginLibrary { source, .. }) => {
+            assert_eq!(
+                source.to_string(),
+                "plugin calls itself ShredCounter, configured as Other"
+            );
+        }
+        Err(e) => panic!("expected PluginLibrary, got {}", e),
+        Ok(_) => panic!("loaded a library under another name"),
+    }
+}